@@ -1,12 +1,20 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap, HashSet},
     error::Error,
     fs,
-    io::Stdout,
     path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossterm::event::{KeyCode, KeyEvent};
+use clap::ValueEnum;
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use log::{Level, warn};
+use serde_json::Value;
 use syntect::{
     easy::HighlightLines,
     highlighting::{Style as SyntectStyle, Theme},
@@ -17,17 +25,34 @@ use syntect::{
 use tui_tree_widget::{Tree, TreeItem, TreeState};
 
 use ratatui::{
-    Frame, Terminal,
-    layout::{Constraint, Direction, Layout},
-    prelude::CrosstermBackend,
+    Frame,
+    layout::{Constraint, Direction, Layout, Position, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Paragraph},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
 };
 
 use crate::{
-    clean_path, config::get_repo_config, get_templates_path,
-    template_rendering::render_template_with_handlebars,
+    clean_path, commands,
+    error::DropkickError,
+    expand_tilde,
+    fuzzy::fuzzy_match,
+    get_templates_path,
+    import::{
+        ConflictPolicy, DestinationCollision, ImportOptions, LayeredFiles, compute_destination,
+        find_destination_collisions, layer_templates, skip_reason,
+    },
+    keymap::{KeyAction, Keymap},
+    logging,
+    manifest::{Manifest, TemplateDefaults, Variable},
+    registry::Index,
+    remote::TemplateSource,
+    template_rendering::{Renderer, referenced_config_keys},
+    templates::{
+        SymlinkGuard, is_binary, is_template_file_name, should_show_entry, split_flag,
+        split_frontmatter, strip_template_suffix, template_names, template_root_of,
+    },
+    theme::Palette,
 };
 
 pub enum Action {
@@ -40,20 +65,382 @@ pub struct App {
     pub(crate) tree_state: TreeState<String>,
     pub(crate) items: Vec<TreeItem<'static, String>>,
     pub(crate) selected_files: HashSet<String>,
+    /// Templates in the order files were first selected from them, which is
+    /// the order they're layered in: later templates override earlier ones
+    template_order: Vec<PathBuf>,
+    /// What's typed after `/`; only files matching it are shown
+    pub(crate) filter: String,
+    /// Each matching file, with the positions of the matched chars in its
+    /// displayed name
+    matches: HashMap<String, Vec<usize>>,
     pub(crate) mode: AppMode,
+    pub(crate) renderer: Renderer,
+    pub(crate) import_options: ImportOptions,
+    /// Where the templates are, and how, so the tree can be rebuilt after a
+    /// template is installed
+    root_path: PathBuf,
+    follow_symlinks: bool,
+    /// URL or path of the template index `i` browses
+    template_index: String,
+    keymap: Keymap,
+    palette: Palette,
+    /// Where the tree and the basket panel were last drawn, to tell what a
+    /// mouse click landed on
+    tree_area: Rect,
+    basket_area: Option<Rect>,
+    /// When and on what the last click landed, to spot double clicks
+    last_click: Option<(Instant, Vec<String>)>,
+    previews: Arc<Mutex<PreviewCache>>,
+    /// Latest last
+    notifications: Vec<Notification>,
+}
+
+/// A warning or error logged while the TUI is up, shown in the corner
+/// until it's been up for `NOTIFICATION_TIME` or Esc dismisses it.
+struct Notification {
+    message: String,
+    error: bool,
+    shown: Instant,
 }
 
+const NOTIFICATION_TIME: Duration = Duration::from_secs(8);
+
+/// Only the latest few notifications are kept on screen.
+const MAX_NOTIFICATIONS: usize = 3;
+
+/// Two clicks on the same item within this long make a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Lines the wheel scrolls the tree by per notch.
+const WHEEL_LINES: usize = 3;
+
+/// The screen the app is currently on.  Each mode owns its key handling and
+/// rendering; overlay modes draw on top of the template tree.
 pub enum AppMode {
-    TreeView,
-    FileView {
-        path: String,
-        content: Vec<Line<'static>>,
+    Browsing,
+    /// Typing a filter after `/`
+    Filtering,
+    /// Previewing a file, rendered with the current values or as written
+    Viewing(FileView),
+    /// Going over the selected files in the basket panel, where they can be
+    /// dropped before confirming the import
+    Basket {
+        selected: usize,
+    },
+    /// Asking whether to import the selection, warning about selected files
+    /// that land on the same path, worked out on the way in
+    Confirming {
+        collisions: Vec<DestinationCollision>,
+    },
+    /// Reviewing the values handed to templates before importing
+    Prompting(VariableForm),
+    /// Asking what to do with each selected file that already exists, under
+    /// `--on-conflict prompt`, showing how the local copy differs
+    Resolving {
+        conflicts: Vec<Conflict>,
         scroll: usize,
     },
+    /// Searching the template index for templates to install
+    Discovering(IndexBrowser),
+    /// Typing the directory the import writes into
+    Destination {
+        input: String,
+    },
+    Help,
+    Error {
+        message: String,
+    },
+}
+
+/// A file being previewed, with where it's scrolled to and what's searched
+/// for in it.
+pub struct FileView {
+    path: String,
+    rendered: Vec<Line<'static>>,
+    raw: Vec<Line<'static>>,
+    show_raw: bool,
+    scroll: usize,
+    line_numbers: bool,
+    wrap: bool,
+    /// Text searched for with `/`, highlighted wherever it appears
+    query: String,
+    /// Whether the query is still being typed
+    searching: bool,
+    /// Where the rendered and highlighted file comes from while that's
+    /// being worked out in the background; plain text is shown meanwhile
+    highlighting: Option<Receiver<Result<Highlighted, String>>>,
+}
+
+/// A file's rendered and raw lines, highlighted.
+#[derive(Clone)]
+struct Highlighted {
+    rendered: Vec<Line<'static>>,
+    raw: Vec<Line<'static>>,
+}
+
+/// How many highlighted files are kept for going back to.
+const PREVIEW_CACHE_SIZE: usize = 32;
+
+/// The files highlighted most recently, so going back to one doesn't
+/// highlight it all over again.  Shared with the threads highlighting them.
+#[derive(Default)]
+struct PreviewCache {
+    /// Each file with when it was modified as highlighted, least recently
+    /// used first
+    entries: Vec<(PathBuf, SystemTime, Highlighted)>,
+    /// Bumped whenever the values files are rendered with change, so a file
+    /// still being highlighted with the old ones isn't cached
+    generation: u64,
+}
+
+impl PreviewCache {
+    /// The highlighted file at `path` if it hasn't changed since.
+    fn get(&mut self, path: &Path, modified: SystemTime) -> Option<Highlighted> {
+        let index = self
+            .entries
+            .iter()
+            .position(|(p, m, _)| p == path && *m == modified)?;
+        let entry = self.entries.remove(index);
+        let highlighted = entry.2.clone();
+        self.entries.push(entry);
+        Some(highlighted)
+    }
+
+    fn insert(
+        &mut self,
+        path: PathBuf,
+        modified: SystemTime,
+        generation: u64,
+        highlighted: Highlighted,
+    ) {
+        if generation != self.generation {
+            return;
+        }
+        self.entries.retain(|(p, _, _)| *p != path);
+        if self.entries.len() >= PREVIEW_CACHE_SIZE {
+            self.entries.remove(0);
+        }
+        self.entries.push((path, modified, highlighted));
+    }
+
+    /// Forgets every file, for when they'd be rendered differently now.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.generation += 1;
+    }
+}
+
+impl FileView {
+    fn content(&self) -> &[Line<'static>] {
+        if self.show_raw {
+            &self.raw
+        } else {
+            &self.rendered
+        }
+    }
+
+    /// Scrolls by `lines`, up when negative, without going past the point
+    /// where the last line is at the bottom.
+    fn scroll_by(&mut self, lines: isize, visible_height: usize) {
+        let last = self.content().len().saturating_sub(visible_height);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(last);
+    }
+
+    /// Scrolls to the next line containing the query after the top one, or
+    /// the previous one before it, wrapping around the file.
+    fn find(&mut self, forward: bool) {
+        let lines: Vec<usize> = self
+            .content()
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| !match_ranges(&line_text(line), &self.query).is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        let found = if forward {
+            lines.iter().find(|&&i| i > self.scroll).or(lines.first())
+        } else {
+            lines
+                .iter()
+                .rev()
+                .find(|&&i| i < self.scroll)
+                .or(lines.last())
+        };
+        if let Some(&line) = found {
+            self.scroll = line;
+        }
+    }
+}
+
+/// The template index, narrowed down as a search is typed.
+pub struct IndexBrowser {
+    index: Index,
+    query: String,
+    /// Position of the highlighted template among the matches
+    selected: usize,
+    installed: BTreeSet<String>,
+    /// How the last install went, and whether it worked
+    status: Option<(String, bool)>,
+}
+
+/// A selected file whose destination already exists.
+pub struct Conflict {
+    dest: PathBuf,
+    /// Unified diff from the local file to the rendered template, colored
+    diff: Vec<Line<'static>>,
+}
+
+/// The values the selected files reference, editable before importing.
+pub struct VariableForm {
+    fields: Vec<FormField>,
+    focus: usize,
+    /// Display name and body of the first selected file, rendered live with
+    /// the form's values
+    sample: Option<(String, String)>,
+}
+
+pub struct FormField {
+    name: String,
+    value: String,
+    /// What the context already held, from git config, `.dropkickrc` or
+    /// `--vars-file`
+    initial: Option<String>,
+    /// The declaration in `dropkick.yaml`, for validation and type
+    variable: Option<Variable>,
+    error: Option<String>,
+}
+
+impl VariableForm {
+    /// Collects the variables the templates of `selected` declare, followed
+    /// by the other `config[:...]` keys the files reference.  Returns `None`
+    /// when there's nothing to ask about.
+    fn new(
+        selected: &HashSet<String>,
+        renderer: &Renderer,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut files: Vec<&String> = selected.iter().collect();
+        files.sort();
+
+        let mut referenced = BTreeSet::new();
+        let mut sample = None;
+        for file in &files {
+            // Binary files aren't rendered, so can't contain placeholders
+            let Ok(content) = fs::read_to_string(file) else {
+                continue;
+            };
+            if is_binary(Path::new(file), content.as_bytes())? {
+                continue;
+            }
+            referenced.extend(referenced_config_keys(&content));
+            if sample.is_none() {
+                let body = match split_frontmatter(&content) {
+                    Ok((_, body)) => body.to_string(),
+                    Err(_) => content.clone(),
+                };
+                sample = Some((get_item_text(file), body));
+            }
+        }
+
+        let roots: BTreeSet<PathBuf> = files
+            .iter()
+            .filter_map(|file| template_root_of(Path::new(file)))
+            .collect();
+
+        let mut fields: Vec<FormField> = Vec::new();
+        for root in &roots {
+            for variable in Manifest::load(root)?.variables {
+                if !fields.iter().any(|f| f.name == variable.name) {
+                    fields.push(FormField::new(
+                        variable.name.clone(),
+                        Some(variable),
+                        renderer,
+                    ));
+                }
+            }
+        }
+        for key in referenced {
+            if !fields.iter().any(|f| f.name == key) {
+                fields.push(FormField::new(key, None, renderer));
+            }
+        }
+
+        if fields.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            fields,
+            focus: 0,
+            sample,
+        }))
+    }
+
+    /// The sample file rendered with the values as they stand, skipping any
+    /// that don't validate yet.
+    fn preview(&self, renderer: &Renderer) -> Option<(String, String)> {
+        let (name, body) = self.sample.as_ref()?;
+
+        let mut preview = renderer.clone();
+        for field in &self.fields {
+            if let Ok(Some(value)) = field.parse() {
+                preview.set_variable(&field.name, value);
+            }
+        }
+
+        Some((name.clone(), interpolate_file(body, &preview)))
+    }
+}
+
+impl FormField {
+    fn new(name: String, variable: Option<Variable>, renderer: &Renderer) -> Self {
+        let initial = renderer.context.get(&name).map(|value| match value {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            // As a list variable is typed in
+            Value::Array(items) if items.iter().all(Value::is_string) => items
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+            other => other.to_string(),
+        });
+        let value = initial
+            .clone()
+            .or_else(|| variable.as_ref().and_then(Variable::default_text))
+            .unwrap_or_default();
+
+        Self {
+            name,
+            value,
+            initial,
+            variable,
+            error: None,
+        }
+    }
+
+    /// The value to hand to templates, or `None` when the field still holds
+    /// what the context already had.
+    fn parse(&self) -> Result<Option<Value>, String> {
+        if self.initial.as_deref() == Some(self.value.as_str()) {
+            return Ok(None);
+        }
+
+        match &self.variable {
+            Some(variable) => variable.answer(&self.value).map(Some),
+            None => Ok(Some(Value::String(self.value.clone()))),
+        }
+    }
 }
 
 impl App {
-    pub fn new(root_path: &Path) -> Result<Self, Box<dyn Error>> {
+    pub fn new(
+        root_path: &Path,
+        renderer: Renderer,
+        import_options: ImportOptions,
+        follow_symlinks: bool,
+        template_index: &str,
+        keymap: Keymap,
+        palette: Palette,
+    ) -> Result<Self, Box<dyn Error>> {
         let items = build_tree(root_path)?;
         let mut tree_state = TreeState::default();
 
@@ -68,266 +455,2098 @@ impl App {
             tree_state,
             items,
             selected_files: HashSet::new(),
-            mode: AppMode::TreeView,
+            template_order: Vec::new(),
+            filter: String::new(),
+            matches: HashMap::new(),
+            mode: AppMode::Browsing,
+            renderer,
+            import_options,
+            root_path: root_path.to_path_buf(),
+            follow_symlinks,
+            template_index: template_index.to_string(),
+            keymap,
+            palette,
+            tree_area: Rect::default(),
+            basket_area: None,
+            last_click: None,
+            previews: Arc::default(),
+            notifications: Vec::new(),
         })
     }
 
     pub fn render(&mut self, f: &mut Frame) {
+        self.render_mode(f);
+        render_notifications(f, &self.notifications);
+    }
+
+    fn render_mode(&mut self, f: &mut Frame) {
+        if let AppMode::Viewing(view) = &self.mode {
+            render_file_view(f, view, &self.palette);
+            return;
+        }
+        if let AppMode::Prompting(form) = &self.mode {
+            self.render_form(f, form);
+            return;
+        }
+        if let AppMode::Resolving { conflicts, scroll } = &self.mode {
+            render_resolve(f, conflicts, *scroll, &self.palette);
+            return;
+        }
+        if let AppMode::Discovering(browser) = &self.mode {
+            render_discover(f, browser, &self.palette);
+            return;
+        }
+
+        self.render_tree(f);
+
         match &self.mode {
-            AppMode::TreeView => self.render_tree(f),
-            AppMode::FileView {
-                path,
-                content,
-                scroll,
-            } => self.render_file_view(f, path, content, *scroll),
+            AppMode::Confirming { collisions } => self.render_confirm(f, collisions),
+            AppMode::Basket { .. } => {}
+            AppMode::Help => render_help(f, &self.keymap),
+            AppMode::Error { message } => render_error(f, message),
+            AppMode::Browsing
+            | AppMode::Filtering
+            | AppMode::Destination { .. }
+            | AppMode::Viewing(_)
+            | AppMode::Prompting(_)
+            | AppMode::Resolving { .. }
+            | AppMode::Discovering(_) => {}
         }
     }
 
+    /// Applies one keypress.  `terminal_height` is the height of the screen
+    /// the app is drawn on, which bounds scrolling in the file view.
     pub fn handle_key(
         &mut self,
         key: KeyEvent,
-        terminal: &Terminal<CrosstermBackend<Stdout>>,
-        ss: &SyntaxSet,
-        theme: &Theme,
+        terminal_height: u16,
+        ss: &Arc<SyntaxSet>,
+        theme: &Arc<Theme>,
     ) -> Result<Action, Box<dyn Error>> {
+        // Esc dismisses notifications before it does anything else
+        if key.code == KeyCode::Esc && !self.notifications.is_empty() {
+            self.notifications.clear();
+            return Ok(Action::Continue);
+        }
+
         match &self.mode {
-            AppMode::TreeView => self.handle_key_tree(key, ss, theme),
-            AppMode::FileView { .. } => self.handle_key_file_view(key, terminal),
+            AppMode::Browsing => self.handle_key_tree(key, ss, theme),
+            AppMode::Filtering => Ok(self.handle_key_filter(key)),
+            AppMode::Destination { .. } => Ok(self.handle_key_destination(key)),
+            AppMode::Viewing(_) => Ok(self.handle_key_file_view(key, terminal_height)),
+            AppMode::Basket { .. } => Ok(self.handle_key_basket(key)),
+            AppMode::Confirming { .. } => Ok(self.handle_key_confirm(key)),
+            AppMode::Prompting(_) => Ok(self.handle_key_form(key)),
+            AppMode::Resolving { .. } => Ok(self.handle_key_resolve(key)),
+            AppMode::Discovering(_) => self.handle_key_discover(key),
+            AppMode::Help | AppMode::Error { .. } => Ok(self.handle_key_dismiss(key)),
         }
     }
 
     fn handle_key_tree(
         &mut self,
         key: KeyEvent,
-        ss: &SyntaxSet,
-        theme: &Theme,
+        ss: &Arc<SyntaxSet>,
+        theme: &Arc<Theme>,
     ) -> Result<Action, Box<dyn Error>> {
-        match key.code {
-            KeyCode::Char('q') => return Ok(Action::Quit),
-            KeyCode::Char('e') => return Ok(Action::Extract),
-            KeyCode::Char('v') | KeyCode::Right | KeyCode::Char('l') => {
-                self.view_selected_file(&ss, theme)?;
+        if key.code == KeyCode::Esc && !self.filter.is_empty() {
+            self.filter.clear();
+            self.apply_filter();
+            return Ok(Action::Continue);
+        }
+
+        let Some(action) = self.keymap.action(key) else {
+            return Ok(Action::Continue);
+        };
+        match action {
+            KeyAction::Quit => return Ok(Action::Quit),
+            KeyAction::Import | KeyAction::Basket => {
+                self.mode = AppMode::Basket { selected: 0 };
             }
-            KeyCode::Down | KeyCode::Char('j') => {
+            KeyAction::Help => self.mode = AppMode::Help,
+            KeyAction::DryRun => self.import_options.dry_run = !self.import_options.dry_run,
+            KeyAction::Filter => self.mode = AppMode::Filtering,
+            KeyAction::Destination => {
+                self.mode = AppMode::Destination {
+                    input: self.import_options.output_dir.display().to_string(),
+                };
+            }
+            KeyAction::Install => self.open_index(),
+            KeyAction::Expand => self.view_selected_file(ss, theme)?,
+            KeyAction::Down => {
                 self.tree_state.key_down();
             }
-            KeyCode::Up | KeyCode::Char('k') => {
+            KeyAction::Up => {
                 self.tree_state.key_up();
             }
-            KeyCode::Left | KeyCode::Char('h') => {
-                self.handle_left_key();
+            KeyAction::Collapse => self.handle_left_key(),
+            KeyAction::Fold => {
+                self.tree_state.toggle_selected();
             }
-            KeyCode::Char(' ') => self.toggle_selected_file(),
-            _ => {}
+            KeyAction::Toggle => self.toggle_selected_file(),
+            KeyAction::SelectAll => self.toggle_all_files(),
+            KeyAction::Raw => {}
         };
         Ok(Action::Continue)
     }
 
-    fn handle_key_file_view(
-        &mut self,
-        key: KeyEvent,
-        terminal: &Terminal<CrosstermBackend<Stdout>>,
-    ) -> Result<Action, Box<dyn Error>> {
-        let visible_height = terminal.size()?.height.saturating_sub(5) as usize;
+    fn handle_key_filter(&mut self, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Enter => self.mode = AppMode::Browsing,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.apply_filter();
+                self.mode = AppMode::Browsing;
+            }
+            KeyCode::Down => {
+                self.tree_state.key_down();
+            }
+            KeyCode::Up => {
+                self.tree_state.key_up();
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.apply_filter();
+            }
+            KeyCode::Char(c) => {
+                self.filter.push(c);
+                self.apply_filter();
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn handle_key_destination(&mut self, key: KeyEvent) -> Action {
+        let AppMode::Destination { input } = &mut self.mode else {
+            return Action::Continue;
+        };
         match key.code {
-            KeyCode::Char('q') | KeyCode::Esc | KeyCode::Left | KeyCode::Char('h') => {
+            KeyCode::Enter => {
+                let dir = match input.trim() {
+                    "" => PathBuf::from("."),
+                    typed => expand_tilde(Path::new(typed)),
+                };
+                // It's created when the first file is written
+                self.mode = if dir.exists() && !dir.is_dir() {
+                    AppMode::Error {
+                        message: format!("{} isn't a directory", clean_path(&dir).display()),
+                    }
+                } else {
+                    self.import_options.output_dir = dir;
+                    AppMode::Browsing
+                };
+            }
+            KeyCode::Esc => self.mode = AppMode::Browsing,
+            KeyCode::Tab => complete_directory(input),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    /// Matches every file against the filter by its path within the
+    /// templates folder, opening the folders of the matches and selecting
+    /// the best one.
+    fn apply_filter(&mut self) {
+        self.matches.clear();
+        if self.filter.trim().is_empty() {
+            return;
+        }
+
+        self.read_all_below(&[]);
+        let templates_path = get_templates_path();
+        let mut files = Vec::new();
+        collect_file_paths(&self.items, &mut Vec::new(), &mut files);
+
+        let mut best: Option<(i64, Vec<String>)> = None;
+        for path in files {
+            let Some(identifier) = path.last() else {
+                continue;
+            };
+            let file = Path::new(identifier);
+            let relative = file
+                .strip_prefix(&templates_path)
+                .unwrap_or(file)
+                .to_string_lossy();
+            let Some(found) = fuzzy_match(&self.filter, &relative) else {
+                continue;
+            };
+
+            // Only chars within the file name can be highlighted
+            let name_start = relative
+                .rfind(std::path::is_separator)
+                .map_or(0, |i| relative[..=i].chars().count());
+            let name_len = get_item_text(identifier).chars().count();
+            let positions = found
+                .positions
+                .iter()
+                .filter_map(|&p| p.checked_sub(name_start))
+                .filter(|&p| p < name_len)
+                .collect();
+            self.matches.insert(identifier.clone(), positions);
+
+            for depth in 1..path.len() {
+                self.tree_state.open(path[..depth].to_vec());
+            }
+            if best.as_ref().is_none_or(|(score, _)| found.score > *score) {
+                best = Some((found.score, path));
+            }
+        }
+
+        if let Some((_, path)) = best {
+            self.tree_state.select(path);
+        }
+    }
+
+    /// Reads the folder at `path`, identifiers from the top of the tree down,
+    /// if it hasn't been yet.  One that can't be read is reported and shown
+    /// empty.
+    fn read_folder_at(&mut self, path: &[String]) {
+        let Some(root) = path.first().map(PathBuf::from) else {
+            return;
+        };
+        let Some(item) = item_mut(&mut self.items, path) else {
+            return;
+        };
+        if !is_unread(item) {
+            return;
+        }
+
+        let dir = PathBuf::from(item.identifier());
+        let children = read_folder(&dir, &root, self.follow_symlinks).unwrap_or_else(|e| {
+            warn!("Unable to read {}: {}", clean_path(&dir).display(), e);
+            Vec::new()
+        });
+        let name = get_item_text(item.identifier());
+        if let Ok(read) = TreeItem::new(item.identifier().clone(), name, children) {
+            *item = read;
+        }
+    }
+
+    /// Reads the folders opened since the tree was last drawn.
+    fn read_opened_folders(&mut self) {
+        let mut opened: Vec<Vec<String>> = self.tree_state.opened().iter().cloned().collect();
+        // Parents first, so their children are there to be read
+        opened.sort_by_key(Vec::len);
+        for path in opened {
+            self.read_folder_at(&path);
+        }
+    }
+
+    /// Reads every folder below `path`, or the whole tree when it's empty,
+    /// for what has to see every file.
+    fn read_all_below(&mut self, path: &[String]) {
+        loop {
+            let mut folders = Vec::new();
+            match path.split_last() {
+                None => collect_unread_folders(&self.items, &mut Vec::new(), &mut folders),
+                Some((_, parents)) => {
+                    if let Some(item) = item_mut(&mut self.items, path) {
+                        collect_unread_folders(
+                            std::slice::from_ref(item),
+                            &mut parents.to_vec(),
+                            &mut folders,
+                        );
+                    }
+                }
+            }
+            if folders.is_empty() {
+                return;
+            }
+            for folder in folders {
+                self.read_folder_at(&folder);
+            }
+        }
+    }
+
+    /// The tree as shown: everything, or only the files matching the filter
+    /// and the folders leading to them.
+    fn visible_items(&self) -> Vec<TreeItem<'static, String>> {
+        if self.filter.trim().is_empty() {
+            return self.items.clone();
+        }
+        prune_items(&self.items, &self.matches)
+    }
+
+    fn handle_key_file_view(&mut self, key: KeyEvent, terminal_height: u16) -> Action {
+        let visible_height = terminal_height.saturating_sub(5) as usize;
+        let AppMode::Viewing(view) = &mut self.mode else {
+            return Action::Continue;
+        };
+
+        if view.searching {
+            match key.code {
+                KeyCode::Enter => {
+                    view.searching = false;
+                    view.find(true);
+                }
+                KeyCode::Esc => {
+                    view.searching = false;
+                    view.query.clear();
+                }
+                KeyCode::Backspace => {
+                    view.query.pop();
+                }
+                KeyCode::Char(c) => view.query.push(c),
+                _ => {}
+            }
+            return Action::Continue;
+        }
+
+        let page = visible_height.max(1) as isize;
+        match (self.keymap.action(key), key.code) {
+            (_, KeyCode::Esc) if !view.query.is_empty() => view.query.clear(),
+            (Some(KeyAction::Quit | KeyAction::Collapse), _) | (_, KeyCode::Esc) => {
                 self.exit_file_view();
             }
-            KeyCode::Down | KeyCode::Char('j') => self.scroll_down(visible_height),
-            KeyCode::Up | KeyCode::Char('k') => self.scroll_up(),
+            (Some(KeyAction::Down), _) => self.scroll_down(visible_height),
+            (Some(KeyAction::Up), _) => self.scroll_up(),
+            (Some(KeyAction::Raw), _) => self.toggle_raw(),
+            (Some(KeyAction::Filter), _) => {
+                view.searching = true;
+                view.query.clear();
+            }
+            (_, KeyCode::PageDown) | (_, KeyCode::Char(' ')) => {
+                view.scroll_by(page, visible_height)
+            }
+            (_, KeyCode::PageUp) => view.scroll_by(-page, visible_height),
+            (_, KeyCode::Home) | (_, KeyCode::Char('g')) => view.scroll = 0,
+            (_, KeyCode::End) | (_, KeyCode::Char('G')) => {
+                view.scroll_by(isize::MAX, visible_height)
+            }
+            (_, KeyCode::Char('n')) if !view.query.is_empty() => view.find(true),
+            (_, KeyCode::Char('N')) if !view.query.is_empty() => view.find(false),
+            (_, KeyCode::Char('L')) => view.line_numbers = !view.line_numbers,
+            (_, KeyCode::Char('w')) => view.wrap = !view.wrap,
             _ => {}
         };
-        Ok(Action::Continue)
+        Action::Continue
     }
 
-    pub fn toggle_selected_file(&mut self) {
-        if let Some(selected) = self.tree_state.selected().last() {
-            let path = PathBuf::from(selected);
-            if path.is_file() {
-                if self.selected_files.contains(selected) {
-                    self.selected_files.remove(selected);
-                } else {
-                    self.selected_files.insert(selected.clone());
+    fn handle_key_basket(&mut self, key: KeyEvent) -> Action {
+        let AppMode::Basket { selected } = self.mode else {
+            return Action::Continue;
+        };
+
+        let basket = self.basket();
+        self.mode = match key.code {
+            KeyCode::Enter | KeyCode::Char('e') => self.confirming(),
+            KeyCode::Esc | KeyCode::Char('b') | KeyCode::Char('q') => AppMode::Browsing,
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < basket.len() => AppMode::Basket {
+                selected: selected + 1,
+            },
+            KeyCode::Up | KeyCode::Char('k') => AppMode::Basket {
+                selected: selected.saturating_sub(1),
+            },
+            KeyCode::Char('x') | KeyCode::Char(' ') | KeyCode::Delete | KeyCode::Backspace => {
+                if let Some(file) = basket.get(selected) {
+                    self.selected_files.remove(file);
+                }
+                AppMode::Basket {
+                    selected: selected.min(basket.len().saturating_sub(2)),
+                }
+            }
+            _ => AppMode::Basket { selected },
+        };
+        Action::Continue
+    }
+
+    /// The confirmation for the current selection.  Finding collisions
+    /// renders every destination path, so it's done once here rather than on
+    /// each draw.
+    fn confirming(&self) -> AppMode {
+        let layered = self.layered_files();
+        AppMode::Confirming {
+            collisions: find_destination_collisions(
+                &layered.files,
+                &self.renderer,
+                &self.import_options,
+            ),
+        }
+    }
+
+    fn handle_key_confirm(&mut self, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => return self.confirm_import(),
+            KeyCode::Char('n') | KeyCode::Char('q') | KeyCode::Esc => {
+                self.mode = AppMode::Basket { selected: 0 };
+            }
+            _ => {}
+        };
+        Action::Continue
+    }
+
+    /// Starts the import, first showing the values handed to templates for
+    /// review when the selected files use any.
+    fn confirm_import(&mut self) -> Action {
+        if let Err(e) = self.choose_engine() {
+            self.mode = AppMode::Error {
+                message: e.to_string(),
+            };
+            return Action::Continue;
+        }
+
+        match VariableForm::new(&self.selected_files, &self.renderer) {
+            Ok(Some(form)) => {
+                self.mode = AppMode::Prompting(form);
+                Action::Continue
+            }
+            Ok(None) => self.start_import(),
+            Err(e) => {
+                self.mode = AppMode::Error {
+                    message: e.to_string(),
+                };
+                Action::Continue
+            }
+        }
+    }
+
+    /// Switches to the engine the selected files' templates are written for,
+    /// with their defaults.  Files from templates that need different engines
+    /// can't be imported together.
+    fn choose_engine(&mut self) -> Result<(), Box<dyn Error>> {
+        let roots: BTreeSet<PathBuf> = self
+            .selected_files
+            .iter()
+            .filter_map(|file| template_root_of(Path::new(file)))
+            .collect();
+
+        let mut engines = Vec::new();
+        for root in &roots {
+            engines.push(Manifest::load(root)?.engine.unwrap_or_default());
+        }
+
+        let Some(&engine) = engines.first() else {
+            return Ok(());
+        };
+        if engines.iter().any(|e| *e != engine) {
+            return Err("the selected files come from templates that use different engines, import them separately".into());
+        }
+
+        // In the order the templates are layered, later ones winning
+        let layered: Vec<PathBuf> = self
+            .template_order
+            .iter()
+            .filter(|root| roots.contains(*root))
+            .cloned()
+            .collect();
+        let mut defaults = TemplateDefaults::default();
+        for root in &layered {
+            defaults.merge(Manifest::load(root)?.defaults);
+        }
+        self.renderer.set_template_defaults(&defaults)?;
+        self.renderer.load_template_files(&layered)?;
+        self.renderer.set_engine(engine);
+        self.clear_previews();
+        Ok(())
+    }
+
+    /// Drops the cached previews, which were rendered with values that have
+    /// since changed.
+    fn clear_previews(&self) {
+        if let Ok(mut previews) = self.previews.lock() {
+            previews.clear();
+        }
+    }
+
+    /// The renderer set up for the engine `file`'s template is written for,
+    /// with its defaults, partials and data.
+    fn renderer_for(&self, file: &Path) -> Renderer {
+        let mut renderer = self.renderer.clone();
+        let Some(root) = template_root_of(file) else {
+            return renderer;
+        };
+        let Ok(manifest) = Manifest::load(&root) else {
+            return renderer;
+        };
+        if let Some(engine) = manifest.engine {
+            renderer.set_engine(engine);
+        }
+        if let Err(e) = renderer.set_template_defaults(&manifest.defaults) {
+            warn!("Previewing without the template's defaults: {}", e);
+        }
+        if let Err(e) = renderer.load_template_files(&[root]) {
+            warn!("Previewing without the template's partials and data: {}", e);
+        }
+        // A `{{#each list}}` file is previewed as its first item's
+        if let Ok(renderers) = renderer.for_each_item(file)
+            && let Some(first) = renderers.into_iter().next()
+        {
+            return first.into_owned();
+        }
+        renderer
+    }
+
+    /// Hands over to the importer, first asking about each destination that
+    /// already exists when the conflict policy is to prompt.
+    fn start_import(&mut self) -> Action {
+        if self.import_options.on_conflict != ConflictPolicy::Prompt {
+            return Action::Extract;
+        }
+
+        let templates_path = get_templates_path();
+        let mut conflicts: Vec<Conflict> = Vec::new();
+        for file in &self.layered_files().files {
+            // A `{{#each list}}` file stands for one file per item
+            let Ok(renderers) = self.renderer.for_each_item(file) else {
+                continue;
+            };
+            for renderer in &renderers {
+                if matches!(skip_reason(file, renderer), Ok(Some(_))) {
+                    continue;
                 }
+                let Ok(dest) = compute_destination(
+                    file,
+                    &templates_path,
+                    &self.import_options.output_dir,
+                    self.import_options.keep_root,
+                    Some(renderer),
+                ) else {
+                    continue;
+                };
+                if dest.exists() {
+                    conflicts.push(Conflict {
+                        diff: self.diff_against_local(file, &dest, renderer),
+                        dest,
+                    });
+                }
+            }
+        }
+
+        if conflicts.is_empty() {
+            return Action::Extract;
+        }
+
+        conflicts.sort_by(|a, b| a.dest.cmp(&b.dest));
+        self.import_options.resolutions.clear();
+        self.mode = AppMode::Resolving {
+            conflicts,
+            scroll: 0,
+        };
+        Action::Continue
+    }
+
+    /// What importing template file `src` with `renderer` would change in
+    /// `dest`.
+    fn diff_against_local(
+        &self,
+        src: &Path,
+        dest: &Path,
+        renderer: &Renderer,
+    ) -> Vec<Line<'static>> {
+        let (Ok(content), Ok(local)) = (fs::read_to_string(src), fs::read_to_string(dest)) else {
+            return vec![Line::from("Binary file, no diff to show.")];
+        };
+        if is_binary(src, content.as_bytes()).unwrap_or(true) {
+            return vec![Line::from("Binary file, no diff to show.")];
+        }
+        let body = match split_frontmatter(&content) {
+            Ok((_, body)) => body,
+            Err(_) => &content,
+        };
+        let rendered = interpolate_file(body, renderer);
+
+        if rendered == local {
+            return vec![Line::from(
+                "No differences, the local file already matches the template.",
+            )];
+        }
+        diff_lines(&local, &rendered)
+    }
+
+    fn handle_key_form(&mut self, key: KeyEvent) -> Action {
+        let AppMode::Prompting(form) = &mut self.mode else {
+            return Action::Continue;
+        };
+
+        let last = form.fields.len() - 1;
+        match key.code {
+            KeyCode::Esc => self.mode = AppMode::Browsing,
+            KeyCode::Enter => return self.submit_form(),
+            KeyCode::Up | KeyCode::BackTab => {
+                form.focus = form.focus.checked_sub(1).unwrap_or(last);
+            }
+            KeyCode::Down | KeyCode::Tab => {
+                form.focus = if form.focus == last {
+                    0
+                } else {
+                    form.focus + 1
+                };
+            }
+            KeyCode::Backspace => {
+                let field = &mut form.fields[form.focus];
+                field.value.pop();
+                field.error = None;
+            }
+            KeyCode::Char(c) => {
+                let field = &mut form.fields[form.focus];
+                field.value.push(c);
+                field.error = None;
+            }
+            _ => {}
+        }
+
+        Action::Continue
+    }
+
+    /// Applies the form's values, or points at the first one that doesn't
+    /// pass validation.
+    fn submit_form(&mut self) -> Action {
+        let AppMode::Prompting(form) = &mut self.mode else {
+            return Action::Continue;
+        };
+
+        let mut values = Vec::new();
+        for field in &mut form.fields {
+            match field.parse() {
+                Ok(value) => {
+                    field.error = None;
+                    values.extend(value.map(|value| (field.name.clone(), value)));
+                }
+                Err(e) => field.error = Some(e),
+            }
+        }
+
+        if let Some(invalid) = form.fields.iter().position(|f| f.error.is_some()) {
+            form.focus = invalid;
+            return Action::Continue;
+        }
+
+        for (name, value) in values {
+            self.renderer.set_variable(&name, value);
+        }
+        self.clear_previews();
+        self.start_import()
+    }
+
+    fn handle_key_resolve(&mut self, key: KeyEvent) -> Action {
+        let AppMode::Resolving { conflicts, scroll } = &mut self.mode else {
+            return Action::Continue;
+        };
+
+        let policy = match key.code {
+            KeyCode::Char('o') => ConflictPolicy::Overwrite,
+            KeyCode::Char('s') => ConflictPolicy::Skip,
+            KeyCode::Char('b') => ConflictPolicy::Backup,
+            KeyCode::Down | KeyCode::Char('j') => {
+                let total_lines = conflicts.first().map_or(0, |c| c.diff.len());
+                if *scroll + 1 < total_lines {
+                    *scroll += 1;
+                }
+                return Action::Continue;
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                *scroll = scroll.saturating_sub(1);
+                return Action::Continue;
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.import_options.resolutions.clear();
+                self.mode = AppMode::Browsing;
+                return Action::Continue;
+            }
+            _ => return Action::Continue,
+        };
+
+        let conflict = conflicts.remove(0);
+        *scroll = 0;
+        self.import_options
+            .resolutions
+            .insert(conflict.dest, policy);
+
+        if conflicts.is_empty() {
+            Action::Extract
+        } else {
+            Action::Continue
+        }
+    }
+
+    /// Applies one mouse event: the wheel scrolls whatever is on screen, and
+    /// clicking moves between the tree and the basket panel and picks the
+    /// item under the pointer.  Clicking a checkbox, or double clicking a
+    /// file, toggles it.
+    pub fn handle_mouse(&mut self, mouse: MouseEvent, terminal_height: u16) {
+        let position = Position::new(mouse.column, mouse.row);
+        let wheel = match mouse.kind {
+            MouseEventKind::ScrollDown => Some(true),
+            MouseEventKind::ScrollUp => Some(false),
+            _ => None,
+        };
+
+        match &mut self.mode {
+            AppMode::Viewing(_) => match wheel {
+                Some(true) => {
+                    for _ in 0..WHEEL_LINES {
+                        self.scroll_down(terminal_height.saturating_sub(5) as usize);
+                    }
+                }
+                Some(false) => {
+                    for _ in 0..WHEEL_LINES {
+                        self.scroll_up();
+                    }
+                }
+                None => {}
+            },
+            AppMode::Resolving { conflicts, scroll } => {
+                let total_lines = conflicts.first().map_or(0, |c| c.diff.len());
+                match wheel {
+                    Some(true) => {
+                        *scroll = (*scroll + WHEEL_LINES).min(total_lines.saturating_sub(1))
+                    }
+                    Some(false) => *scroll = scroll.saturating_sub(WHEEL_LINES),
+                    None => {}
+                }
+            }
+            AppMode::Discovering(browser) => {
+                let n_matches = browser.index.search(&browser.query).len();
+                match wheel {
+                    Some(true) if browser.selected + 1 < n_matches => browser.selected += 1,
+                    Some(false) => browser.selected = browser.selected.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            AppMode::Browsing | AppMode::Basket { .. } => {
+                self.handle_mouse_panes(mouse.kind, position, wheel);
+            }
+            AppMode::Filtering
+            | AppMode::Destination { .. }
+            | AppMode::Confirming { .. }
+            | AppMode::Prompting(_)
+            | AppMode::Help
+            | AppMode::Error { .. } => {}
+        }
+    }
+
+    fn handle_mouse_panes(
+        &mut self,
+        kind: MouseEventKind,
+        position: Position,
+        wheel: Option<bool>,
+    ) {
+        if let Some(basket_area) = self.basket_area
+            && basket_area.contains(position)
+        {
+            let len = self.selected_files.len();
+            let selected = match self.mode {
+                AppMode::Basket { selected } => selected,
+                _ => 0,
+            };
+            let selected = match (kind, wheel) {
+                (_, Some(true)) => (selected + 1).min(len.saturating_sub(1)),
+                (_, Some(false)) => selected.saturating_sub(1),
+                (MouseEventKind::Down(MouseButton::Left), _) => {
+                    // Rows are drawn from the same offset render_basket uses
+                    let visible_height = basket_area.height.saturating_sub(2) as usize;
+                    let offset = (selected + 1).saturating_sub(visible_height);
+                    let row = position.y.saturating_sub(basket_area.y + 1) as usize;
+                    (offset + row).min(len.saturating_sub(1))
+                }
+                _ => return,
+            };
+            self.mode = AppMode::Basket { selected };
+            return;
+        }
+
+        if !self.tree_area.contains(position) {
+            return;
+        }
+        match (kind, wheel) {
+            (_, Some(true)) => {
+                self.tree_state.scroll_down(WHEEL_LINES);
+            }
+            (_, Some(false)) => {
+                self.tree_state.scroll_up(WHEEL_LINES);
+            }
+            (MouseEventKind::Down(MouseButton::Left), _) => {
+                self.mode = AppMode::Browsing;
+                let Some(path) = self
+                    .tree_state
+                    .rendered_at(position)
+                    .map(<[String]>::to_vec)
+                else {
+                    return;
+                };
+                self.tree_state.click_at(position);
+                self.tree_state.select(path.clone());
+
+                // The checkbox follows the highlight symbol, two columns of
+                // indent per level and the folder symbol
+                let depth = path.len().saturating_sub(1) as u16;
+                let checkbox_x = self.tree_area.x + 1 + 3 + depth * 2 + 2;
+                let on_checkbox = (checkbox_x..checkbox_x + 3).contains(&position.x);
+
+                let items = self.visible_items();
+                let is_file = path
+                    .last()
+                    .and_then(|id| find_item(&items, id))
+                    .is_some_and(|item| item.children().is_empty());
+                let double_click = self
+                    .last_click
+                    .as_ref()
+                    .is_some_and(|(at, last)| *last == path && at.elapsed() < DOUBLE_CLICK);
+
+                if on_checkbox || (is_file && double_click) {
+                    self.toggle_selected_file();
+                    self.last_click = None;
+                } else {
+                    self.last_click = Some((Instant::now(), path));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Fetches the template index to browse, or shows why it couldn't be.
+    fn open_index(&mut self) {
+        self.mode = match Index::load(&self.template_index) {
+            Ok(index) => AppMode::Discovering(IndexBrowser {
+                index,
+                query: String::new(),
+                selected: 0,
+                installed: template_names().unwrap_or_default().into_iter().collect(),
+                status: None,
+            }),
+            Err(e) => AppMode::Error {
+                message: e.to_string(),
+            },
+        };
+    }
+
+    fn handle_key_discover(&mut self, key: KeyEvent) -> Result<Action, Box<dyn Error>> {
+        let AppMode::Discovering(browser) = &mut self.mode else {
+            return Ok(Action::Continue);
+        };
+
+        browser.status = None;
+        let n_matches = browser.index.search(&browser.query).len();
+        match key.code {
+            KeyCode::Esc => self.mode = AppMode::Browsing,
+            KeyCode::Down if browser.selected + 1 < n_matches => browser.selected += 1,
+            KeyCode::Up => browser.selected = browser.selected.saturating_sub(1),
+            KeyCode::Backspace => {
+                browser.query.pop();
+                browser.selected = 0;
+            }
+            KeyCode::Char(c) => {
+                browser.query.push(c);
+                browser.selected = 0;
+            }
+            KeyCode::Enter => {
+                let Some(entry) = browser
+                    .index
+                    .search(&browser.query)
+                    .get(browser.selected)
+                    .map(|entry| (*entry).clone())
+                else {
+                    return Ok(Action::Continue);
+                };
+                if browser.installed.contains(&entry.name) {
+                    browser.status =
+                        Some((format!("'{}' is already installed", entry.name), false));
+                    return Ok(Action::Continue);
+                }
+
+                // git can't print progress over the TUI, so it's told to keep quiet
+                let installed = TemplateSource::parse(&entry.source)
+                    .map_err(|e| e.into())
+                    .and_then(|source| commands::install_source(&source, &entry.name, None, true));
+                browser.status = Some(match installed {
+                    Ok(dest) => {
+                        browser.installed.insert(entry.name.clone());
+                        (
+                            format!(
+                                "Installed '{}' in {}",
+                                entry.name,
+                                clean_path(&dest).display()
+                            ),
+                            true,
+                        )
+                    }
+                    Err(e) => (e.to_string(), false),
+                });
+                self.items = build_tree(&self.root_path)?;
+                self.apply_filter();
+            }
+            _ => {}
+        }
+
+        Ok(Action::Continue)
+    }
+
+    /// Help and error overlays close on any of the usual "back" keys.
+    fn handle_key_dismiss(&mut self, key: KeyEvent) -> Action {
+        if let KeyCode::Char('q') | KeyCode::Char('?') | KeyCode::Esc | KeyCode::Enter = key.code {
+            self.mode = AppMode::Browsing;
+        }
+        Action::Continue
+    }
+
+    /// Toggles the highlighted file, or every file below the highlighted
+    /// folder: all of them are selected unless they already were.
+    pub fn toggle_selected_file(&mut self) {
+        let path = self.tree_state.selected().to_vec();
+        self.read_all_below(&path);
+        let Some(selected) = path.last() else {
+            return;
+        };
+        let items = self.visible_items();
+        let Some(item) = find_item(&items, selected) else {
+            return;
+        };
+
+        let mut files = Vec::new();
+        collect_files(item, &mut files);
+        if files.iter().all(|file| self.selected_files.contains(file)) {
+            for file in &files {
+                self.selected_files.remove(file);
+            }
+        } else {
+            for file in &files {
+                if let Some(root) = template_root_of(Path::new(file))
+                    && !self.template_order.contains(&root)
+                {
+                    self.template_order.push(root);
+                }
+            }
+            self.selected_files.extend(files);
+        }
+    }
+
+    /// Selects every file the tree shows (only the matches while filtering),
+    /// or clears them when they're all selected already.
+    fn toggle_all_files(&mut self) {
+        self.read_all_below(&[]);
+        let mut files = Vec::new();
+        for item in &self.visible_items() {
+            collect_files(item, &mut files);
+        }
+        if files.iter().all(|file| self.selected_files.contains(file)) {
+            for file in &files {
+                self.selected_files.remove(file);
+            }
+            return;
+        }
+
+        for file in &files {
+            if let Some(root) = template_root_of(Path::new(file))
+                && !self.template_order.contains(&root)
+            {
+                self.template_order.push(root);
+            }
+        }
+        self.selected_files.extend(files);
+    }
+
+    /// The selected files, sorted, as listed in the basket panel.
+    fn basket(&self) -> Vec<String> {
+        let mut files: Vec<String> = self.selected_files.iter().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /// The selected files to import, with those a later template overrides
+    /// left out.
+    pub fn layered_files(&self) -> LayeredFiles {
+        let mut layers: Vec<Vec<PathBuf>> = vec![Vec::new(); self.template_order.len() + 1];
+        for file in &self.selected_files {
+            let path = PathBuf::from(file);
+            let layer = template_root_of(&path)
+                .and_then(|root| self.template_order.iter().position(|r| *r == root))
+                .map_or(0, |i| i + 1);
+            layers[layer].push(path);
+        }
+        for layer in &mut layers {
+            layer.sort();
+        }
+
+        layer_templates(&layers, &self.renderer, &self.import_options)
+    }
+
+    pub fn view_selected_file(
+        &mut self,
+        ss: &Arc<SyntaxSet>,
+        theme: &Arc<Theme>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(selected) = self.tree_state.selected().last() {
+            let path = PathBuf::from(selected);
+            if path.is_file() {
+                // Try to read as UTF-8, skip if binary
+                match fs::read_to_string(&path) {
+                    Ok(content) => {
+                        // Files listed as binary are imported as they are
+                        let binary = is_binary(&path, content.as_bytes())?;
+                        let modified = fs::metadata(&path)
+                            .and_then(|metadata| metadata.modified())
+                            .ok();
+                        let cached = modified
+                            .and_then(|modified| self.previews.lock().ok()?.get(&path, modified));
+
+                        let (rendered, raw, highlighting) = match cached {
+                            Some(cached) => (cached.rendered, cached.raw, None),
+                            None => {
+                                let plain: Vec<Line<'static>> = content
+                                    .lines()
+                                    .map(|line| Line::raw(line.replace('\t', "  ")))
+                                    .collect();
+                                let receiver = self.highlight_in_background(
+                                    path, content, binary, modified, ss, theme,
+                                );
+                                (plain.clone(), plain, Some(receiver))
+                            }
+                        };
+
+                        self.mode = AppMode::Viewing(FileView {
+                            path: selected.clone(),
+                            rendered,
+                            raw,
+                            show_raw: false,
+                            scroll: 0,
+                            line_numbers: false,
+                            wrap: false,
+                            query: String::new(),
+                            searching: false,
+                            highlighting,
+                        });
+                    }
+                    Err(e) => {
+                        // File is likely binary, explain why there's no preview
+                        self.mode = AppMode::Error {
+                            message: format!(
+                                "Unable to preview {}: {}",
+                                get_item_text(selected),
+                                e
+                            ),
+                        };
+                    }
+                }
+            } else if path.is_dir() {
+                // For directories, just expand them
+                self.tree_state.key_right();
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders and highlights `content`, the file at `path`, which takes a
+    /// while for a big file, so it's done off the UI thread.  The result is
+    /// cached under `modified`, even when the view is closed before it's
+    /// ready.
+    fn highlight_in_background(
+        &self,
+        path: PathBuf,
+        content: String,
+        binary: bool,
+        modified: Option<SystemTime>,
+        ss: &Arc<SyntaxSet>,
+        theme: &Arc<Theme>,
+    ) -> Receiver<Result<Highlighted, String>> {
+        let renderer = self.renderer_for(&path);
+        let previews = Arc::clone(&self.previews);
+        let generation = previews.lock().map_or(0, |previews| previews.generation);
+        let (ss, theme) = (Arc::clone(ss), Arc::clone(theme));
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let interpolated = if binary {
+                content.clone()
+            } else {
+                interpolate_file(&content, &renderer)
+            };
+            let highlighted = highlight_file(&interpolated, &path, &ss, &theme)
+                .and_then(|rendered| {
+                    Ok(Highlighted {
+                        rendered,
+                        raw: highlight_file(&content, &path, &ss, &theme)?,
+                    })
+                })
+                .map_err(|e| e.to_string());
+
+            if let (Ok(highlighted), Some(modified)) = (&highlighted, modified)
+                && let Ok(mut previews) = previews.lock()
+            {
+                previews.insert(path, modified, generation, highlighted.clone());
+            }
+            // Nobody's waiting when the view was closed first
+            let _ = sender.send(highlighted);
+        });
+        receiver
+    }
+
+    /// Picks up what's happened in the background: a file highlighted, or
+    /// warnings logged.  Returns whether that changed what's on screen.
+    pub fn tick(&mut self) -> bool {
+        let highlighted = self.pick_up_highlighting();
+        self.update_notifications() || highlighted
+    }
+
+    /// Shows the warnings and errors logged since the last tick, and drops
+    /// the notifications that have been up long enough.
+    fn update_notifications(&mut self) -> bool {
+        let before = self.notifications.len();
+        self.notifications
+            .retain(|notification| notification.shown.elapsed() < NOTIFICATION_TIME);
+        let mut changed = self.notifications.len() != before;
+
+        for (level, message) in logging::take_notices() {
+            self.notifications.push(Notification {
+                message,
+                error: level == Level::Error,
+                shown: Instant::now(),
+            });
+            changed = true;
+        }
+        let excess = self.notifications.len().saturating_sub(MAX_NOTIFICATIONS);
+        self.notifications.drain(..excess);
+        changed
+    }
+
+    fn pick_up_highlighting(&mut self) -> bool {
+        let AppMode::Viewing(view) = &mut self.mode else {
+            return false;
+        };
+        let Some(receiver) = &view.highlighting else {
+            return false;
+        };
+
+        match receiver.try_recv() {
+            Ok(Ok(highlighted)) => {
+                view.rendered = highlighted.rendered;
+                view.raw = highlighted.raw;
+            }
+            // The plain text stays up
+            Ok(Err(e)) => warn!(
+                "Unable to highlight {}: {}",
+                clean_path(Path::new(&view.path)).display(),
+                e
+            ),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => {}
+        }
+        view.highlighting = None;
+        true
+    }
+
+    /// Whether something is being worked out in the background, which the
+    /// event loop should check on sooner than it otherwise would.
+    pub fn is_busy(&self) -> bool {
+        matches!(&self.mode, AppMode::Viewing(view) if view.highlighting.is_some())
+    }
+
+    pub fn scroll_up(&mut self) {
+        if let AppMode::Viewing(view) = &mut self.mode {
+            view.scroll = view.scroll.saturating_sub(1);
+        }
+    }
+
+    pub fn scroll_down(&mut self, max_lines: usize) {
+        if let AppMode::Viewing(view) = &mut self.mode
+            && view.scroll + max_lines < view.content().len()
+        {
+            view.scroll += 1;
+        }
+    }
+
+    /// Switches the file view between the rendered file and the template as
+    /// written, keeping the scroll position where both are long enough.
+    pub fn toggle_raw(&mut self) {
+        if let AppMode::Viewing(view) = &mut self.mode {
+            view.show_raw = !view.show_raw;
+            view.scroll = view.scroll.min(view.content().len().saturating_sub(1));
+        }
+    }
+
+    pub fn handle_left_key(&mut self) {
+        self.tree_state.key_left();
+
+        // If selection is empty after left, re-select the first item
+        if self.tree_state.selected().is_empty() && !self.items.is_empty() {
+            let first_id = self.items[0].identifier().clone();
+            self.tree_state.select(vec![first_id]);
+        }
+    }
+
+    pub fn exit_file_view(&mut self) {
+        self.mode = AppMode::Browsing;
+    }
+
+    /// Prefixes an item with its checkbox, highlighting the chars matching
+    /// the filter.  A folder's checkbox shows whether none, some (`[-]`) or
+    /// all of the files below it are selected.
+    pub fn get_display_text(&self, item: &TreeItem<String>, text: &str) -> Line<'static> {
+        let mut spans = match self.matches.get(item.identifier()) {
+            Some(positions) => highlight_matches(text, positions),
+            None => vec![Span::raw(text.to_string())],
+        };
+
+        // Files below a folder that hasn't been read can still be in the
+        // basket, from before the tree was rebuilt
+        if is_unread(item) {
+            let below = format!("{}/", item.identifier());
+            let checkbox = if self
+                .selected_files
+                .iter()
+                .any(|file| file.starts_with(&below))
+            {
+                "[-]"
+            } else {
+                "[ ]"
+            };
+            spans.insert(0, Span::raw(format!("{} ", checkbox)));
+            return Line::from(spans);
+        }
+
+        let mut files = Vec::new();
+        collect_files(item, &mut files);
+        if files.is_empty() {
+            return Line::from(spans);
+        }
+
+        let n_selected = files
+            .iter()
+            .filter(|file| self.selected_files.contains(*file))
+            .count();
+        let checkbox = if n_selected == 0 {
+            "[ ]"
+        } else if n_selected == files.len() {
+            "[x]"
+        } else {
+            "[-]"
+        };
+        spans.insert(0, Span::raw(format!("{} ", checkbox)));
+        Line::from(spans)
+    }
+
+    fn render_tree(&mut self, f: &mut Frame) {
+        self.read_opened_folders();
+        let templates_path = get_templates_path();
+
+        let mut chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)])
+            .split(f.area())
+            .to_vec();
+
+        // The basket panel sits beside the tree once something is selected
+        let basket_selected = match self.mode {
+            AppMode::Basket { selected } => Some(selected),
+            _ => None,
+        };
+        if !self.selected_files.is_empty() || basket_selected.is_some() {
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(chunks[0]);
+            chunks[0] = panes[0];
+            self.render_basket(f, panes[1], basket_selected);
+            self.basket_area = Some(panes[1]);
+        } else {
+            self.basket_area = None;
+        }
+        self.tree_area = chunks[0];
+
+        let visible = self.visible_items();
+        // Rather than panicking in the middle of drawing, say what went wrong
+        let display_items = match render_tree_with_checkboxes(&visible, self) {
+            Ok(items) => items,
+            Err(e) => {
+                render_error(f, &e.to_string());
+                return;
+            }
+        };
+        let filter = if self.filter.is_empty() {
+            String::new()
+        } else {
+            format!(" [/{}: {} match(es)]", self.filter, self.matches.len())
+        };
+
+        let clean_templates_path = clean_path(&templates_path.to_path_buf());
+        let tree_widget = match Tree::new(&display_items) {
+            Ok(tree) => tree,
+            Err(e) => {
+                render_error(f, &DropkickError::Tree(e).to_string());
+                return;
+            }
+        };
+        let tree_widget = tree_widget
+            .block(self.palette.block().title(format!(
+                " Templates: {}{} ",
+                clean_templates_path.to_string_lossy(),
+                filter
+            )))
+            .highlight_style(self.palette.selected())
+            .highlight_symbol(">> ");
+
+        f.render_stateful_widget(tree_widget, chunks[0], &mut self.tree_state);
+
+        if let AppMode::Filtering = self.mode {
+            let input = Paragraph::new(format!("/{}█", self.filter))
+                .block(
+                    self.palette
+                        .block()
+                        .title(" Filter (Enter to browse matches, Esc to clear) "),
+                )
+                .style(Style::default().fg(Color::Yellow));
+            f.render_widget(input, chunks[1]);
+            return;
+        }
+        if let AppMode::Destination { input } = &self.mode {
+            let input = Paragraph::new(format!("{}█", input))
+                .block(
+                    self.palette
+                        .block()
+                        .title(" Import into (Tab completes, Enter to set, Esc to cancel) "),
+                )
+                .style(Style::default().fg(Color::Yellow));
+            f.render_widget(input, chunks[1]);
+            return;
+        }
+
+        let status = Paragraph::new(self.key_hints(basket_selected.is_some()))
+            .block(self.palette.block().title(self.status_summary()))
+            .style(self.palette.status());
+
+        f.render_widget(status, chunks[1]);
+    }
+
+    /// The status bar's title: the template under the cursor, what's in the
+    /// basket, and how an import would treat existing files.
+    fn status_summary(&self) -> String {
+        let template = self
+            .tree_state
+            .selected()
+            .last()
+            .and_then(|selected| template_root_of(Path::new(selected)))
+            .and_then(|root| {
+                root.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "no template".to_string());
+        let size: u64 = self
+            .selected_files
+            .iter()
+            .filter_map(|file| fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let policy = self
+            .import_options
+            .on_conflict
+            .to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string());
+
+        let mut parts = vec![
+            template,
+            format!(
+                "{} selected ({})",
+                self.selected_files.len(),
+                format_size(size)
+            ),
+            format!("on conflict: {}", policy),
+        ];
+        if self.import_options.output_dir != Path::new(".") {
+            parts.insert(
+                2,
+                format!(
+                    "into {}",
+                    clean_path(&self.import_options.output_dir).display()
+                ),
+            );
+        }
+        if self.import_options.dry_run {
+            parts.push("dry run".to_string());
+        }
+        format!(" {} ", parts.join(" | "))
+    }
+
+    /// The keys that matter most in the focused pane: the basket's, or the
+    /// tree's for what the cursor is on.
+    fn key_hints(&self, basket_focused: bool) -> String {
+        if basket_focused {
+            return "↑/k: Up | ↓/j: Down | x/Space: Remove | Enter/e: Import | Esc/b: Back to templates"
+                .to_string();
+        }
+
+        let on_file = self
+            .tree_state
+            .selected()
+            .last()
+            .is_some_and(|selected| Path::new(selected).is_file());
+        let mut hints = vec![(KeyAction::Up, "Up"), (KeyAction::Down, "Down")];
+        if on_file {
+            hints.push((KeyAction::Expand, "View"));
+            hints.push((KeyAction::Toggle, "Toggle"));
+        } else {
+            hints.push((KeyAction::Expand, "Expand"));
+            hints.push((KeyAction::Collapse, "Collapse"));
+            hints.push((KeyAction::Toggle, "Toggle all"));
+        }
+        hints.push((KeyAction::Filter, "Filter"));
+        if self.selected_files.is_empty() {
+            hints.push((KeyAction::Install, "Install"));
+        } else {
+            hints.push((KeyAction::Basket, "Basket"));
+            hints.push((KeyAction::Import, "Export"));
+            hints.push((KeyAction::DryRun, "Dry run"));
+            hints.push((KeyAction::Destination, "Destination"));
+        }
+        hints.push((KeyAction::Help, "Help"));
+        hints.push((KeyAction::Quit, "Quit"));
+
+        hints
+            .iter()
+            .map(|(action, label)| format!("{}: {}", self.keymap.describe(*action), label))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    /// Lists the selected files by template, with their count and total
+    /// size.  `selected` is the highlighted file while the basket has focus.
+    fn render_basket(&self, f: &mut Frame, area: Rect, selected: Option<usize>) {
+        let templates_path = get_templates_path();
+        let basket = self.basket();
+        let total: u64 = basket
+            .iter()
+            .filter_map(|file| fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        let visible_height = area.height.saturating_sub(2) as usize;
+        let offset = selected.map_or(0, |s| (s + 1).saturating_sub(visible_height));
+        let mut lines: Vec<Line> = basket
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(visible_height)
+            .map(|(i, file)| {
+                let name = Path::new(file)
+                    .strip_prefix(&templates_path)
+                    .unwrap_or(Path::new(file))
+                    .to_string_lossy()
+                    .into_owned();
+                if selected == Some(i) {
+                    Line::from(Span::styled(
+                        format!(">> {}", name),
+                        self.palette.selected(),
+                    ))
+                } else {
+                    Line::from(format!("   {}", name))
+                }
+            })
+            .collect();
+        if basket.is_empty() {
+            lines.push(Line::from(Span::styled(
+                "Nothing selected yet, Space adds files",
+                Style::default().fg(Color::Gray),
+            )));
+        }
+
+        let border = if selected.is_some() {
+            self.palette.selected
+        } else {
+            self.palette.border
+        };
+        let panel = Paragraph::new(lines).block(
+            self.palette
+                .block()
+                .border_style(Style::default().fg(border))
+                .title(format!(
+                    " Basket: {} file(s), {} ",
+                    basket.len(),
+                    format_size(total)
+                )),
+        );
+        f.render_widget(panel, area);
+    }
+
+    fn render_form(&self, f: &mut Frame, form: &VariableForm) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)])
+            .split(f.area());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .split(chunks[0]);
+
+        let mut lines = Vec::new();
+        for (i, field) in form.fields.iter().enumerate() {
+            let focused = i == form.focus;
+            let label_style = if focused {
+                Style::default()
+                    .fg(self.palette.selected)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("{}{}: ", if focused { ">> " } else { "   " }, field.name),
+                    label_style,
+                ),
+                Span::raw(format!("{}{}", field.value, if focused { "_" } else { "" })),
+            ]));
+            if let Some(prompt) = field.variable.as_ref().and_then(|v| v.prompt.as_deref()) {
+                lines.push(Line::from(Span::styled(
+                    format!("   {}", prompt),
+                    Style::default().fg(Color::Gray),
+                )));
+            }
+            if let Some(error) = &field.error {
+                lines.push(Line::from(Span::styled(
+                    format!("   {}", error),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+        }
+
+        let fields =
+            Paragraph::new(lines).block(self.palette.block().title(" Template variables "));
+        f.render_widget(fields, panes[0]);
+
+        let (title, rendered) = match form.preview(&self.renderer) {
+            Some((name, rendered)) => (format!(" Preview: {} ", name), rendered),
+            None => (" Preview ".to_string(), String::new()),
+        };
+        let preview = Paragraph::new(rendered).block(self.palette.block().title(title));
+        f.render_widget(preview, panes[1]);
+
+        let help = Paragraph::new("↑/↓/Tab: Move | Type to edit | Enter: Import | Esc: Cancel")
+            .block(self.palette.block().title(" Help "))
+            .style(self.palette.status());
+        f.render_widget(help, chunks[1]);
+    }
+
+    fn render_confirm(&self, f: &mut Frame, collisions: &[DestinationCollision]) {
+        let n_selected = self.selected_files.len();
+        let message = if n_selected == 0 {
+            "No files are selected.  Exit anyway?".to_string()
+        } else if self.import_options.dry_run {
+            format!(
+                "Preview importing {} selected file(s)?  Dry run is on, nothing will be written.",
+                n_selected
+            )
+        } else {
+            format!(
+                "Import {} selected file(s) into the working directory?",
+                n_selected
+            )
+        };
+
+        let mut lines = vec![Line::from(message)];
+
+        let layered = self.layered_files();
+        if !layered.overridden.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(format!(
+                "{} file(s) replaced by the same file from a template selected later:",
+                layered.overridden.len()
+            )));
+            for (file, winner) in &layered.overridden {
+                lines.push(Line::from(format!(
+                    "  {} -> {}",
+                    clean_path(file).to_string_lossy(),
+                    clean_path(winner).to_string_lossy()
+                )));
+            }
+        }
+
+        if !collisions.is_empty() {
+            lines.push(Line::from(""));
+            lines.push(Line::from(Span::styled(
+                "Warning: some selected files land on the same path:",
+                Style::default().fg(Color::Red),
+            )));
+            for collision in collisions {
+                lines.push(Line::from(format!("  {}", collision)));
             }
         }
+
+        lines.push(Line::from(""));
+        lines.push(Line::from("y/Enter: Yes | n/Esc: No"));
+
+        render_popup(f, " Confirm ", lines, Color::Yellow);
     }
+}
 
-    pub fn view_selected_file(
-        &mut self,
-        ss: &SyntaxSet,
-        theme: &Theme,
-    ) -> Result<(), Box<dyn Error>> {
-        if let Some(selected) = self.tree_state.selected().last() {
-            let path = PathBuf::from(selected);
-            if path.is_file() {
-                // Try to read as UTF-8, skip if binary
-                match fs::read_to_string(&path) {
-                    Ok(content) => {
-                        let interpolated = interpolate_file(&content);
-                        let highlighted = highlight_file(&interpolated, &path, ss, theme)?;
+fn render_help(f: &mut Frame, keymap: &Keymap) {
+    let bindings: Vec<(String, &str)> = keymap
+        .bindings()
+        .map(|action| (keymap.describe(action), action.description()))
+        .chain([
+            ("Esc".to_string(), "Clear the filter"),
+            (
+                "x".to_string(),
+                "In the basket, remove the highlighted file",
+            ),
+        ])
+        .collect();
 
-                        self.mode = AppMode::FileView {
-                            path: selected.clone(),
-                            content: highlighted,
-                            scroll: 0,
-                        };
-                    }
-                    Err(_) => {
-                        // File is likely binary, just stay in tree view
-                        // Could optionally show an error message here
-                    }
-                }
-            } else if path.is_dir() {
-                // For directories, just expand them
-                self.tree_state.key_right();
+    let lines = bindings
+        .iter()
+        .map(|(key, description)| {
+            Line::from(vec![
+                Span::styled(
+                    format!("{:>8}  ", key),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(*description),
+            ])
+        })
+        .collect();
+
+    render_popup(f, " Help (Esc to close) ", lines, Color::Cyan);
+}
+
+/// The previewed file from its scroll position, with the search matches
+/// highlighted and line numbers when they're on.
+fn render_file_view(f: &mut Frame, view: &FileView, palette: &Palette) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(3)])
+        .split(f.area());
+
+    let content = view.content();
+    let visible_height = chunks[0].height.saturating_sub(2) as usize;
+    let total_lines = content.len();
+    let number_width = total_lines.max(1).to_string().len();
+
+    // Build visible content with tildes for lines beyond EOF
+    let mut visible_content: Vec<Line> = Vec::new();
+    for i in 0..visible_height {
+        let line_idx = view.scroll + i;
+        if line_idx < total_lines {
+            let mut line = highlight_query(&content[line_idx], &view.query);
+            if view.line_numbers {
+                line.spans.insert(
+                    0,
+                    Span::styled(
+                        format!("{:>width$} ", line_idx + 1, width = number_width),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                );
             }
+            visible_content.push(line);
+        } else {
+            // Add tilde for empty lines beyond EOF
+            visible_content.push(Line::from(Span::styled(
+                "~",
+                Style::default().fg(Color::DarkGray),
+            )));
         }
-        Ok(())
     }
 
-    pub fn scroll_up(&mut self) {
-        if let AppMode::FileView { scroll, .. } = &mut self.mode {
-            *scroll = scroll.saturating_sub(1);
-        }
-    }
+    let file_name = PathBuf::from(&view.path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(&view.path)
+        .to_string();
 
-    pub fn scroll_down(&mut self, max_lines: usize) {
-        if let AppMode::FileView {
-            scroll, content, ..
-        } = &mut self.mode
-        {
-            if *scroll + max_lines < content.len() {
-                *scroll += 1;
-            }
+    // Calculate scroll position indicator
+    let position = if total_lines == 0 {
+        "Empty".to_string()
+    } else if view.scroll == 0 {
+        "Top".to_string()
+    } else if view.scroll + visible_height >= total_lines {
+        "Bottom".to_string()
+    } else {
+        let percentage = ((view.scroll + visible_height / 2) * 100) / total_lines;
+        format!("{}%", percentage)
+    };
+    let search = if view.query.is_empty() {
+        String::new()
+    } else {
+        let n_matches: usize = content
+            .iter()
+            .map(|line| match_ranges(&line_text(line), &view.query).len())
+            .sum();
+        format!(" [/{}: {} match(es)]", view.query, n_matches)
+    };
+
+    let mut paragraph = Paragraph::new(visible_content).block(palette.block().title(format!(
+        " Viewing: {} [{}] ({} - line {}/{}){}{} ",
+        file_name,
+        if view.show_raw { "raw" } else { "rendered" },
+        position,
+        view.scroll + 1,
+        total_lines.max(1),
+        search,
+        if view.highlighting.is_some() {
+            " [loading…]"
+        } else {
+            ""
         }
+    )));
+    if view.wrap {
+        paragraph = paragraph.wrap(Wrap { trim: false });
     }
+    f.render_widget(paragraph, chunks[0]);
+
+    let help = if view.searching {
+        Paragraph::new(format!("/{}█", view.query))
+            .block(
+                palette
+                    .block()
+                    .title(" Search (Enter to jump to the next match, Esc to clear) "),
+            )
+            .style(Style::default().fg(Color::Yellow))
+    } else {
+        Paragraph::new(
+            "↑/k ↓/j: Scroll | PgUp/PgDn/Home/End: Page | /: Search | n/N: Next/Previous match | L: Line numbers | w: Wrap | r: Raw/Rendered | q/Esc: Back",
+        )
+        .block(palette.block().title(" Help "))
+        .style(palette.status())
+    };
+    f.render_widget(help, chunks[1]);
+}
 
-    pub fn handle_left_key(&mut self) {
-        self.tree_state.key_left();
+/// The text of a line without its styling.
+fn line_text(line: &Line) -> String {
+    line.spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect()
+}
 
-        // If selection is empty after left, re-select the first item
-        if self.tree_state.selected().is_empty() && !self.items.is_empty() {
-            let first_id = self.items[0].identifier().clone();
-            self.tree_state.select(vec![first_id]);
+/// Char ranges where `query` appears in `text`, ignoring case unless the
+/// query has an upper case letter (as in the tree's filter).
+fn match_ranges(text: &str, query: &str) -> Vec<(usize, usize)> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let fold = |s: &str| -> Vec<char> {
+        if case_sensitive {
+            s.chars().collect()
+        } else {
+            s.chars().flat_map(char::to_lowercase).collect()
+        }
+    };
+    let (text, query) = (fold(text), fold(query));
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start + query.len() <= text.len() {
+        if text[start..start + query.len()] == query[..] {
+            ranges.push((start, start + query.len()));
+            start += query.len();
+        } else {
+            start += 1;
         }
     }
+    ranges
+}
 
-    pub fn exit_file_view(&mut self) {
-        self.mode = AppMode::TreeView;
+/// `line` with the chars matching `query` picked out, keeping the syntax
+/// colors of the rest.
+fn highlight_query(line: &Line<'static>, query: &str) -> Line<'static> {
+    let ranges = match_ranges(&line_text(line), query);
+    if ranges.is_empty() {
+        return line.clone();
     }
 
-    pub fn get_display_text(&self, identifier: &str, text: &str) -> String {
-        let path = PathBuf::from(identifier);
-        if path.is_file() {
-            let checkbox = if self.selected_files.contains(identifier) {
-                "[x]"
-            } else {
-                "[ ]"
-            };
-            format!("{} {}", checkbox, text)
-        } else {
-            text.to_string()
+    let matched = |i: usize| ranges.iter().any(|&(start, end)| (start..end).contains(&i));
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut i = 0;
+    for span in &line.spans {
+        let mut current = String::new();
+        let mut current_matched = false;
+        for c in span.content.chars() {
+            if matched(i) != current_matched && !current.is_empty() {
+                spans.push(match_span(
+                    std::mem::take(&mut current),
+                    span.style,
+                    current_matched,
+                ));
+            }
+            current_matched = matched(i);
+            current.push(c);
+            i += 1;
+        }
+        if !current.is_empty() {
+            spans.push(match_span(current, span.style, current_matched));
         }
     }
+    Line::from(spans)
+}
 
-    fn render_tree(&mut self, f: &mut Frame) {
-        let templates_path = get_templates_path();
+fn match_span(text: String, style: Style, matched: bool) -> Span<'static> {
+    if matched {
+        Span::styled(text, style.fg(Color::Black).bg(Color::Yellow))
+    } else {
+        Span::styled(text, style)
+    }
+}
 
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0), Constraint::Length(3)])
-            .split(f.area());
+/// Shows the first unresolved conflict as a diff of the local file against
+/// the rendered template, with the choices below it.
+fn render_resolve(f: &mut Frame, conflicts: &[Conflict], scroll: usize, palette: &Palette) {
+    let Some(conflict) = conflicts.first() else {
+        return;
+    };
 
-        let display_items = render_tree_with_checkboxes(&self.items, &self);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(3)])
+        .split(f.area());
 
-        let clean_templates_path = clean_path(&templates_path.to_path_buf());
-        let tree_widget = Tree::new(&display_items)
-            .expect("Failed to create tree widget")
-            .block(Block::default().borders(Borders::ALL).title(format!(
-                " Templates: {} ({} selected) ",
-                clean_templates_path.to_string_lossy(),
-                self.selected_files.len()
-            )))
-            .highlight_style(
-                Style::default()
-                    .fg(Color::Black)
-                    .bg(Color::Cyan)
-                    .add_modifier(Modifier::BOLD),
-            )
-            .highlight_symbol(">> ");
+    let visible_height = chunks[0].height.saturating_sub(2) as usize;
+    let visible: Vec<Line> = conflict
+        .diff
+        .iter()
+        .skip(scroll)
+        .take(visible_height)
+        .cloned()
+        .collect();
+    let more = if conflicts.len() > 1 {
+        format!(", {} more after this one", conflicts.len() - 1)
+    } else {
+        String::new()
+    };
 
-        f.render_stateful_widget(tree_widget, chunks[0], &mut self.tree_state);
+    let diff = Paragraph::new(visible).block(
+        palette
+            .block()
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(format!(
+                " '{}' already exists{}: - local, + template ",
+                conflict.dest.to_string_lossy(),
+                more
+            )),
+    );
+    f.render_widget(diff, chunks[0]);
+
+    let help = Paragraph::new(
+        "o: Overwrite | s: Skip | b: Backup to .orig | ↑/k ↓/j: Scroll | Esc: Cancel",
+    )
+    .block(palette.block().title(" Help "))
+    .style(palette.status());
+    f.render_widget(help, chunks[1]);
+}
+
+/// The templates in the index matching the search, above the search itself.
+fn render_discover(f: &mut Frame, browser: &IndexBrowser, palette: &Palette) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(3),
+            Constraint::Length(3),
+        ])
+        .split(f.area());
+
+    let matches = browser.index.search(&browser.query);
+    let visible_height = chunks[0].height.saturating_sub(2) as usize;
+    let offset = (browser.selected + 1).saturating_sub(visible_height);
+    let lines: Vec<Line> = matches
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(visible_height)
+        .map(|(i, entry)| {
+            let name_style = if i == browser.selected {
+                palette.selected()
+            } else {
+                Style::default().add_modifier(Modifier::BOLD)
+            };
+            let mut spans = vec![
+                Span::styled(
+                    format!(
+                        "{}{}",
+                        if i == browser.selected { ">> " } else { "   " },
+                        entry.name
+                    ),
+                    name_style,
+                ),
+                Span::styled(
+                    format!("  {}", entry.description),
+                    Style::default().fg(Color::Gray),
+                ),
+            ];
+            if browser.installed.contains(&entry.name) {
+                spans.push(Span::styled(
+                    " [installed]",
+                    Style::default().fg(Color::Green),
+                ));
+            }
+            Line::from(spans)
+        })
+        .collect();
 
-        let help = Paragraph::new("↑/k: Up | ↓/j: Down | ←/h: Collapse | →/l: Expand/View | Space: Toggle | e: Export | q: Quit")
-                        .block(Block::default().borders(Borders::ALL).title(" Help "))
-                        .style(Style::default().fg(Color::Gray));
+    let list = Paragraph::new(lines).block(
+        palette
+            .block()
+            .title(format!(" Template index ({} match(es)) ", matches.len())),
+    );
+    f.render_widget(list, chunks[0]);
+
+    let input = Paragraph::new(format!("{}█", browser.query))
+        .block(palette.block().title(" Search "))
+        .style(Style::default().fg(Color::Yellow));
+    f.render_widget(input, chunks[1]);
+
+    let help = match &browser.status {
+        Some((message, true)) => {
+            Paragraph::new(message.as_str()).style(Style::default().fg(Color::Green))
+        }
+        Some((message, false)) => {
+            Paragraph::new(message.as_str()).style(Style::default().fg(Color::Red))
+        }
+        None => Paragraph::new("↑/↓: Move | Type to search | Enter: Install | Esc: Back")
+            .style(palette.status()),
+    };
+    f.render_widget(help.block(palette.block().title(" Help ")), chunks[2]);
+}
 
-        f.render_widget(help, chunks[1]);
+/// A byte count as `512 B`, `3.4 KB` or `1.2 MB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
     }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
 
-    fn render_file_view(&self, f: &mut Frame, path: &str, content: &[Line], scroll: usize) {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0), Constraint::Length(3)])
-            .split(f.area());
+/// A unified diff from `old` to `new`, colored like `git diff`.
+fn diff_lines(old: &str, new: &str) -> Vec<Line<'static>> {
+    diffy::create_patch(old, new)
+        .to_string()
+        .lines()
+        // The ---/+++ header only names "original" and "modified"
+        .skip(2)
+        .map(|line| {
+            let color = match line.chars().next() {
+                Some('+') => Color::Green,
+                Some('-') => Color::Red,
+                Some('@') => Color::Cyan,
+                _ => Color::Reset,
+            };
+            Line::from(Span::styled(line.to_string(), Style::default().fg(color)))
+        })
+        .collect()
+}
 
-        let visible_height = chunks[0].height.saturating_sub(2) as usize;
-        let total_lines = content.len();
+/// Completes the last part of the directory path being typed as far as the
+/// directories it could name agree, adding a separator once there's only one.
+fn complete_directory(input: &mut String) {
+    let split = input.rfind(std::path::is_separator).map_or(0, |i| i + 1);
+    let (parent, prefix) = input.split_at(split);
+    let dir = match parent {
+        "" => PathBuf::from("."),
+        parent => expand_tilde(Path::new(parent)),
+    };
+    let names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.starts_with(prefix))
+        // Hidden folders only once a `.` is typed
+        .filter(|name| prefix.starts_with('.') || !name.starts_with('.'))
+        .collect();
+    let Some((first, rest)) = names.split_first() else {
+        return;
+    };
 
-        // Build visible content with tildes for lines beyond EOF
-        let mut visible_content: Vec<Line> = Vec::new();
-        for i in 0..visible_height {
-            let line_idx = scroll + i;
-            if line_idx < total_lines {
-                visible_content.push(content[line_idx].clone());
-            } else {
-                // Add tilde for empty lines beyond EOF
-                visible_content.push(Line::from(Span::styled(
-                    "~",
-                    Style::default().fg(Color::DarkGray),
-                )));
-            }
-        }
+    let mut common = first.clone();
+    for name in rest {
+        let shared = common
+            .chars()
+            .zip(name.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        common = common.chars().take(shared).collect();
+    }
+    if rest.is_empty() {
+        common.push(std::path::MAIN_SEPARATOR);
+    }
+    input.truncate(split);
+    input.push_str(&common);
+}
 
-        let file_name = PathBuf::from(path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(&path)
-            .to_string();
+fn render_error(f: &mut Frame, message: &str) {
+    render_popup(
+        f,
+        " Error ",
+        vec![
+            Line::from(message.to_string()),
+            Line::from(""),
+            Line::from("Esc/Enter: Dismiss"),
+        ],
+        Color::Red,
+    );
+}
 
-        // Calculate scroll position indicator
-        let position = if total_lines == 0 {
-            "Empty".to_string()
-        } else if scroll == 0 {
-            "Top".to_string()
-        } else if scroll + visible_height >= total_lines {
-            "Bottom".to_string()
+/// Stacks the notifications in the top right corner, over whatever is
+/// already on screen.
+fn render_notifications(f: &mut Frame, notifications: &[Notification]) {
+    let area = f.area();
+    let width = area.width.min(48);
+    let mut y = area.y + 1;
+    for notification in notifications.iter().rev() {
+        let (title, color) = if notification.error {
+            (" Error (Esc to dismiss) ", Color::Red)
         } else {
-            let percentage = ((scroll + visible_height / 2) * 100) / total_lines;
-            format!("{}%", percentage)
+            (" Warning (Esc to dismiss) ", Color::Yellow)
         };
+        let lines = vec![Line::from(notification.message.clone())];
+        let height = (wrapped_height(&lines, width) + 2).min(area.bottom().saturating_sub(y));
+        if height < 3 {
+            break;
+        }
 
-        let paragraph = Paragraph::new(visible_content).block(
-            Block::default().borders(Borders::ALL).title(format!(
-                " Viewing: {} ({} - line {}/{}) ",
-                file_name,
-                position,
-                scroll + 1,
-                total_lines.max(1)
-            )),
+        let toast = Rect::new(area.right() - width, y, width, height);
+        let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(color))
+                .title(title),
         );
+        f.render_widget(Clear, toast);
+        f.render_widget(paragraph, toast);
+        y += height;
+    }
+}
 
-        f.render_widget(paragraph, chunks[0]);
-
-        let help = Paragraph::new(
-            "↑/k: Scroll Up | ↓/j: Scroll Down | ←/h: Back to Tree | q/Esc: Back to Tree",
-        )
-        .block(Block::default().borders(Borders::ALL).title(" Help "))
-        .style(Style::default().fg(Color::Gray));
+/// How many rows `lines` take up wrapped inside a bordered box `width` wide.
+fn wrapped_height(lines: &[Line], width: u16) -> u16 {
+    let inner = width.saturating_sub(2).max(1) as usize;
+    lines
+        .iter()
+        .map(|line| line.width().div_ceil(inner).max(1) as u16)
+        .sum()
+}
 
-        f.render_widget(help, chunks[1]);
-    }
+/// Draws a bordered box centered over whatever is already on screen.
+fn render_popup(f: &mut Frame, title: &str, lines: Vec<Line>, border_color: Color) {
+    let area = f.area();
+    let width = area.width.min(64);
+    let height = area.height.min(wrapped_height(&lines, width) + 2);
+    let popup = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+
+    let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(border_color))
+            .title(title.to_string()),
+    );
+
+    f.render_widget(Clear, popup);
+    f.render_widget(paragraph, popup);
 }
 
 fn build_tree(path: &Path) -> Result<Vec<TreeItem<'static, String>>, Box<dyn Error>> {
@@ -346,13 +2565,14 @@ fn build_tree(path: &Path) -> Result<Vec<TreeItem<'static, String>>, Box<dyn Err
 
     paths.sort();
 
+    // Each template's folders are read as they're opened, so a huge
+    // template doesn't hold up the start
     for entry in paths {
         if let Some(name) = entry.file_name().and_then(|n| n.to_str()) {
             let identifier = entry.to_string_lossy().to_string();
 
             if entry.is_dir() {
-                let children = build_tree_recursive(&entry)?;
-                items.push(TreeItem::new(identifier, name.to_string(), children)?);
+                items.push(unread_folder(identifier, name)?);
             } else {
                 items.push(TreeItem::new_leaf(identifier, name.to_string()));
             }
@@ -362,10 +2582,43 @@ fn build_tree(path: &Path) -> Result<Vec<TreeItem<'static, String>>, Box<dyn Err
     Ok(items)
 }
 
-fn build_tree_recursive(path: &Path) -> Result<Vec<TreeItem<'static, String>>, Box<dyn Error>> {
-    let mut items = Vec::new();
+/// Stands in for the contents of a folder that hasn't been read yet, so the
+/// tree shows the folder as one that opens.
+const UNREAD: &str = "…";
+
+fn unread_folder(
+    identifier: String,
+    name: &str,
+) -> Result<TreeItem<'static, String>, Box<dyn Error>> {
+    let placeholder = TreeItem::new_leaf(format!("{}/{}", identifier, UNREAD), UNREAD.to_string());
+    Ok(TreeItem::new(
+        identifier,
+        name.to_string(),
+        vec![placeholder],
+    )?)
+}
 
-    let entries = fs::read_dir(path)?;
+fn is_unread(item: &TreeItem<String>) -> bool {
+    matches!(item.children(), [only] if *only.identifier() == format!("{}/{}", item.identifier(), UNREAD))
+}
+
+/// The entries of `dir`, a folder within template folder `root`, with the
+/// folders among them left unread.
+fn read_folder(
+    dir: &Path,
+    root: &Path,
+    follow_symlinks: bool,
+) -> Result<Vec<TreeItem<'static, String>>, Box<dyn Error>> {
+    // Each template is its own root, so a symlinked template folder is fine
+    // but links out of it are not.  The folders on the way down to `dir`
+    // count as its ancestors, so a link back up to one of them is still
+    // caught.
+    let mut guard = SymlinkGuard::new(root, follow_symlinks);
+    for ancestor in dir.ancestors().take_while(|ancestor| *ancestor != root) {
+        guard.should_descend(ancestor);
+    }
+
+    let entries = fs::read_dir(dir)?;
     let mut paths: Vec<PathBuf> = entries
         .filter_map(|e| e.ok())
         .map(|e| e.path())
@@ -374,14 +2627,19 @@ fn build_tree_recursive(path: &Path) -> Result<Vec<TreeItem<'static, String>>, B
 
     paths.sort();
 
+    let mut items = Vec::new();
     for entry in paths {
         if let Some(name) = entry.file_name().and_then(|n| n.to_str()) {
             let identifier = entry.to_string_lossy().to_string();
 
             if entry.is_dir() {
-                let children = build_tree_recursive(&entry)?;
-                items.push(TreeItem::new(identifier, name.to_string(), children)?);
-            } else {
+                if !guard.should_descend(&entry) {
+                    continue;
+                }
+                // It's read when expanded, so the walk doesn't stay in it
+                guard.leave();
+                items.push(unread_folder(identifier, name)?);
+            } else if guard.allows_file(&entry) {
                 items.push(TreeItem::new_leaf(identifier, name.to_string()));
             }
         }
@@ -390,6 +2648,147 @@ fn build_tree_recursive(path: &Path) -> Result<Vec<TreeItem<'static, String>>, B
     Ok(items)
 }
 
+/// The item at `path`, identifiers from the top of the tree down.
+fn item_mut<'a>(
+    items: &'a mut [TreeItem<'static, String>],
+    path: &[String],
+) -> Option<&'a mut TreeItem<'static, String>> {
+    let (first, rest) = path.split_first()?;
+    let mut item = items.iter_mut().find(|item| item.identifier() == first)?;
+    for identifier in rest {
+        let index = item
+            .children()
+            .iter()
+            .position(|child| child.identifier() == identifier)?;
+        item = item.child_mut(index)?;
+    }
+    Some(item)
+}
+
+/// The path of identifiers to each folder below `items` that hasn't been
+/// read yet.
+fn collect_unread_folders(
+    items: &[TreeItem<'static, String>],
+    parents: &mut Vec<String>,
+    folders: &mut Vec<Vec<String>>,
+) {
+    for item in items {
+        parents.push(item.identifier().clone());
+        if is_unread(item) {
+            folders.push(parents.clone());
+        } else {
+            collect_unread_folders(item.children(), parents, folders);
+        }
+        parents.pop();
+    }
+}
+
+/// The item for `identifier` anywhere in the tree.
+fn find_item<'a>(
+    items: &'a [TreeItem<'static, String>],
+    identifier: &str,
+) -> Option<&'a TreeItem<'static, String>> {
+    items.iter().find_map(|item| {
+        if item.identifier() == identifier {
+            Some(item)
+        } else {
+            find_item(item.children(), identifier)
+        }
+    })
+}
+
+/// The path of identifiers from the top of the tree to each file, as the
+/// tree state addresses them.
+fn collect_file_paths(
+    items: &[TreeItem<'static, String>],
+    parents: &mut Vec<String>,
+    files: &mut Vec<Vec<String>>,
+) {
+    for item in items {
+        parents.push(item.identifier().clone());
+        if item.children().is_empty() {
+            if Path::new(item.identifier()).is_file() {
+                files.push(parents.clone());
+            }
+        } else {
+            collect_file_paths(item.children(), parents, files);
+        }
+        parents.pop();
+    }
+}
+
+/// `items` without the files missing from `matches` and the folders left
+/// empty by that.
+fn prune_items(
+    items: &[TreeItem<'static, String>],
+    matches: &HashMap<String, Vec<usize>>,
+) -> Vec<TreeItem<'static, String>> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let text = get_item_text(item.identifier());
+            if item.children().is_empty() {
+                return matches
+                    .contains_key(item.identifier())
+                    .then(|| TreeItem::new_leaf(item.identifier().clone(), text));
+            }
+
+            let children = prune_items(item.children(), matches);
+            if children.is_empty() {
+                return None;
+            }
+            TreeItem::new(item.identifier().clone(), text, children).ok()
+        })
+        .collect()
+}
+
+/// `text` split into spans, with the chars at `positions` emphasized.
+fn highlight_matches(text: &str, positions: &[usize]) -> Vec<Span<'static>> {
+    let highlight = Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut current = String::new();
+    let mut current_matched = false;
+
+    for (i, c) in text.chars().enumerate() {
+        let matched = positions.contains(&i);
+        if matched != current_matched && !current.is_empty() {
+            let run = std::mem::take(&mut current);
+            spans.push(if current_matched {
+                Span::styled(run, highlight)
+            } else {
+                Span::raw(run)
+            });
+        }
+        current_matched = matched;
+        current.push(c);
+    }
+    if !current.is_empty() {
+        spans.push(if current_matched {
+            Span::styled(current, highlight)
+        } else {
+            Span::raw(current)
+        });
+    }
+
+    spans
+}
+
+/// Every file at or below `item`.
+fn collect_files(item: &TreeItem<String>, files: &mut Vec<String>) {
+    if item.children().is_empty() {
+        if Path::new(item.identifier()).is_file() {
+            files.push(item.identifier().clone());
+        }
+        return;
+    }
+
+    for child in item.children() {
+        collect_files(child, files);
+    }
+}
+
 fn get_item_text(path: &str) -> String {
     let file_name = PathBuf::from(path)
         .file_name()
@@ -397,75 +2796,41 @@ fn get_item_text(path: &str) -> String {
         .unwrap_or(path)
         .to_string();
 
-    // Strip .tt extension for display
-    file_name
-        .strip_suffix(".tt")
-        .unwrap_or(&file_name)
-        .to_string()
+    // Strip .tt extension for display, keeping any condition visible
+    let stripped = strip_template_suffix(&file_name).unwrap_or(&file_name);
+    match split_flag(&file_name) {
+        (_, Some(flag)) if stripped != file_name => format!("{} [{}]", stripped, flag),
+        _ => stripped.to_string(),
+    }
 }
 
 pub fn render_tree_with_checkboxes<'a>(
     items: &'a [TreeItem<'a, String>],
     app: &App,
-) -> Vec<TreeItem<'a, String>> {
+) -> Result<Vec<TreeItem<'a, String>>, DropkickError> {
     items
         .iter()
         .map(|item| {
             let text = get_item_text(item.identifier());
-            let display_text = app.get_display_text(item.identifier(), &text);
+            let display_text = app.get_display_text(item, &text);
 
             if item.children().is_empty() {
-                TreeItem::new_leaf(item.identifier().clone(), display_text)
+                Ok(TreeItem::new_leaf(item.identifier().clone(), display_text))
             } else {
-                let children = render_tree_with_checkboxes(item.children(), app);
+                let children = render_tree_with_checkboxes(item.children(), app)?;
                 TreeItem::new(item.identifier().clone(), display_text, children)
-                    .expect("Failed to create tree item")
+                    .map_err(DropkickError::Tree)
             }
         })
         .collect()
 }
 
-fn should_show_entry(path: &Path) -> bool {
-    // Get the file name
-    let file_name = match path.file_name().and_then(|n| n.to_str()) {
-        Some(name) => name,
-        None => return false,
-    };
-
-    // Hide .DS_Store files
-    if file_name.eq_ignore_ascii_case(".ds_store") {
-        return false;
-    }
-
-    if file_name.eq_ignore_ascii_case(".git") {
-        return false;
-    }
-
-    if file_name.eq_ignore_ascii_case("node_modules") {
-        return false;
-    }
-
-    // Always show directories
-    if path.is_dir() {
-        return true;
-    }
-
-    // For files, only show .tt files
-    if path.is_file() {
-        return file_name.ends_with(".tt");
-    }
-
-    false
-}
-
 //
 // File Viewer
 //
 
-pub fn interpolate_file(content: &str) -> String {
-    let repo_config = get_repo_config();
-
-    match render_template_with_handlebars(&content, &repo_config) {
+pub fn interpolate_file(content: &str, renderer: &Renderer) -> String {
+    match renderer.render(content) {
         Ok(interpolation) => interpolation,
         _ => content.to_string(),
     }
@@ -480,21 +2845,17 @@ pub fn highlight_file(
     // Determine the syntax based on file extension or name
     let syntax = if let Some(syntax) = get_syntax_for_special_file(path, ss) {
         syntax
-    } else if path.extension().and_then(|e| e.to_str()) == Some("tt") {
+    } else if is_template_file_name(&path.to_string_lossy()) {
         // For .tt files, strip the .tt and get syntax from the underlying extension
         let path_str = path.to_string_lossy();
-        if let Some(stripped) = path_str.strip_suffix(".tt") {
-            let underlying_path = Path::new::<str>(stripped.as_ref());
-            // Use find_syntax_by_extension which is safer (doesn't do IO)
-            if let Some(ext) = underlying_path.extension().and_then(|e| e.to_str()) {
-                ss.find_syntax_by_extension(ext)
-                    .unwrap_or_else(|| ss.find_syntax_plain_text())
-            } else {
-                ss.find_syntax_plain_text()
-            }
+        let stripped = strip_template_suffix(&path_str).unwrap_or(&path_str);
+        let underlying_path = Path::new(stripped);
+        // Use find_syntax_by_extension which is safer (doesn't do IO)
+        if let Some(ext) = underlying_path.extension().and_then(|e| e.to_str()) {
+            ss.find_syntax_by_extension(ext)
+                .unwrap_or_else(|| ss.find_syntax_plain_text())
         } else {
-            // if the file path string doesn't have the .tt extension
-            ss.find_syntax_plain_text() // wait, I will not get here ever?
+            ss.find_syntax_plain_text()
         }
     } else {
         // For non-.tt files, use the extension directly
@@ -541,7 +2902,7 @@ fn get_syntax_for_special_file<'a>(
     // Handle files without extensions by name
     if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
         // Strip .tt if present to get the actual filename
-        let name = file_name.strip_suffix(".tt").unwrap_or(file_name);
+        let name = strip_template_suffix(file_name).unwrap_or(file_name);
 
         match name.to_lowercase().as_str() {
             "dockerfile" => {
@@ -562,3 +2923,176 @@ fn get_syntax_for_special_file<'a>(
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, template_rendering::ContextOptions};
+
+    /// An app over a templates folder in `dir` holding one template with one
+    /// file, as the TUI starts out.
+    fn app(dir: &Path) -> App {
+        fs::create_dir_all(dir.join("web")).unwrap();
+        fs::write(dir.join("web/README.md.tt"), "# {{name}}\n").unwrap();
+        let mut config = Config::new("demo");
+        config.project.github_user = Some("me".to_string());
+        let options = ContextOptions {
+            new_project: true,
+            ..Default::default()
+        };
+        App::new(
+            dir,
+            Renderer::new(&config, &options).unwrap(),
+            ImportOptions::default(),
+            false,
+            "",
+            Keymap::default(),
+            Palette::default(),
+        )
+        .unwrap()
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Action {
+        let ss = Arc::new(SyntaxSet::default());
+        let theme = Arc::new(Theme::default());
+        app.handle_key(KeyEvent::from(code), 40, &ss, &theme)
+            .unwrap()
+    }
+
+    fn form() -> VariableForm {
+        VariableForm {
+            fields: vec![FormField {
+                name: "service".to_string(),
+                value: String::new(),
+                initial: None,
+                variable: None,
+                error: None,
+            }],
+            focus: 0,
+            sample: None,
+        }
+    }
+
+    #[test]
+    fn browsing_opens_other_modes_and_quits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+
+        press(&mut app, KeyCode::Char('?'));
+        assert!(matches!(app.mode, AppMode::Help));
+
+        app.mode = AppMode::Browsing;
+        press(&mut app, KeyCode::Char('/'));
+        assert!(matches!(app.mode, AppMode::Filtering));
+
+        app.mode = AppMode::Browsing;
+        press(&mut app, KeyCode::Char('b'));
+        assert!(matches!(app.mode, AppMode::Basket { selected: 0 }));
+
+        app.mode = AppMode::Browsing;
+        assert!(matches!(press(&mut app, KeyCode::Char('q')), Action::Quit));
+    }
+
+    #[test]
+    fn filtering_types_into_the_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+        app.mode = AppMode::Filtering;
+
+        // Keys bound to actions while browsing are typed here instead
+        for c in ['q', 'r', 'e'] {
+            assert!(matches!(
+                press(&mut app, KeyCode::Char(c)),
+                Action::Continue
+            ));
+        }
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(app.filter, "qr");
+        assert!(matches!(app.mode, AppMode::Filtering));
+
+        press(&mut app, KeyCode::Enter);
+        assert!(matches!(app.mode, AppMode::Browsing));
+        assert_eq!(app.filter, "qr");
+
+        app.mode = AppMode::Filtering;
+        press(&mut app, KeyCode::Esc);
+        assert!(matches!(app.mode, AppMode::Browsing));
+        assert!(app.filter.is_empty());
+    }
+
+    #[test]
+    fn confirming_goes_back_to_the_basket() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+        app.mode = app.confirming();
+
+        press(&mut app, KeyCode::Char('j'));
+        assert!(matches!(app.mode, AppMode::Confirming { .. }));
+
+        press(&mut app, KeyCode::Char('n'));
+        assert!(matches!(app.mode, AppMode::Basket { selected: 0 }));
+    }
+
+    #[test]
+    fn help_and_error_are_dismissed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+
+        app.mode = AppMode::Help;
+        press(&mut app, KeyCode::Char('j'));
+        assert!(matches!(app.mode, AppMode::Help));
+        press(&mut app, KeyCode::Esc);
+        assert!(matches!(app.mode, AppMode::Browsing));
+
+        app.mode = AppMode::Error {
+            message: "boom".to_string(),
+        };
+        assert!(matches!(
+            press(&mut app, KeyCode::Char('x')),
+            Action::Continue
+        ));
+        assert!(matches!(app.mode, AppMode::Error { .. }));
+        press(&mut app, KeyCode::Enter);
+        assert!(matches!(app.mode, AppMode::Browsing));
+    }
+
+    #[test]
+    fn prompting_edits_the_focused_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path());
+        app.mode = AppMode::Prompting(form());
+
+        for c in ['a', 'p', 'i', 'x'] {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Backspace);
+        let AppMode::Prompting(form) = &app.mode else {
+            panic!("left the form");
+        };
+        assert_eq!(form.fields[0].value, "api");
+
+        press(&mut app, KeyCode::Esc);
+        assert!(matches!(app.mode, AppMode::Browsing));
+    }
+
+    #[test]
+    fn previews_rendered_with_old_values_are_dropped() {
+        let highlighted = Highlighted {
+            rendered: vec![Line::raw("# demo")],
+            raw: vec![Line::raw("# {{name}}")],
+        };
+        let path = PathBuf::from("web/README.md.tt");
+        let modified = SystemTime::UNIX_EPOCH;
+        let mut previews = PreviewCache::default();
+        previews.insert(path.clone(), modified, 0, highlighted.clone());
+        assert!(previews.get(&path, modified).is_some());
+
+        previews.clear();
+        assert!(previews.get(&path, modified).is_none());
+        // Still being highlighted with the values from before
+        previews.insert(path.clone(), modified, 0, highlighted.clone());
+        assert!(previews.get(&path, modified).is_none());
+        previews.insert(path.clone(), modified, 1, highlighted);
+        assert!(previews.get(&path, modified).is_some());
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    case, clean_path,
+    logging::warn_once,
+    manifest::{FileRule, Manifest, Variable, VariableKind, is_truthy},
+    template_rendering::Engine,
+    templates::template_root_of,
+};
+
+/// The settings of a cargo-generate template, kept at the top of its folder.
+pub const CARGO_GENERATE_FILE: &str = "cargo-generate.toml";
+
+/// Paths of a cargo-generate template that are never generated, one a line.
+pub const GENIGNORE_FILE: &str = ".genignore";
+
+/// A `cargo-generate.toml`, as far as dropkick understands it.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    #[serde(default)]
+    template: TemplateSettings,
+    /// Kept as a table so the placeholders are asked for in the file's order
+    #[serde(default)]
+    placeholders: toml::Table,
+    /// Settings that apply when a Rhai condition, the key, holds
+    #[serde(default)]
+    conditional: toml::Table,
+    #[serde(default)]
+    hooks: Hooks,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TemplateSettings {
+    /// Paths that aren't generated
+    #[serde(default)]
+    ignore: Vec<String>,
+    /// Paths copied without rendering
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// The only paths rendered, which dropkick can't express
+    #[serde(default)]
+    include: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Hooks {
+    #[serde(default)]
+    pre: Vec<String>,
+    #[serde(default)]
+    post: Vec<String>,
+}
+
+/// e.g. `license = { type = "string", prompt = "License?", choices = ["MIT", "Apache-2.0"] }`
+#[derive(Debug, Deserialize)]
+struct Placeholder {
+    #[serde(rename = "type")]
+    kind: String,
+    prompt: Option<String>,
+    default: Option<toml::Value>,
+    choices: Option<Vec<String>>,
+    regex: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Conditional {
+    #[serde(default)]
+    ignore: Vec<String>,
+    #[serde(default)]
+    placeholders: toml::Table,
+}
+
+/// Whether template folder `root` is a cargo-generate template: it has a
+/// `cargo-generate.toml`, or a `Cargo.toml` (not `Cargo.toml.tt`) that uses
+/// cargo-generate's placeholders.
+pub fn is_cargo_generate(root: &Path) -> bool {
+    if root.join(CARGO_GENERATE_FILE).is_file() {
+        return true;
+    }
+    let placeholder = Regex::new(r"\{\{\s*(project-name|crate_name)\b").unwrap();
+    fs::read_to_string(root.join("Cargo.toml")).is_ok_and(|raw| placeholder.is_match(&raw))
+}
+
+/// Whether `path` is one of a cargo-generate template's files, every one of
+/// which is rendered, `.tt` suffix or not.  Its settings, hooks and ignored
+/// paths aren't.
+pub fn is_template_file(path: &Path) -> bool {
+    let Some(root) = template_root_of(path) else {
+        return false;
+    };
+    if !is_cargo_generate(&root) {
+        return false;
+    }
+    let Ok(relative) = path.strip_prefix(&root) else {
+        return false;
+    };
+    if [CARGO_GENERATE_FILE, GENIGNORE_FILE]
+        .iter()
+        .any(|name| relative == Path::new(name))
+        || relative.starts_with("target")
+    {
+        return false;
+    }
+
+    let settings = load(&root).unwrap_or_default();
+    let mut ignored = settings.template.ignore;
+    ignored.extend(settings.hooks.pre);
+    ignored.extend(settings.hooks.post);
+    if let Ok(raw) = fs::read_to_string(root.join(GENIGNORE_FILE)) {
+        ignored.extend(
+            raw.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    !ignored.iter().any(|entry| matches(entry, relative))
+}
+
+/// Whether an `ignore` or `exclude` entry, a path, a folder or `*.ext`,
+/// covers `relative`.
+fn matches(entry: &str, relative: &Path) -> bool {
+    match entry.strip_prefix("*.") {
+        Some(ext) => relative
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(ext)),
+        None => relative.starts_with(entry.trim_matches('/')),
+    }
+}
+
+fn load(root: &Path) -> Result<Settings, Box<dyn Error>> {
+    let path = root.join(CARGO_GENERATE_FILE);
+    let Ok(raw) = fs::read_to_string(&path) else {
+        return Ok(Settings::default());
+    };
+    toml::from_str(&raw)
+        .map_err(|e| format!("unable to parse {}: {}", clean_path(&path).display(), e).into())
+}
+
+/// The settings dropkick reads from cargo-generate template `root`: the
+/// `cargo-generate` engine, a variable for each placeholder, `exclude` as
+/// files copied as they are, and conditional `ignore`s as `files` rules.
+pub fn manifest(root: &Path) -> Result<Manifest, Box<dyn Error>> {
+    let settings = load(root)?;
+    let display = clean_path(&root.join(CARGO_GENERATE_FILE));
+
+    let mut variables = Vec::new();
+    for (name, value) in settings.placeholders {
+        let placeholder: Placeholder = value.try_into().map_err(|e| {
+            format!(
+                "invalid placeholder '{}' in {}: {}",
+                name,
+                display.display(),
+                e
+            )
+        })?;
+        variables.push(variable(name, placeholder));
+    }
+
+    let mut files = Vec::new();
+    for (condition, value) in settings.conditional {
+        let conditional: Conditional = value.try_into().map_err(|e| {
+            format!(
+                "invalid conditional '{}' in {}: {}",
+                condition,
+                display.display(),
+                e
+            )
+        })?;
+        if !conditional.placeholders.is_empty() {
+            warn_once(format!(
+                "Not asking for the placeholders under conditional '{}' in {}, conditional placeholders aren't supported",
+                condition,
+                display.display()
+            ));
+        }
+        let Some(condition) = liquid_condition(&condition) else {
+            warn_once(format!(
+                "Generating the files conditional '{}' in {} ignores anyway, only ==, !=, && and || can be translated",
+                condition,
+                display.display()
+            ));
+            continue;
+        };
+        for entry in &conditional.ignore {
+            for path in expand(root, entry) {
+                files.push(FileRule {
+                    path,
+                    when: Some(format!(
+                        "{{% if {} %}}false{{% else %}}true{{% endif %}}",
+                        condition
+                    )),
+                    mode: None,
+                });
+            }
+        }
+    }
+
+    if !settings.template.include.is_empty() {
+        warn_once(format!(
+            "Rendering every file of {} rather than only those under `include`, which isn't supported",
+            clean_path(root).display()
+        ));
+    }
+    if !settings.hooks.pre.is_empty() || !settings.hooks.post.is_empty() {
+        warn_once(format!(
+            "Not running the Rhai hooks in {}, they aren't supported",
+            display.display()
+        ));
+    }
+
+    let binary = settings
+        .template
+        .exclude
+        .iter()
+        .flat_map(|entry| match entry.starts_with("*.") {
+            true => vec![entry.clone()],
+            false => expand(root, entry)
+                .into_iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+        })
+        .collect();
+
+    Ok(Manifest {
+        engine: Some(Engine::CargoGenerate),
+        variables,
+        files,
+        binary,
+        ..Manifest::default()
+    })
+}
+
+fn variable(name: String, placeholder: Placeholder) -> Variable {
+    let kind = match placeholder.kind.as_str() {
+        "bool" => VariableKind::Bool,
+        _ => VariableKind::String,
+    };
+    let default = placeholder.default.and_then(|default| match default {
+        toml::Value::String(s) => Some(serde_yaml::Value::from(s)),
+        toml::Value::Boolean(b) => Some(serde_yaml::Value::from(b)),
+        toml::Value::Integer(i) => Some(serde_yaml::Value::from(i.to_string())),
+        _ => None,
+    });
+    let question = placeholder.prompt.unwrap_or_else(|| name.clone());
+
+    match placeholder.choices.filter(|choices| !choices.is_empty()) {
+        Some(choices) => {
+            let alternatives: Vec<String> = choices.iter().map(|c| regex::escape(c)).collect();
+            Variable {
+                default: default.or_else(|| Some(serde_yaml::Value::from(choices[0].as_str()))),
+                prompt: Some(format!("{} ({})", question, choices.join(", "))),
+                validation: Some(format!("^(?:{})$", alternatives.join("|"))),
+                name,
+                kind,
+            }
+        }
+        None => Variable {
+            default,
+            prompt: Some(question),
+            validation: placeholder.regex,
+            name,
+            kind,
+        },
+    }
+}
+
+/// A path from the settings as the files it covers, relative to `root`.
+fn expand(root: &Path, entry: &str) -> Vec<PathBuf> {
+    let entry = entry.trim_matches('/');
+    let path = root.join(entry);
+    if !path.is_dir() {
+        return vec![PathBuf::from(entry)];
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![path];
+    while let Some(dir) = dirs.pop() {
+        for child in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let child = child.path();
+            if child.is_dir() {
+                dirs.push(child);
+            } else if let Ok(relative) = child.strip_prefix(root) {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+    files
+}
+
+/// A Rhai condition such as `crate_type == "lib" && !use_serde` as a Liquid
+/// one, where that's just a matter of spelling.
+fn liquid_condition(condition: &str) -> Option<String> {
+    let condition = condition.replace("&&", " and ").replace("||", " or ");
+    if condition.replace("!=", "").contains(['!', '(']) {
+        return None;
+    }
+    Some(condition.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// The context with cargo-generate's built-in placeholders added, derived
+/// from dropkick's fields: `project-name`, `crate_name`, `crate_type`,
+/// `authors`, `username`, `os-arch`, `is_init` and `within_cargo_project`.
+pub fn with_builtins(context: &Value) -> Value {
+    let mut context = context.clone();
+    let Value::Object(map) = &mut context else {
+        return context;
+    };
+    let text = |key: &str| {
+        map.get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let name = text("name");
+    let crate_type = if map
+        .get("bin")
+        .is_some_and(|bin| is_truthy(&bin.to_string()))
+    {
+        "bin"
+    } else {
+        "lib"
+    };
+    let builtins = [
+        ("project-name", Value::from(case::kebab(&name))),
+        ("crate_name", Value::from(case::snake(&name))),
+        ("crate_type", Value::from(crate_type)),
+        (
+            "authors",
+            Value::from(format!("{} <{}>", text("author"), text("email"))),
+        ),
+        ("username", Value::from(text("github_user"))),
+        (
+            "os-arch",
+            Value::from(format!(
+                "{}-{}",
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )),
+        ),
+        ("is_init", Value::from(false)),
+        ("within_cargo_project", Value::from(false)),
+    ];
+    for (key, value) in builtins {
+        map.entry(key).or_insert(value);
+    }
+    context
+}
@@ -0,0 +1,179 @@
+use inflector::string::{pluralize::to_plural, singularize::to_singular};
+
+/// Splits a name into its words, whatever case it's written in: separators
+/// such as `-`, `_` and spaces end a word, as does a change from lower to
+/// upper case.  A run of capitals stays together as an acronym, so
+/// `HTTPServer` is `HTTP` and `Server`.
+pub fn words(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if c.is_uppercase() && !current.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if !prev.is_uppercase() || next_is_lower {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first.to_uppercase().collect::<String>() + &chars.as_str().to_lowercase(),
+    }
+}
+
+/// `my-project` to `MyProject`
+pub fn pascal(s: &str) -> String {
+    words(s).iter().map(|w| capitalize(w)).collect()
+}
+
+/// `my-project` to `myProject`
+pub fn camel(s: &str) -> String {
+    words(s)
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            if i == 0 {
+                w.to_lowercase()
+            } else {
+                capitalize(w)
+            }
+        })
+        .collect()
+}
+
+/// `MyProject` to `my_project`
+pub fn snake(s: &str) -> String {
+    join_lowercase(s, "_")
+}
+
+/// `MyProject` to `my-project`
+pub fn kebab(s: &str) -> String {
+    join_lowercase(s, "-")
+}
+
+/// `my-project` to `MY_PROJECT`
+pub fn scream(s: &str) -> String {
+    snake(s).to_uppercase()
+}
+
+/// `my-project` to `MY-PROJECT`
+pub fn scream_kebab(s: &str) -> String {
+    kebab(s).to_uppercase()
+}
+
+/// `my_project` to `My Project`
+pub fn title(s: &str) -> String {
+    words(s)
+        .iter()
+        .map(|w| capitalize(w))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Irregular nouns Inflector's rules get wrong, singular then plural.
+const IRREGULAR: &[(&str, &str)] = &[
+    ("person", "people"),
+    ("child", "children"),
+    ("man", "men"),
+    ("woman", "women"),
+    ("cactus", "cacti"),
+];
+
+/// `blog_post` to `blog_posts`, `Person` to `People`: the last word is
+/// pluralized and the rest kept as it is.
+pub fn plural(s: &str) -> String {
+    inflect_last_word(s, |word| {
+        match IRREGULAR
+            .iter()
+            .find(|(singular, plural)| word == *singular || word == *plural)
+        {
+            Some((_, plural)) => plural.to_string(),
+            None => to_plural(word),
+        }
+    })
+}
+
+/// `blog_posts` to `blog_post`, `People` to `Person`
+pub fn singular(s: &str) -> String {
+    inflect_last_word(s, |word| {
+        match IRREGULAR
+            .iter()
+            .find(|(singular, plural)| word == *singular || word == *plural)
+        {
+            Some((singular, _)) => singular.to_string(),
+            None => to_singular(word),
+        }
+    })
+}
+
+/// `author_id` to `Author`, `BlogPost` to `Blog post`: words in lower case
+/// but the first, with an `id` at the end dropped, for labels and messages.
+pub fn humanize(s: &str) -> String {
+    let mut words = words(s);
+    if words.len() > 1 && words.last().is_some_and(|w| w.eq_ignore_ascii_case("id")) {
+        words.pop();
+    }
+    let sentence = words
+        .iter()
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ");
+    capitalize_first(&sentence)
+}
+
+/// Replaces the last word of `s` with `inflect` applied to it in lower case,
+/// put back in the word's case: `BlogPost` stays `BlogPosts`, `USER` becomes
+/// `USERS`.
+fn inflect_last_word(s: &str, inflect: impl Fn(&str) -> String) -> String {
+    let Some(word) = words(s).pop() else {
+        return s.to_string();
+    };
+    let Some(start) = s.rfind(&word) else {
+        return s.to_string();
+    };
+    let inflected = inflect(&word.to_lowercase());
+    let inflected = if word.chars().count() > 1 && word.chars().all(|c| !c.is_lowercase()) {
+        inflected.to_uppercase()
+    } else if word.starts_with(char::is_uppercase) {
+        capitalize_first(&inflected)
+    } else {
+        inflected
+    };
+    format!("{}{}{}", &s[..start], inflected, &s[start + word.len()..])
+}
+
+fn capitalize_first(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+    }
+}
+
+fn join_lowercase(s: &str, separator: &str) -> String {
+    words(s)
+        .iter()
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
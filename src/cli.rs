@@ -0,0 +1,389 @@
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+use crate::{
+    expand_tilde,
+    import::ConflictPolicy,
+    template_rendering::{ContextOptions, Engine},
+};
+
+/// Browse and import files from your local template library.  Running without
+/// a subcommand opens the interactive TUI.
+///
+/// Templates and logs live in $XDG_DATA_HOME/dropkick (~/.local/share/dropkick
+/// by default), or in DROPKICK_TEMPLATES for templates, and config.yaml in
+/// $XDG_CONFIG_HOME/dropkick (~/.config/dropkick).  Where an old ~/.dropkick
+/// couldn't be moved there, and on Windows unless both variables are set,
+/// ~/.dropkick is used instead.
+#[derive(Debug, Parser)]
+#[command(name = "dropkick", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// JSON or YAML file of extra template variables.  Built-in fields take
+    /// precedence over values from this file.
+    #[arg(long, global = true, value_parser = parse_path)]
+    pub vars_file: Option<PathBuf>,
+
+    /// Merge nested maps from the vars file key by key instead of replacing them
+    #[arg(long, global = true)]
+    pub deep_merge: bool,
+
+    /// Write the fully merged render context as JSON to stderr, or to
+    /// `--dump-context=FILE`
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-",
+        value_parser = parse_path
+    )]
+    pub dump_context: Option<PathBuf>,
+
+    /// Tag for `full_image`, instead of the current commit's short sha
+    #[arg(long, global = true)]
+    pub image_tag: Option<String>,
+
+    /// Show debug output, or trace output too with `-vv`
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only show errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Also write debug output (trace with `-vv`) to `--log-file=FILE`, or
+    /// to a new file under `$XDG_DATA_HOME/dropkick/logs/`
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_parser = parse_path
+    )]
+    pub log_file: Option<PathBuf>,
+
+    /// Keep each template's top-level folder in the destination path instead
+    /// of importing its contents straight into the output directory
+    #[arg(long, global = true)]
+    pub keep_root: bool,
+
+    /// Print what an import would create, overwrite or skip without writing
+    /// anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Don't run a template's pre_gen and post_gen hook scripts
+    #[arg(long, global = true)]
+    pub no_hooks: bool,
+
+    /// Let templates run commands with the sh helper, e.g. `{{sh "git
+    /// rev-parse --short HEAD"}}`, as `allow_exec: true` in config.yaml does
+    #[arg(long, global = true)]
+    pub allow_exec: bool,
+
+    /// Follow symlinks to directories or files outside the template
+    #[arg(long, global = true)]
+    pub follow_symlinks: bool,
+
+    /// Directory the TUI imports into, created if it doesn't exist yet (the
+    /// working directory by default).  Commands that write files take their
+    /// own `--output` after the command's name.
+    #[arg(
+        long = "output",
+        visible_alias = "output-dir",
+        value_name = "DIR",
+        value_parser = parse_path
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    /// Drive the TUI with keys read from FILE (e.g. `Down Space e y`) instead
+    /// of the keyboard, for reproducible demos and bug reports
+    #[arg(long, value_name = "FILE", value_parser = parse_path)]
+    pub replay: Option<PathBuf>,
+
+    /// Strip PREFIX (e.g. `dk-`) from the project's name for
+    /// `unprefixed_name` and `unprefixed_pascal`, instead of `.dropkickrc`'s
+    /// `project.prefix`
+    #[arg(long, global = true, value_name = "PREFIX")]
+    pub prefix: Option<String>,
+
+    /// Turn on the `bin` flag, for templates with files only an executable
+    /// project needs
+    #[arg(long, global = true)]
+    pub bin: bool,
+
+    /// Turn on the `test` flag, for templates with optional test scaffolding
+    #[arg(long, global = true)]
+    pub test: bool,
+
+    /// Template syntax to render with, instead of what each template's
+    /// dropkick.yaml says (handlebars by default)
+    #[arg(long, value_enum, global = true)]
+    pub engine: Option<Engine>,
+
+    /// What to do when a rendered file already exists locally [default: skip,
+    /// or `on_conflict` in $XDG_CONFIG_HOME/dropkick/config.yaml]
+    #[arg(long, value_enum, global = true)]
+    pub on_conflict: Option<ConflictPolicy>,
+}
+
+impl Cli {
+    pub fn context_options(&self) -> ContextOptions {
+        ContextOptions {
+            vars_file: self.vars_file.clone(),
+            deep_merge: self.deep_merge,
+            dump_context: self.dump_context.clone(),
+            image_tag: self.image_tag.clone(),
+            output_dir: PathBuf::new(),
+            new_project: false,
+            engine: self.engine,
+            prefix: self.prefix.clone(),
+            bin: self.bin,
+            test: self.test,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List every `config[:...]` placeholder a template references, or
+    /// without a template, every value templates get and where it came from
+    Vars {
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates
+        #[arg(long)]
+        template: Option<String>,
+        /// How to print the resolved values
+        #[arg(long, value_enum, default_value_t, conflicts_with = "template")]
+        format: OutputFormat,
+    },
+    /// Generate a whole new project directory from a template
+    New {
+        /// Directory to create; its last component becomes the project name
+        #[arg(value_parser = parse_path)]
+        name: PathBuf,
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates.  Repeat
+        /// it to layer templates, later ones overriding files of earlier ones.
+        /// Defaults to `default_template` in $XDG_CONFIG_HOME/dropkick/config.yaml
+        #[arg(long)]
+        template: Vec<String>,
+    },
+    /// Install a template by cloning its git repository into $XDG_DATA_HOME/dropkick/templates
+    Install {
+        /// `gh:user/repo`, an https or ssh git URL, or the name of a template
+        /// in the template index
+        source: String,
+        /// Folder name to install the template as, the repository name by default
+        #[arg(long)]
+        name: Option<String>,
+        /// Check out this branch instead of the repository's default
+        #[arg(long, conflicts_with = "tag")]
+        branch: Option<String>,
+        /// Check out this tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Search the template index for community templates to install
+    Search {
+        /// Words to look for in template names, descriptions and tags;
+        /// every template is listed when not given
+        term: Option<String>,
+    },
+    /// Pull upstream changes into installed templates and record their
+    /// commits in $XDG_DATA_HOME/dropkick/templates.lock
+    Update {
+        /// Template to update, every installed template when not given
+        template: Option<String>,
+    },
+    /// List the available templates, or the files in one template
+    List {
+        /// Template to list the files of
+        template: Option<String>,
+    },
+    /// Import files from a template into the working directory, without the TUI
+    Import {
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates
+        template: String,
+        /// Files within the template to import, without the `.tt` suffix.
+        /// Every file is imported when none are given.
+        files: Vec<String>,
+        /// Directory the rendered files are written into
+        #[arg(long, visible_alias = "output", default_value = ".", value_parser = parse_path)]
+        output_dir: PathBuf,
+    },
+    /// Render a template file, or stdin, to stdout with the current
+    /// project's values, e.g. in a pipeline or a Makefile
+    Render {
+        /// Path of the template file, `-` or none for stdin
+        #[arg(value_parser = parse_path)]
+        file: Option<PathBuf>,
+        /// Write the rendered file here instead of to stdout
+        #[arg(short, long, value_parser = parse_path)]
+        output: Option<PathBuf>,
+    },
+    /// Write a commented .dropkickrc for the project in the current
+    /// directory, asking for its name, prefix, flags and extra variables
+    Init {
+        /// Replace an existing .dropkickrc
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check a template for syntax errors, unknown variables, unbalanced
+    /// blocks, unsafe paths and files missing the .tt suffix
+    Lint {
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates
+        #[arg(required_unless_present = "template_flag")]
+        template: Option<String>,
+        /// The same as TEMPLATE, which is how lint used to take it
+        #[arg(
+            long = "template",
+            id = "template_flag",
+            value_name = "TEMPLATE",
+            conflicts_with = "template"
+        )]
+        template_flag: Option<String>,
+    },
+    /// Render a template with each case in its tests/ folder and compare the
+    /// result with the files the case expects
+    Test {
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates
+        template: String,
+        /// Replace each case's expected files with what the template renders now
+        #[arg(long)]
+        update: bool,
+    },
+    /// Render every file in a template into a directory, without the TUI
+    Scaffold(ScaffoldArgs),
+    /// Render a single file from a template, e.g. just the CI config
+    RenderFile {
+        /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates
+        #[arg(long)]
+        template: String,
+        /// Path of the file within the template, without the `.tt` suffix
+        #[arg(long)]
+        file: String,
+        /// Print the rendered file instead of writing it
+        #[arg(long)]
+        stdout: bool,
+        /// Directory the rendered file is written into
+        #[arg(long, visible_alias = "output", default_value = ".", value_parser = parse_path)]
+        output_dir: PathBuf,
+    },
+    /// Render a template checked out by other means, e.g. with `git clone`,
+    /// in place: `.tt` files are replaced by what they render to and
+    /// placeholders in file and folder names are filled in
+    RenderDir {
+        /// The template's folder
+        #[arg(value_parser = parse_path)]
+        dir: PathBuf,
+    },
+    /// Check dropkick's own settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Create and manage your own templates
+    Template {
+        #[command(subcommand)]
+        command: TemplateCommand,
+    },
+    /// Turn an existing project into a template under $XDG_DATA_HOME/dropkick/templates,
+    /// with its name, author, repo URLs and so on replaced by placeholders
+    Templatize {
+        /// Project directory
+        #[arg(value_parser = parse_path)]
+        dir: PathBuf,
+        /// Folder name for the template, the project directory's by default
+        #[arg(long)]
+        name: Option<String>,
+        /// Name the project goes by in its files, its `.dropkickrc` name or
+        /// directory's name by default
+        #[arg(long)]
+        project_name: Option<String>,
+    },
+    /// Remove the files the last import created, unless they've been changed
+    /// since
+    Undo {
+        /// Directory the import wrote into, e.g. the one `dropkick new` created
+        #[arg(long, default_value = ".", value_parser = parse_path)]
+        output_dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Check .dropkickrc and $XDG_CONFIG_HOME/dropkick/config.yaml for YAML errors, values
+    /// of the wrong type and unknown keys
+    Validate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TemplateCommand {
+    /// Start a template under $XDG_DATA_HOME/dropkick/templates, from a skeleton or an
+    /// existing project
+    New {
+        /// Folder name for the template
+        name: String,
+        /// Project to turn into the template, like `dropkick templatize`
+        #[arg(long, value_parser = parse_path)]
+        from: Option<PathBuf>,
+        /// Name the project goes by in its files, its `.dropkickrc` name or
+        /// directory's name by default
+        #[arg(long, requires = "from")]
+        project_name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("reports").args(["count_only", "stats"]).multiple(true)))]
+pub struct ScaffoldArgs {
+    /// Name of the template folder under $XDG_DATA_HOME/dropkick/templates.  Repeat it to
+    /// layer templates, later ones overriding files of earlier ones.  Defaults
+    /// to `default_template` in $XDG_CONFIG_HOME/dropkick/config.yaml
+    #[arg(long)]
+    pub template: Vec<String>,
+    /// Directory the rendered files are written into
+    #[arg(long, visible_alias = "output", default_value = ".", value_parser = parse_path)]
+    pub output_dir: PathBuf,
+    /// Write without asking for confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+    /// Never prompt on the terminal; --yes is then required to write files
+    #[arg(long)]
+    pub no_input: bool,
+    /// Render in memory and only print how many files would be created,
+    /// updated, skipped and errored, tab separated.  Nothing is written.
+    #[arg(long)]
+    pub count_only: bool,
+    /// Print a breakdown of time spent on config, rendering and writing to
+    /// stderr when done
+    #[arg(long)]
+    pub stats: bool,
+    /// Print the --count-only summary and --stats breakdown as JSON
+    #[arg(long, requires = "reports")]
+    pub json: bool,
+}
+
+/// Value parser for every path argument, so `~` works even where the shell
+/// didn't expand it.
+fn parse_path(value: &str) -> Result<PathBuf, Infallible> {
+    Ok(expand_tilde(Path::new(value)))
+}
@@ -0,0 +1,1704 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    error::Error,
+    fmt, fs,
+    io::{self, IsTerminal, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    clean_path,
+    cli::{OutputFormat, ScaffoldArgs},
+    config::{
+        Config, ConfigFormat, REPO_CONFIG_FILES, UserConfig, find_repo_config, get_repo_config,
+        parse_checked, parse_config,
+    },
+    cookiecutter::is_project_dir,
+    get_templates_path,
+    hooks::{self, Hook},
+    import::{
+        ImportCounts, ImportOptions, PlannedAction, compute_destination, file_mode,
+        find_destination_collisions, import_template_files, layer_templates, merge_into_file,
+        merge_target, plan_template_file, render_path, skip_reason,
+    },
+    interpolation_config::InterpolationConfig,
+    manifest::{
+        Manifest, Variable, VariableKind, apply_manifests, ask_variable, is_truthy,
+        template_lineage,
+    },
+    registry::Index,
+    remote::{self, Lockfile, TemplateSource},
+    skeleton, snapshot,
+    snapshot::is_fixture_file,
+    stats::{self, Phase, Stats},
+    template_rendering::{
+        ContextOptions, Engine, Renderer, TEMPLATE_DATA_DIR, loop_list, referenced_config_keys,
+        referenced_variables, resolve_context, unbalanced_blocks,
+    },
+    templates::{
+        MergeDirective, TEMPLATE_SETTINGS, declared_variables, is_binary, is_ignored,
+        split_frontmatter, strip_template_suffix, template_files, template_names, template_root,
+        template_root_of, untemplated_files,
+    },
+    transaction,
+};
+
+/// Generates a new project in directory `dir` from every file of a template,
+/// naming the project after the directory.  With several templates, later
+/// ones override the files of earlier ones.
+pub fn new(
+    dir: &Path,
+    templates: &[String],
+    follow_symlinks: bool,
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("'{}' isn't a usable project name", dir.display()))?;
+
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(format!("'{}' already exists and isn't empty", dir.display()).into());
+    }
+
+    let roots = template_roots(templates)?;
+    // The new project has no .dropkickrc yet, so its name is all there is
+    let mut renderer = Renderer::new(&Config::new(name), options)?;
+    apply_manifests(&roots, &mut renderer, true)?;
+    let files = layered_template_files(&roots, follow_symlinks, &renderer, import_options)?;
+    check_collisions(&files, &renderer, import_options)?;
+
+    info!(
+        "Creating {} from {}",
+        dir.display(),
+        describe_templates(templates)
+    );
+    run_hooks(&roots, Hook::PreGen, &renderer, import_options)?;
+    let report = import_template_files(&files, &renderer, import_options)?;
+    report.log_summary("Generated", files.len());
+    report.check_failures()?;
+    run_hooks(&roots, Hook::PostGen, &renderer, import_options)?;
+
+    Ok(())
+}
+
+/// Installs a template by cloning its repository under the templates path,
+/// pinned to `reference` (a branch or tag) when given.
+pub fn install(
+    source: &str,
+    name: Option<&str>,
+    reference: Option<&str>,
+    index: &str,
+) -> Result<(), Box<dyn Error>> {
+    // A plain name is looked up in the template index
+    let (source, default_name) = match TemplateSource::parse(source) {
+        Ok(parsed) => {
+            let name = parsed.name.clone();
+            (parsed, name)
+        }
+        Err(_) if !source.contains([':', '/', '\\']) => {
+            let entry = Index::load(index)?.find(source).cloned().ok_or_else(|| {
+                format!(
+                    "'{}' isn't a git URL or a template in the index, try dropkick search",
+                    source
+                )
+            })?;
+            (TemplateSource::parse(&entry.source)?, entry.name)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let name = name.unwrap_or(&default_name);
+
+    info!("Installing {} as '{}'", source.url, name);
+    let dest = install_source(&source, name, reference, false)?;
+    info!(
+        "Installed template '{}' in {}",
+        name,
+        clean_path(&dest).display()
+    );
+
+    Ok(())
+}
+
+/// Clones `source` as template `name` and records it in the lockfile,
+/// returning where it was installed.
+pub fn install_source(
+    source: &TemplateSource,
+    name: &str,
+    reference: Option<&str>,
+    quiet: bool,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let dest = new_template_dir(name)?;
+    remote::clone(&source.url, &dest, reference, quiet)?;
+
+    let mut lockfile = Lockfile::load()?;
+    lockfile.record(name, &dest, reference.map(String::from))?;
+    lockfile.save()?;
+
+    Ok(dest)
+}
+
+/// Lists the templates in the index matching `term`, or all of them, marking
+/// those already installed.
+pub fn search(term: Option<&str>, index: &str) -> Result<(), Box<dyn Error>> {
+    let index = Index::load(index)?;
+    let found = index.search(term.unwrap_or_default());
+    if found.is_empty() {
+        println!(
+            "No templates in the index match '{}'.",
+            term.unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    let installed = template_names().unwrap_or_default();
+    let width = found
+        .iter()
+        .map(|entry| entry.name.len())
+        .max()
+        .unwrap_or(0);
+    for entry in &found {
+        let marker = if installed.contains(&entry.name) {
+            " [installed]"
+        } else {
+            ""
+        };
+        println!(
+            "{:width$}  {}{}",
+            entry.name,
+            entry.description,
+            marker,
+            width = width
+        );
+    }
+    println!(
+        "\n{} template(s), install one with dropkick install <name>",
+        found.len()
+    );
+
+    Ok(())
+}
+
+/// Starts template `name` under the templates path from a skeleton to fill in.
+pub fn new_template(name: &str) -> Result<(), Box<dyn Error>> {
+    let dest = new_template_dir(name)?;
+    skeleton::create_skeleton(&dest)?;
+    info!(
+        "Created template '{}' in {}, with an example file to start from",
+        name,
+        clean_path(&dest).display()
+    );
+
+    Ok(())
+}
+
+/// Interviews the user for the project in the current directory and writes
+/// its `.dropkickrc`, with the settings left out commented for reference.
+pub fn init(force: bool) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(".dropkickrc");
+    if let Some(existing) = find_repo_config()
+        && !force
+    {
+        return Err(format!(
+            "{} already exists, pass --force to replace it",
+            existing.display()
+        )
+        .into());
+    }
+    if !io::stdin().is_terminal() {
+        return Err("dropkick init asks its questions on a terminal, and there's none".into());
+    }
+
+    let dir_name = env::current_dir()?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let question =
+        |name: &str, prompt: &str, kind: VariableKind, default: serde_yaml::Value| Variable {
+            name: name.to_string(),
+            kind,
+            default: Some(default),
+            prompt: Some(prompt.to_string()),
+            validation: None,
+        };
+
+    let name = ask_variable(&question(
+        "name",
+        "Project name",
+        VariableKind::String,
+        dir_name.into(),
+    ))?;
+    let prefix = ask_variable(&question(
+        "prefix",
+        "Prefix stripped from the name for unprefixed_name (blank for none)",
+        VariableKind::String,
+        "".into(),
+    ))?;
+    let github_user = ask_variable(&question(
+        "github_user",
+        "GitHub account owning the repo (blank for git config's)",
+        VariableKind::String,
+        "".into(),
+    ))?;
+    let bin = ask_variable(&question(
+        "bin",
+        "Does it build an executable?",
+        VariableKind::Bool,
+        false.into(),
+    ))?;
+    let test = ask_variable(&question(
+        "test",
+        "Does it want test scaffolding?",
+        VariableKind::Bool,
+        false.into(),
+    ))?;
+
+    let mut variables = Map::new();
+    let extra = Variable {
+        validation: Some(r"^(?:\w+=.*)?$".to_string()),
+        ..question(
+            "variable",
+            "Another template value as name=value (blank when done)",
+            VariableKind::String,
+            "".into(),
+        )
+    };
+    while let Value::String(answer) = ask_variable(&extra)?
+        && let Some((key, value)) = answer.split_once('=')
+    {
+        let value = match value.trim() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            value => Value::String(value.to_string()),
+        };
+        variables.insert(key.to_string(), value);
+    }
+
+    let scalar = |value: &Value| {
+        serde_yaml::to_string(value)
+            .map(|yaml| yaml.trim_end().to_string())
+            .unwrap_or_default()
+    };
+    let github_user = match &github_user {
+        Value::String(user) if !user.is_empty() => format!("github_user: {}", scalar(&github_user)),
+        _ => "# github_user: your-account".to_string(),
+    };
+    let variables = match variables.is_empty() {
+        true => "# variables:\n#   registry_domain: ghcr.io\n".to_string(),
+        false => {
+            let mut lines = "variables:\n".to_string();
+            for (key, value) in &variables {
+                lines.push_str(&format!("  {}: {}\n", key, scalar(value)));
+            }
+            lines
+        }
+    };
+    let contents = format!(
+        r#"# Settings dropkick reads when rendering templates in this repo.
+
+project:
+  # The project's name, in every case: {{{{name}}}}, {{{{pascal_name}}}} and so on
+  name: {name}
+  # Stripped from the start of the name for {{{{unprefixed_name}}}}, e.g. dk-
+  prefix: {prefix}
+  # GitHub account owning the repo, when it isn't git config's user.github
+  # or user.name
+  {github_user}
+  # Tag used in full_image instead of the current commit's short sha
+  # image_tag: latest
+  # Turns on [bin] files and {{{{#if bin}}}}
+  bin: {bin}
+  # Turns on [test] files
+  test: {test}
+
+# Values for this repo's templates, overriding the built-in fields
+{variables}
+# Legacy placeholder syntaxes rewritten into handlebars before rendering
+# rewrite_rules:
+#   - pattern: '%(\w+)%'
+#     replacement: '{{{{$1}}}}'
+"#,
+        name = scalar(&name),
+        prefix = scalar(&prefix),
+        bin = scalar(&bin),
+        test = scalar(&test),
+    );
+    // Whatever was typed, the file has to read back the same
+    parse_config(&contents).map_err(|e| format!("unable to write .dropkickrc: {}", e))?;
+
+    fs::write(path, contents)?;
+    info!("Wrote .dropkickrc");
+    Ok(())
+}
+
+/// Checks the repo's `.dropkickrc` (in whichever formats there are) and
+/// `$XDG_CONFIG_HOME/dropkick/config.yaml`, where they exist, reporting every error and
+/// unknown key rather than warning and going on.
+pub fn validate_config() -> Result<(), Box<dyn Error>> {
+    let repo_configs: Vec<PathBuf> = REPO_CONFIG_FILES
+        .iter()
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+        .collect();
+    if repo_configs.is_empty() {
+        info!(".dropkickrc: not found");
+    }
+    let user_config = UserConfig::path();
+    if !user_config.exists() {
+        info!("{}: not found", clean_path(&user_config).display());
+    }
+
+    let mut problems = 0;
+    for (i, path) in repo_configs.iter().chain([&user_config]).enumerate() {
+        let display = clean_path(path);
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("unable to read {}: {}", display.display(), e).into()),
+        };
+
+        let unknown = match *path == user_config {
+            true => {
+                parse_checked::<UserConfig>(&raw, ConfigFormat::Yaml).map(|(_, unknown)| unknown)
+            }
+            false => parse_checked::<Config>(&raw, ConfigFormat::detect(path, &raw))
+                .map(|(_, unknown)| unknown),
+        };
+        if i > 0 && *path != user_config {
+            warn!(
+                "{}: ignored, {} is used instead",
+                display.display(),
+                repo_configs[0].display()
+            );
+        }
+        match unknown {
+            Ok(unknown) if unknown.is_empty() => info!("{}: OK", display.display()),
+            Ok(unknown) => {
+                for key in unknown {
+                    error!("{}: {}", display.display(), key);
+                    problems += 1;
+                }
+            }
+            Err(e) => {
+                error!("{}: {}", display.display(), e);
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(format!("{} problem(s) found", problems).into());
+    }
+    Ok(())
+}
+
+/// Turns project directory `dir` into a template named `name` (the
+/// directory's name by default).  The values templates would get in the
+/// project, e.g. its name in every case, its author and its repo URLs, are
+/// replaced by placeholders wherever they appear in file names and contents.
+pub fn templatize(
+    dir: &Path,
+    name: Option<&str>,
+    project_name: Option<&str>,
+    options: &ContextOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Err(format!("'{}' isn't a directory", dir.display()).into());
+    }
+    let dir = dir.canonicalize()?;
+    let dir_name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("'{}' has no usable name", dir.display()))?;
+    let name = name.unwrap_or(dir_name);
+    let dest = new_template_dir(name)?;
+
+    // Values are looked up as they would be when generating in the project:
+    // its .dropkickrc, its origin remote and so on
+    env::set_current_dir(&dir)?;
+    let mut repo_config = get_repo_config()?;
+    if let Some(project_name) = project_name {
+        repo_config.project.name = project_name.to_string();
+    } else if find_repo_config().is_none() {
+        repo_config.project.name = dir_name.to_string();
+    }
+    let options = ContextOptions {
+        vars_file: None,
+        output_dir: PathBuf::new(),
+        ..options.clone()
+    };
+    let (context, sources) = resolve_context(&repo_config, &options)?;
+
+    let values: Vec<(String, String)> = skeleton::TEMPLATIZED_FIELDS
+        .iter()
+        .filter(|field| {
+            sources
+                .get(**field)
+                .is_none_or(|source| source != "default")
+        })
+        .filter_map(|field| {
+            let value = context.get(*field)?.as_str()?;
+            Some((value.to_string(), field.to_string()))
+        })
+        .collect();
+    debug!("templatizing with {:?}", values);
+
+    let report = skeleton::reverse_template(&dir, values, &dest)?;
+    info!(
+        "Created template '{}' in {} from {} file(s), {} of them with values replaced by placeholders",
+        name,
+        clean_path(&dest).display(),
+        report.files,
+        report.replaced
+    );
+
+    Ok(())
+}
+
+/// Where template `name` goes under the templates path, which has to be a
+/// plain folder name that isn't taken yet.
+fn new_template_dir(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!("'{}' isn't a usable template name", name).into());
+    }
+
+    let templates_path = get_templates_path();
+    let dest = templates_path.join(name);
+    if dest.exists() {
+        return Err(format!(
+            "template '{}' already exists in {}",
+            name,
+            clean_path(&dest).display()
+        )
+        .into());
+    }
+    fs::create_dir_all(&templates_path).map_err(|e| {
+        format!(
+            "unable to create {}: {}",
+            clean_path(&templates_path).display(),
+            e
+        )
+    })?;
+
+    Ok(dest)
+}
+
+/// Pulls upstream changes into one installed template, or every one, listing
+/// the files that changed and recording the new commits in the lockfile.
+/// Templates checked out at a tag stay where they are.
+pub fn update(template: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let names = match template {
+        Some(template) => {
+            if !remote::is_installed(&template_root(template)?) {
+                return Err(format!(
+                    "template '{}' wasn't installed from git, so there's nothing to update",
+                    template
+                )
+                .into());
+            }
+            vec![template.to_string()]
+        }
+        None => template_names()?
+            .into_iter()
+            .filter(|name| remote::is_installed(&get_templates_path().join(name)))
+            .collect(),
+    };
+
+    if names.is_empty() {
+        info!("No templates were installed from git, nothing to update.");
+        return Ok(());
+    }
+
+    let mut lockfile = Lockfile::load()?;
+    let mut failures = 0;
+    for name in &names {
+        if let Err(e) = update_template(name, &mut lockfile) {
+            error!("Unable to update '{}': {}", name, e);
+            failures += 1;
+        }
+    }
+    lockfile.save()?;
+
+    if failures > 0 {
+        return Err(format!("{} template(s) failed to update", failures).into());
+    }
+
+    Ok(())
+}
+
+fn update_template(name: &str, lockfile: &mut Lockfile) -> Result<(), Box<dyn Error>> {
+    let root = get_templates_path().join(name);
+    let reference = lockfile
+        .templates
+        .get(name)
+        .and_then(|locked| locked.reference.clone());
+    let before = remote::head_commit(&root)?;
+
+    if remote::current_branch(&root).is_none() {
+        info!(
+            "'{}' is pinned to {}, leaving it alone",
+            name,
+            reference.as_deref().unwrap_or(short_sha(&before))
+        );
+        return lockfile.record(name, &root, reference);
+    }
+
+    remote::pull(&root)?;
+    let after = remote::head_commit(&root)?;
+
+    if before == after {
+        info!("'{}' is already up to date", name);
+    } else {
+        info!(
+            "Updated '{}' {}..{}",
+            name,
+            short_sha(&before),
+            short_sha(&after)
+        );
+        for line in remote::diff_stat(&root, &before, &after)?.lines() {
+            info!("  {}", line.trim());
+        }
+    }
+
+    lockfile.record(name, &root, reference)
+}
+
+fn short_sha(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
+
+/// Prints the available templates, or the files of one template as they're
+/// named once imported.
+pub fn list(template: Option<&str>, follow_symlinks: bool) -> Result<(), Box<dyn Error>> {
+    match template {
+        None => {
+            for name in template_names()? {
+                println!("{}", name);
+            }
+        }
+        Some(template) => {
+            let roots = template_roots(&[template.to_string()])?;
+            let mut seen = BTreeSet::new();
+            for (_, name) in lineage_entries(&roots, follow_symlinks)? {
+                if seen.insert(name.clone()) {
+                    println!("{}", name);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Imports files from a template into the working directory without the TUI
+/// or a confirmation prompt, for scripts and CI.  With no `files`, the whole
+/// template is imported.
+pub fn import(
+    template: &str,
+    files: &[String],
+    follow_symlinks: bool,
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let roots = template_roots(&[template.to_string()])?;
+    let entries = lineage_entries(&roots, follow_symlinks)?;
+
+    let selected: Vec<PathBuf> = if files.is_empty() {
+        entries.iter().map(|(src, _)| src.clone()).collect()
+    } else {
+        let mut selected = Vec::new();
+        for file in files {
+            // `main.rs.tt[bin]` and `main.rs.tt[!bin]` are both `main.rs`,
+            // and their conditions pick which one is generated
+            let matches: Vec<PathBuf> = entries
+                .iter()
+                .filter(|(_, name)| name == file)
+                .map(|(src, _)| src.clone())
+                .collect();
+            if matches.is_empty() {
+                return Err(missing_file_message(&entries, template, file).into());
+            }
+            selected.extend(matches);
+        }
+        selected
+    };
+
+    let mut renderer = Renderer::new(&get_repo_config()?, options)?;
+    apply_manifests(&roots, &mut renderer, true)?;
+    let selected = layer_files(&roots, selected, &renderer, import_options);
+    check_collisions(&selected, &renderer, import_options)?;
+
+    // Hooks are written for the whole template, so picking files skips them
+    let whole_template = files.is_empty();
+    if whole_template {
+        run_hooks(&roots, Hook::PreGen, &renderer, import_options)?;
+    }
+    let report = import_template_files(&selected, &renderer, import_options)?;
+    report.log_summary("Imported", selected.len());
+    report.check_failures()?;
+    if whole_template {
+        run_hooks(&roots, Hook::PostGen, &renderer, import_options)?;
+    }
+
+    Ok(())
+}
+
+/// Renders a template file from anywhere on disk, or stdin when `file` is
+/// `None` or `-`, to stdout or `output`.  Unlike the importer, a file
+/// handlebars can't render is an error rather than copied, and nothing is
+/// written when it is.
+pub fn render(
+    file: Option<&Path>,
+    output: Option<&Path>,
+    options: &ContextOptions,
+) -> Result<(), Box<dyn Error>> {
+    let file = file.filter(|file| *file != Path::new("-"));
+    let bytes = match file {
+        Some(file) => {
+            fs::read(file).map_err(|e| format!("unable to read {}: {}", file.display(), e))?
+        }
+        None => {
+            let mut bytes = Vec::new();
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .map_err(|e| format!("unable to read stdin: {}", e))?;
+            bytes
+        }
+    };
+    let name = file.unwrap_or(Path::new("stdin"));
+    if is_binary(name, &bytes)? {
+        return Err(format!("{} is a binary file, which isn't rendered", name.display()).into());
+    }
+    let content = String::from_utf8_lossy(&bytes);
+    let (_, body) = split_frontmatter(&content)?;
+
+    let mut renderer = Renderer::new(&get_repo_config()?, options)?;
+    if let Some(root) = file.and_then(template_root_of) {
+        if let Some(engine) = Manifest::load(&root)?.engine {
+            renderer.set_engine(engine);
+        }
+        renderer.load_template_files(&template_lineage(&root)?)?;
+    }
+    // A `{{#each list}}` file is rendered once per item
+    let mut rendered = String::new();
+    for renderer in renderer.for_each_item(name)? {
+        rendered.push_str(&renderer.render(body)?);
+    }
+
+    match output {
+        Some(output) => fs::write(output, rendered)
+            .map_err(|e| format!("unable to write {}: {}", output.display(), e))?,
+        None => io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Renders a template checked out somewhere other than the templates path,
+/// e.g. with `git clone`, where it is: each `.tt` file is replaced by what it
+/// renders to, placeholders in file and folder names are filled in and a
+/// file whose condition doesn't hold is removed.  The folder's own
+/// `dropkick.yaml` applies as it would to a template.  A file that can't be
+/// rendered is reported and left as it is.
+pub fn render_dir(dir: &Path, options: &ContextOptions) -> Result<(), Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Err(format!("'{}' isn't a directory", dir.display()).into());
+    }
+    let roots = template_lineage(dir)?;
+    let manifest = Manifest::load(dir)?;
+    let mut renderer = Renderer::new(&get_repo_config()?, options)?;
+    apply_manifests(&roots, &mut renderer, true)?;
+
+    // Folders first, so their names can be tidied up deepest first once
+    // the files in them have moved
+    let mut files = Vec::new();
+    let mut folders = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        for path in fs::read_dir(&folder)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+        {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            if name == ".git"
+                || (folder == dir && TEMPLATE_SETTINGS.contains(&name))
+                || is_fixture_file(dir, &path)
+                || is_ignored(dir, &path)
+                || path.is_symlink()
+            {
+                continue;
+            }
+            if path.is_dir() {
+                folders.push(path.clone());
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+
+    let (mut rendered, mut removed, mut failed) = (0, 0, 0);
+    for file in &files {
+        match render_in_place(dir, file, &manifest, &renderer) {
+            Ok(Some(true)) => rendered += 1,
+            Ok(Some(false)) => removed += 1,
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}: {}", clean_path(file).display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    folders.sort_by_key(|folder| std::cmp::Reverse(folder.components().count()));
+    for folder in folders {
+        let relative = folder.strip_prefix(dir).unwrap_or(&folder);
+        let renamed = render_path(relative, &renderer).map_or(true, |path| path != relative);
+        // Only what's left empty goes
+        if renamed && fs::remove_dir(&folder).is_ok() {
+            debug!("removed {}", clean_path(&folder).display());
+        }
+    }
+
+    match removed {
+        0 => info!(
+            "Rendered {} file(s) in {}",
+            rendered,
+            clean_path(dir).display()
+        ),
+        removed => info!(
+            "Rendered {} file(s) in {}, removed {} whose condition doesn't hold",
+            rendered,
+            clean_path(dir).display(),
+            removed
+        ),
+    }
+    if failed > 0 {
+        return Err(format!("{} file(s) couldn't be rendered", failed).into());
+    }
+    Ok(())
+}
+
+/// `render_dir` for one file: `Some(true)` when it was rendered or moved,
+/// `Some(false)` when it was removed and `None` when it was left alone.
+fn render_in_place(
+    dir: &Path,
+    file: &Path,
+    manifest: &Manifest,
+    renderer: &Renderer,
+) -> Result<Option<bool>, Box<dyn Error>> {
+    let relative = file.strip_prefix(dir).unwrap_or(file);
+    let name = relative
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let stripped = strip_template_suffix(name);
+
+    let rule = manifest.rule_for(relative);
+    let mut skip = skip_reason(file, renderer)?;
+    if let Some(when) = rule.and_then(|rule| rule.when.as_ref())
+        && !is_truthy(&renderer.render(when)?)
+    {
+        skip = Some(format!("only generated when {}", when));
+    }
+    if let (Some(_), Some(reason)) = (stripped, skip) {
+        fs::remove_file(file)?;
+        info!("Removed {} ({})", clean_path(file).display(), reason);
+        return Ok(Some(false));
+    }
+
+    let bytes = fs::read(file)?;
+    let binary = is_binary(file, &bytes)? || manifest.lists_binary(relative);
+    let mode = match rule.and_then(|rule| rule.mode) {
+        Some(mode) => Some(mode),
+        None => file_mode(file)?,
+    };
+    let target = relative.with_file_name(stripped.unwrap_or(name));
+
+    // Everything renders before anything's written, so a file that doesn't
+    // is left as it is
+    let mut outputs: Vec<(PathBuf, Vec<u8>, Option<MergeDirective>)> = Vec::new();
+    for renderer in renderer.for_each_item(relative)? {
+        let dest = dir.join(render_path(&target, &renderer)?);
+        if stripped.is_none() && dest == file {
+            return Ok(None);
+        }
+
+        let (content, merge) = match (stripped, binary) {
+            (Some(_), false) => {
+                let (frontmatter, body) = split_frontmatter(std::str::from_utf8(&bytes)?)?;
+                (renderer.render(body)?.into_bytes(), frontmatter.merge)
+            }
+            _ => (bytes.clone(), None),
+        };
+        if merge.is_none()
+            && dest != file
+            && (dest.exists() || outputs.iter().any(|(other, _, _)| *other == dest))
+        {
+            return Err(format!("{} already exists", clean_path(&dest).display()).into());
+        }
+        outputs.push((dest, content, merge));
+    }
+
+    let mut written = Vec::new();
+    for (dest, content, merge) in outputs {
+        if let Some(merge) = merge {
+            let target = merge_target(&dest, &merge)?;
+            merge_into_file(&target, &String::from_utf8(content)?, &merge)?;
+            info!(
+                "Merged {} -> {}",
+                clean_path(file).display(),
+                clean_path(&target).display()
+            );
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&dest, content)?;
+        if let Some(mode) = mode {
+            transaction::set_mode(&dest, mode)?;
+        }
+        debug!(
+            "rendered {} -> {}",
+            clean_path(file).display(),
+            clean_path(&dest).display()
+        );
+        written.push(dest);
+    }
+
+    if !written.iter().any(|dest| dest == file) {
+        fs::remove_file(file)?;
+    }
+    Ok(Some(true))
+}
+
+/// Prints every `config[:...]` key referenced by a template's files, flagging
+/// which ones are built in and which must be supplied by the user.
+pub fn vars(template: &str, follow_symlinks: bool) -> Result<(), Box<dyn Error>> {
+    let roots = template_roots(&[template.to_string()])?;
+
+    let mut keys = BTreeSet::new();
+    for (file, _) in lineage_entries(&roots, follow_symlinks)? {
+        // Binary files aren't rendered, so unreadable content is skipped
+        if let Ok(bytes) = fs::read(&file)
+            && !is_binary(&file, &bytes)?
+        {
+            keys.extend(referenced_config_keys(&String::from_utf8_lossy(&bytes)));
+        }
+    }
+
+    if keys.is_empty() {
+        println!(
+            "Template '{}' doesn't reference any config values.",
+            template
+        );
+        return Ok(());
+    }
+
+    let built_in = InterpolationConfig::field_names();
+    let mut declared = BTreeSet::new();
+    for root in &roots {
+        declared.extend(Manifest::load(root)?.variables.into_iter().map(|v| v.name));
+    }
+    let width = keys.iter().map(|k| k.len()).max().unwrap_or(0);
+
+    println!("\nVariables referenced by '{}':", template);
+    println!("{}", "=".repeat(50));
+    for key in &keys {
+        let kind = if built_in.contains(key) {
+            "built-in"
+        } else if declared.contains(key) {
+            "declared in dropkick.yaml"
+        } else {
+            "custom (must be supplied)"
+        };
+        println!("  {:width$}  {}", key, kind, width = width);
+    }
+    println!("{}", "=".repeat(50));
+
+    let n_custom = keys
+        .iter()
+        .filter(|k| !built_in.contains(k) && !declared.contains(*k))
+        .count();
+    println!("{} variable(s), {} custom\n", keys.len(), n_custom);
+
+    Ok(())
+}
+
+/// Prints every value templates get in the working directory, with where it
+/// came from, for working out why a placeholder renders the way it does.
+pub fn context(format: OutputFormat, options: &ContextOptions) -> Result<(), Box<dyn Error>> {
+    let (context, sources) = resolve_context(&get_repo_config()?, options)?;
+    let Value::Object(values) = context else {
+        return Ok(());
+    };
+
+    let source_of = |key: &str| sources.get(key).map_or("--vars-file", String::as_str);
+    match format {
+        OutputFormat::Table => {
+            let width = values.keys().map(|k| k.len()).max().unwrap_or(0);
+            let value_width = values
+                .values()
+                .map(|v| display_value(v).len())
+                .max()
+                .unwrap_or(0)
+                .min(60);
+            for (key, value) in &values {
+                println!(
+                    "{:width$}  {:value_width$}  {}",
+                    key,
+                    display_value(value),
+                    source_of(key),
+                    width = width,
+                    value_width = value_width
+                );
+            }
+        }
+        OutputFormat::Json | OutputFormat::Yaml => {
+            let report: Map<String, Value> = values
+                .iter()
+                .map(|(key, value)| {
+                    let entry = json!({ "value": value, "source": source_of(key) });
+                    (key.clone(), entry)
+                })
+                .collect();
+            match format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                _ => print!("{}", serde_yaml::to_string(&report)?),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A value as it reads in a template: strings bare, anything else as JSON.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// A problem found by `lint`, pointing at a file within the template and,
+/// where it applies, the line.
+struct LintFinding {
+    file: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file.display(), line, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// Checks a template for authoring mistakes before it's shared: handlebars
+/// that doesn't parse or leaves blocks unbalanced, variables and config keys
+/// that are neither built in nor declared in `template.yaml` or
+/// `dropkick.yaml`, ERB and handlebars tags mixed in one file, files that
+/// would land outside the output directory, and files with placeholders but
+/// no `.tt` suffix.  Every finding is reported before failing.
+pub fn lint(template: &str, follow_symlinks: bool) -> Result<(), Box<dyn Error>> {
+    let root = template_root(template)?;
+    let templates_path = get_templates_path();
+
+    let mut known: BTreeSet<String> = InterpolationConfig::field_names().into_iter().collect();
+    // Variables declared by the templates this one extends are supplied too
+    for ancestor in template_lineage(&root)? {
+        known.extend(declared_variables(&ancestor)?);
+        if ancestor.join(TEMPLATE_DATA_DIR).is_dir() {
+            known.insert("data".to_string());
+        }
+    }
+    let engine = Manifest::load(&root)?.engine.unwrap_or_default();
+
+    let mut findings = Vec::new();
+    for file in template_files(&root, follow_symlinks)? {
+        let relative = file.strip_prefix(&root).unwrap_or(&file).to_path_buf();
+        let finding = |line, message| LintFinding {
+            file: relative.clone(),
+            line,
+            message,
+        };
+
+        // Placeholders in the path can't be judged without the user's values,
+        // so only the path as written is checked
+        match compute_destination(&file, &templates_path, Path::new(""), false, None) {
+            Ok(dest) if dest.components().all(|c| matches!(c, Component::Normal(_))) => {}
+            Ok(dest) => findings.push(finding(
+                None,
+                format!("renders outside the output directory ({})", dest.display()),
+            )),
+            Err(e) => findings.push(finding(None, e.to_string())),
+        }
+        let looped = loop_list(&relative);
+        if let Some(list) = looped
+            && !known.contains(list)
+        {
+            findings.push(finding(
+                None,
+                format!(
+                    "generated per item of '{}', which is neither built in nor declared in template.yaml or dropkick.yaml",
+                    list
+                ),
+            ));
+        }
+
+        let content = match fs::read(&file) {
+            // Binary files aren't rendered, so can't have placeholders
+            Ok(bytes) if is_binary(&file, &bytes)? => continue,
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                findings.push(finding(None, format!("unable to read file ({})", e)));
+                continue;
+            }
+        };
+
+        let body = match split_frontmatter(&content) {
+            Ok((frontmatter, body)) => {
+                if let Some(merge) = &frontmatter.merge
+                    && let Err(e) = merge_target(&relative, merge)
+                {
+                    findings.push(finding(None, e));
+                }
+                body
+            }
+            Err(e) => {
+                findings.push(finding(None, e));
+                continue;
+            }
+        };
+        // Keep line numbers pointing into the file as written
+        let body_offset = content[..content.len() - body.len()].lines().count();
+
+        let mut unbalanced = Vec::new();
+        if engine == Engine::Handlebars {
+            unbalanced = unbalanced_blocks(body);
+            for (line, message) in &unbalanced {
+                findings.push(finding(Some(line + body_offset), message.clone()));
+            }
+            for (line, name) in referenced_variables(body) {
+                let per_item = looped.is_some() && matches!(name.as_str(), "item" | "item_index");
+                if !known.contains(&name) && !per_item {
+                    findings.push(finding(
+                        Some(line + body_offset),
+                        format!(
+                            "'{}' is neither built in nor declared in template.yaml or dropkick.yaml",
+                            name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        // The parser's own message for an unbalanced block says less
+        if unbalanced.is_empty()
+            && let Err(e) = engine.backend().check_syntax(body)
+        {
+            let line = e.line.map(|line| line + body_offset);
+            findings.push(finding(line, format!("invalid template: {}", e.reason)));
+        }
+
+        let erb_line = content
+            .lines()
+            .enumerate()
+            .skip(body_offset)
+            .find(|(_, line)| !referenced_config_keys(line).is_empty());
+        if let Some((i, _)) = erb_line
+            && engine == Engine::Handlebars
+            && body.contains("{{")
+        {
+            findings.push(finding(
+                Some(i + 1),
+                "mixes ERB-style config[:key] tags with {{...}} tags, pick one".to_string(),
+            ));
+        }
+
+        for (i, line) in content.lines().enumerate().skip(body_offset) {
+            for key in referenced_config_keys(line) {
+                if !known.contains(&key) {
+                    findings.push(finding(
+                        Some(i + 1),
+                        format!(
+                            "config[:{}] is neither built in nor declared in template.yaml or dropkick.yaml",
+                            key
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    for file in untemplated_files(&root, follow_symlinks)? {
+        let Ok(bytes) = fs::read(&file) else {
+            continue;
+        };
+        if is_binary(&file, &bytes)? {
+            continue;
+        }
+        let content = String::from_utf8_lossy(&bytes);
+        let marker = content
+            .lines()
+            .position(|line| line.contains("{{") || line.contains("<%=") || line.contains("{%"));
+        if let Some(i) = marker {
+            findings.push(LintFinding {
+                file: file.strip_prefix(&root).unwrap_or(&file).to_path_buf(),
+                line: Some(i + 1),
+                message: "has template placeholders but no .tt suffix, so it's never generated"
+                    .to_string(),
+            });
+        }
+    }
+
+    if findings.is_empty() {
+        println!("No problems found in template '{}'.", template);
+        return Ok(());
+    }
+
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    Err(format!(
+        "{} problem(s) found in template '{}'",
+        findings.len(),
+        template
+    )
+    .into())
+}
+
+/// Renders a template with each of its snapshot tests' settings into a
+/// scratch directory and compares the result with the files the test
+/// expects, printing a diff for each difference.  With `update` the expected
+/// files are replaced instead.  Hooks don't run.
+pub fn test(template: &str, update: bool, follow_symlinks: bool) -> Result<(), Box<dyn Error>> {
+    let root = template_root(template)?;
+    let fixtures = snapshot::fixtures(&root)?;
+    if fixtures.is_empty() {
+        return Err(format!(
+            "template '{}' has no tests, add {}/<case>/expected/ with the files it should generate",
+            template,
+            snapshot::TESTS_DIR
+        )
+        .into());
+    }
+
+    let roots = template_roots(&[template.to_string()])?;
+    let mut failed = 0;
+    for fixture in &fixtures {
+        let scratch = env::temp_dir().join(format!(
+            "dropkick-test-{}-{}",
+            std::process::id(),
+            fixture.name
+        ));
+        let result = render_fixture(fixture, &roots, &scratch, follow_symlinks).and_then(|()| {
+            if update {
+                snapshot::update(&fixture.expected, &scratch)?;
+                return Ok(Vec::new());
+            }
+            snapshot::compare(&fixture.expected, &scratch)
+        });
+        if scratch.exists() {
+            fs::remove_dir_all(&scratch)?;
+        }
+
+        match result {
+            Ok(differences) if differences.is_empty() => {
+                let outcome = if update { "updated" } else { "ok" };
+                println!("test {} ... {}", fixture.name, outcome);
+            }
+            Ok(differences) => {
+                failed += 1;
+                println!("test {} ... FAILED", fixture.name);
+                for difference in differences {
+                    println!("  {}", difference);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "test {} ... FAILED
+  {}",
+                    fixture.name, e
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} test(s) failed", failed, fixtures.len()).into());
+    }
+    Ok(())
+}
+
+/// Generates the template layered in `roots` into `scratch` as a new project
+/// with `fixture`'s settings, and nothing from the user's machine, so a
+/// snapshot recorded on one passes on any other.
+fn render_fixture(
+    fixture: &snapshot::Fixture,
+    roots: &[PathBuf],
+    scratch: &Path,
+    follow_symlinks: bool,
+) -> Result<(), Box<dyn Error>> {
+    let options = ContextOptions {
+        output_dir: scratch.to_path_buf(),
+        new_project: true,
+        pinned: true,
+        ..ContextOptions::default()
+    };
+    let import_options = ImportOptions {
+        output_dir: scratch.to_path_buf(),
+        no_hooks: true,
+        ..ImportOptions::default()
+    };
+    fs::create_dir_all(scratch)?;
+
+    let mut renderer = Renderer::new(&fixture.config, &options)?;
+    apply_manifests(roots, &mut renderer, false)?;
+
+    // Per-file progress would bury the results
+    let level = log::max_level();
+    log::set_max_level(level.min(log::LevelFilter::Warn));
+    let report = layered_template_files(roots, follow_symlinks, &renderer, &import_options)
+        .and_then(|files| import_template_files(&files, &renderer, &import_options));
+    log::set_max_level(level);
+
+    report?.check_failures()
+}
+
+/// Renders every file of a template into the output directory.  Unless `yes`
+/// is set, the plan is summarized and confirmed on the terminal first.
+pub fn scaffold(
+    args: &ScaffoldArgs,
+    follow_symlinks: bool,
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let output_dir = &import_options.output_dir;
+    let roots = template_roots(&args.template)?;
+
+    let resolved_output_dir = if output_dir.is_absolute() {
+        output_dir.to_path_buf()
+    } else {
+        env::current_dir()?.join(output_dir)
+    };
+
+    let mut renderer = stats::timed(Phase::Config, || {
+        Renderer::new(&get_repo_config()?, options)
+    })?;
+    apply_manifests(&roots, &mut renderer, !args.no_input)?;
+    let files = layered_template_files(&roots, follow_symlinks, &renderer, import_options)?;
+    check_collisions(&files, &renderer, import_options)?;
+
+    let summary = format!(
+        "\nAbout to scaffold:\n  Template:   {}\n  Output dir: {}\n  Files:      {}",
+        args.template.join(" + "),
+        clean_path(&resolved_output_dir).to_string_lossy(),
+        files.len()
+    );
+    if import_options.dry_run {
+        info!("{}", summary);
+    } else if !confirm_scaffold(&summary, args.yes, args.no_input)? {
+        info!("Aborted, nothing was written.\n");
+        return Ok(());
+    }
+
+    run_hooks(&roots, Hook::PreGen, &renderer, import_options)?;
+    info!("{}", "=".repeat(50));
+    let report = import_template_files(&files, &renderer, import_options)?;
+    info!("{}", "=".repeat(50));
+    report.log_summary("Scaffolded", files.len());
+    if args.stats {
+        print_stats(start.elapsed(), files.len(), args.json)?;
+    }
+    report.check_failures()?;
+    run_hooks(&roots, Hook::PostGen, &renderer, import_options)?;
+
+    Ok(())
+}
+
+/// Runs the scaffold pipeline in memory and prints only the resulting counts,
+/// so CI can assert on them.  Fails if any file would error.
+pub fn scaffold_count_only(
+    args: &ScaffoldArgs,
+    follow_symlinks: bool,
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let roots = template_roots(&args.template)?;
+    let mut renderer = stats::timed(Phase::Config, || {
+        Renderer::new(&get_repo_config()?, options)
+    })?;
+    apply_manifests(&roots, &mut renderer, false)?;
+    let files = layered_template_files(&roots, follow_symlinks, &renderer, import_options)?;
+
+    let mut counts = ImportCounts::default();
+    for file in &files {
+        match renderer.for_each_item(file) {
+            Ok(renderers) => {
+                for renderer in renderers {
+                    counts.add(plan_template_file(file, &renderer, import_options));
+                }
+            }
+            Err(_) => counts.add(PlannedAction::Error),
+        }
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string(&counts)?);
+    } else {
+        println!(
+            "{}\t{}\t{}\t{}",
+            counts.created, counts.updated, counts.skipped, counts.errored
+        );
+    }
+
+    if args.stats {
+        print_stats(start.elapsed(), files.len(), args.json)?;
+    }
+
+    if counts.errored > 0 {
+        return Err(format!("{} file(s) would fail to import", counts.errored).into());
+    }
+
+    Ok(())
+}
+
+/// Reports the `--stats` breakdown on stderr, so it never mixes with output
+/// meant for pipes and is silenced by `--quiet`.
+fn print_stats(total: Duration, files: usize, json: bool) -> Result<(), Box<dyn Error>> {
+    let stats = Stats::collect(total, files);
+    if json {
+        info!("{}", serde_json::to_string(&stats)?);
+    } else {
+        info!("{}", stats);
+    }
+    Ok(())
+}
+
+/// The folders of `templates` in order, each preceded by the templates it
+/// extends.
+fn template_roots(templates: &[String]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for template in templates {
+        for root in template_lineage(&template_root(template)?)? {
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+    }
+    Ok(roots)
+}
+
+fn describe_templates(templates: &[String]) -> String {
+    match templates {
+        [template] => format!("template '{}'", template),
+        _ => format!("templates '{}'", templates.join("', '")),
+    }
+}
+
+/// Every file of the templates at `roots`, base template first, with files
+/// that a later template replaces left out.
+fn layered_template_files(
+    roots: &[PathBuf],
+    follow_symlinks: bool,
+    renderer: &Renderer,
+    import_options: &ImportOptions,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for root in roots {
+        files.extend(template_files(root, follow_symlinks)?);
+    }
+    Ok(layer_files(roots, files, renderer, import_options))
+}
+
+/// Leaves out the `files` that one from a later template in `roots` replaces,
+/// reporting each.
+fn layer_files(
+    roots: &[PathBuf],
+    files: Vec<PathBuf>,
+    renderer: &Renderer,
+    import_options: &ImportOptions,
+) -> Vec<PathBuf> {
+    let mut layers = vec![Vec::new(); roots.len()];
+    for file in files {
+        let layer = roots
+            .iter()
+            .rposition(|root| file.starts_with(root))
+            .unwrap_or(0);
+        layers[layer].push(file);
+    }
+
+    let layered = layer_templates(&layers, renderer, import_options);
+    for (file, winner) in &layered.overridden {
+        info!(
+            "Using {} instead of {}",
+            clean_path(winner).display(),
+            clean_path(file).display()
+        );
+    }
+    layered.files
+}
+
+/// Runs `hook` of each template in order.
+fn run_hooks(
+    roots: &[PathBuf],
+    hook: Hook,
+    renderer: &Renderer,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    for root in roots {
+        hooks::run(root, hook, renderer, import_options)?;
+    }
+    Ok(())
+}
+
+/// Refuses to import when several files would land on the same destination.
+fn check_collisions(
+    files: &[PathBuf],
+    renderer: &Renderer,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let collisions = find_destination_collisions(files, renderer, import_options);
+    if collisions.is_empty() {
+        return Ok(());
+    }
+
+    let details: Vec<String> = collisions.iter().map(|c| format!("  {}", c)).collect();
+    Err(format!(
+        "several template files render to the same destination:\n{}",
+        details.join("\n")
+    )
+    .into())
+}
+
+fn confirm_scaffold(summary: &str, yes: bool, no_input: bool) -> Result<bool, Box<dyn Error>> {
+    if no_input && !yes {
+        return Err("--no-input is set, pass --yes to confirm writing files".into());
+    }
+
+    // Nobody is around to answer a prompt in CI, so go ahead
+    if yes || !io::stdin().is_terminal() {
+        info!("{}", summary);
+        return Ok(true);
+    }
+
+    // Whoever is asked has to see what they're agreeing to, even with -q
+    println!("{}", summary);
+    print!("Proceed? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Renders one named file from a template, writing it where importing it
+/// would (or to stdout).
+pub fn render_file(
+    template: &str,
+    file: &str,
+    stdout: bool,
+    follow_symlinks: bool,
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let roots = template_roots(&[template.to_string()])?;
+    let entries = lineage_entries(&roots, follow_symlinks)?;
+
+    // Files of the template itself come before those it extends
+    let candidates: Vec<&PathBuf> = entries
+        .iter()
+        .rev()
+        .filter(|(_, name)| name == file)
+        .map(|(src, _)| src)
+        .collect();
+    if candidates.is_empty() {
+        return Err(missing_file_message(&entries, template, file).into());
+    }
+
+    let mut renderer = Renderer::new(&get_repo_config()?, options)?;
+    apply_manifests(&roots, &mut renderer, !stdout)?;
+
+    // The first variant whose condition holds, e.g. `main.rs.tt[bin]`
+    let mut src = None;
+    for candidate in &candidates {
+        match skip_reason(candidate, &renderer)? {
+            None => {
+                src = Some(*candidate);
+                break;
+            }
+            Some(reason) if candidates.len() == 1 => {
+                return Err(format!("'{}' is {}", file, reason).into());
+            }
+            Some(_) => {}
+        }
+    }
+    let Some(src) = src else {
+        return Err(format!("no variant of '{}' applies with the current values", file).into());
+    };
+
+    if stdout {
+        let bytes = fs::read(src)?;
+        // Images, fonts and the like are printed byte for byte
+        if is_binary(src, &bytes)? {
+            io::stdout().write_all(&bytes)?;
+        } else {
+            let (_, body) = split_frontmatter(std::str::from_utf8(&bytes)?)?;
+            for renderer in renderer.for_each_item(src)? {
+                io::stdout().write_all(renderer.render(body)?.as_bytes())?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Written as any import is, so it lands in the output directory, stays
+    // inside it and follows the conflict policy
+    let report = import_template_files(std::slice::from_ref(src), &renderer, import_options)?;
+    report.log_summary("Rendered", 1);
+    report.check_failures()
+}
+
+/// Builds the error for a file that isn't in the template, suggesting the
+/// closest file names so a typo is easy to spot.
+fn missing_file_message(entries: &[(PathBuf, String)], template: &str, file: &str) -> String {
+    let mut candidates: Vec<(usize, &String)> = entries
+        .iter()
+        .map(|(_, candidate)| (edit_distance(file, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= (file.len() / 3).max(3)
+                || Path::new(candidate).file_name() == Path::new(file).file_name()
+        })
+        .collect();
+
+    candidates.sort();
+
+    let mut message = format!("'{}' isn't part of template '{}'", file, template);
+    if !candidates.is_empty() {
+        message.push_str(". Close matches:");
+        for (_, candidate) in candidates.iter().take(5) {
+            message.push_str(&format!("\n  {}", candidate));
+        }
+    }
+
+    message
+}
+
+/// Pairs each file in a template with its path relative to the template
+/// root minus the `.tt` suffix, which is where it lands when imported.
+fn template_entries(
+    root: &Path,
+    follow_symlinks: bool,
+) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+    Ok(template_files(root, follow_symlinks)?
+        .into_iter()
+        .filter_map(|path| {
+            let mut relative = path.strip_prefix(root).ok()?;
+            // Named as they land, without a cookiecutter template's project folder
+            if let Some(first) = relative.iter().next()
+                && is_project_dir(&root.join(first))
+            {
+                relative = relative.strip_prefix(first).ok()?;
+            }
+            let relative = relative.to_string_lossy().to_string();
+            let name = strip_template_suffix(&relative)
+                .unwrap_or(&relative)
+                .to_string();
+            Some((path, name))
+        })
+        .collect())
+}
+
+/// `template_entries` of each template in `roots`, in order.
+fn lineage_entries(
+    roots: &[PathBuf],
+    follow_symlinks: bool,
+) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for root in roots {
+        entries.extend(template_entries(root, follow_symlinks)?);
+    }
+    Ok(entries)
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn renderer() -> Renderer {
+        let mut config = Config::new("demo");
+        config.project.github_user = Some("me".to_string());
+        let options = ContextOptions {
+            new_project: true,
+            ..Default::default()
+        };
+        Renderer::new(&config, &options).unwrap()
+    }
+
+    #[test]
+    fn rendering_in_place_replaces_the_template_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("README.md.tt");
+        fs::write(&file, "# {{name}}\n").unwrap();
+
+        let rendered = render_in_place(dir.path(), &file, &Manifest::default(), &renderer());
+        assert_eq!(rendered.unwrap(), Some(true));
+        assert!(!file.exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("README.md")).unwrap(),
+            "# demo\n"
+        );
+    }
+
+    #[test]
+    fn a_file_that_doesnt_render_is_left_as_it_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bad.txt.tt");
+        fs::write(&file, "{{#if name}}broken\n").unwrap();
+
+        let rendered = render_in_place(dir.path(), &file, &Manifest::default(), &renderer());
+        assert!(rendered.is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "{{#if name}}broken\n");
+        assert!(!dir.path().join("bad.txt").exists());
+    }
+}
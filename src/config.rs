@@ -1,17 +1,66 @@
-use serde::Deserialize;
-use std::{fs, io};
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Deserialize)]
+use crate::{
+    clean_path,
+    error::DropkickError,
+    exec::AllowExec,
+    import::ConflictPolicy,
+    keymap::{KeyAction, KeySpecs},
+    logging::warn_once,
+    theme::ThemeConfig,
+    xdg,
+};
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Project {
     pub name: String,
     // pub version: String,
+    /// The old name for `prefix`, still read when `prefix` isn't set
     #[serde(default)]
     pub template: String,
+    /// Stripped from the start of `name` for `unprefixed_name`, e.g. `dk-`
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// GitHub account that owns the repo, when it differs from `user.name`
+    #[serde(default)]
+    pub github_user: Option<String>,
+    /// Tag used in `full_image` instead of the current commit's short sha
+    #[serde(default)]
+    pub image_tag: Option<String>,
+    /// The project builds an executable, for `[bin]` files and `{{#if bin}}`
+    #[serde(default)]
+    pub bin: bool,
+    /// The project wants test scaffolding, for `[test]` files
+    #[serde(default)]
+    pub test: bool,
+}
+
+/// Rewrites a legacy placeholder syntax into handlebars before rendering,
+/// e.g. `pattern: '%(\w+)%'` with `replacement: '{{$1}}'`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RewriteRule {
+    pub pattern: String,
+    pub replacement: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub project: Project,
+    #[serde(default)]
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// Values for this repo's templates, overriding the built-in fields
+    #[serde(default)]
+    pub variables: Map<String, Value>,
 }
 
 impl Config {
@@ -20,15 +69,365 @@ impl Config {
             project: Project {
                 name: name.to_string(),
                 template: "".to_string(),
+                prefix: None,
+                github_user: None,
+                image_tag: None,
+                bin: false,
+                test: false,
             },
+            rewrite_rules: Vec::new(),
+            variables: Map::new(),
         }
     }
 }
 
-pub fn get_repo_config() -> Config {
-    fs::read_to_string("./.dropkickrc")
-        .and_then(|raw| {
-            serde_yaml::from_str(&raw).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+pub const CONFIG_FILE: &str = "config.yaml";
+
+/// Personal defaults from `$XDG_CONFIG_HOME/dropkick/config.yaml`, for values that don't
+/// belong in git config.  A repo's `.dropkickrc` takes precedence.
+#[derive(Debug, Default, Deserialize)]
+pub struct UserConfig {
+    pub author: Option<String>,
+    pub email: Option<String>,
+    pub registry_domain: Option<String>,
+    pub k8s_domain: Option<String>,
+    /// Syntax highlighting theme for the TUI's file view, e.g. `Dracula`,
+    /// or that and the TUI's colors
+    pub theme: Option<ThemeConfig>,
+    /// Template `new` and `scaffold` use when `--template` isn't given
+    pub default_template: Option<String>,
+    /// Used when `--on-conflict` isn't given
+    pub on_conflict: Option<ConflictPolicy>,
+    /// URL or path of the template index `search` and `install <name>` use
+    pub template_index: Option<String>,
+    /// Whether templates may run commands with the sh helper: `true`, or the
+    /// programs they may run
+    pub allow_exec: Option<AllowExec>,
+    /// TUI keys to use instead of the defaults, e.g. `toggle: x`
+    #[serde(default)]
+    pub keys: BTreeMap<KeyAction, KeySpecs>,
+}
+
+impl UserConfig {
+    pub fn path() -> PathBuf {
+        xdg::config_dir().join(CONFIG_FILE)
+    }
+
+    /// The values set here that templates see, standing in for git config.
+    pub fn variables(&self) -> Map<String, Value> {
+        [
+            ("author", &self.author),
+            ("email", &self.email),
+            ("registry_domain", &self.registry_domain),
+            ("k8s_domain", &self.k8s_domain),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), Value::String(value.clone()?))))
+        .collect()
+    }
+}
+
+/// Reads `$XDG_CONFIG_HOME/dropkick/config.yaml`.  It's optional, and a broken one is
+/// reported and ignored rather than stopping every command.
+pub fn get_user_config() -> UserConfig {
+    let path = UserConfig::path();
+    let Ok(raw) = fs::read_to_string(&path) else {
+        return UserConfig::default();
+    };
+
+    match parse_checked(&raw, ConfigFormat::Yaml) {
+        Ok((config, unknown)) => {
+            warn_unknown_keys(&path, &unknown);
+            config
+        }
+        Err(e) => {
+            warn!("Ignoring {}: {}", clean_path(&path).display(), e);
+            UserConfig::default()
+        }
+    }
+}
+
+/// Prefix of environment variables that set template values, e.g.
+/// `DROPKICK_VAR_K8S_DOMAIN` for `k8s_domain`.
+const ENV_PREFIX: &str = "DROPKICK_VAR_";
+
+/// Template values from `DROPKICK_VAR_*` environment variables, for CI jobs
+/// that can't touch git config or commit a `.dropkickrc`.  `true` and
+/// `false` become booleans so flags like `bin` work; everything else is text.
+pub fn env_variables() -> Map<String, Value> {
+    env::vars()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let value = match value.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => Value::String(value),
+            };
+            Some((name, value))
         })
-        .unwrap_or_else(|_| Config::new("Repo Name"))
+        .collect()
+}
+
+/// The names a repo's settings can go by, looked for in this order.
+pub const REPO_CONFIG_FILES: &[&str] = &[
+    ".dropkickrc",
+    ".dropkickrc.toml",
+    ".dropkickrc.json",
+    "dropkick.toml",
+];
+
+/// How a config file is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Goes by the extension, or for a plain `.dropkickrc` by what it looks
+    /// like: an object is JSON, a `[table]` or `key = value` line TOML, and
+    /// anything else YAML.
+    pub fn detect(path: &Path, raw: &str) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => return ConfigFormat::Toml,
+            Some("json") => return ConfigFormat::Json,
+            Some("yaml" | "yml") => return ConfigFormat::Yaml,
+            _ => {}
+        }
+
+        let toml_line = Regex::new(r#"^(\[[\w.-]+\]|[\w.-]+\s*=)"#).unwrap();
+        let first = raw
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        if first.starts_with('{') {
+            ConfigFormat::Json
+        } else if toml_line.is_match(first) {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+}
+
+/// The repo's settings file in the current directory, if there is one.  When
+/// there are several, the first in `REPO_CONFIG_FILES` wins, with a warning.
+pub fn find_repo_config() -> Option<PathBuf> {
+    let mut found = REPO_CONFIG_FILES
+        .iter()
+        .map(PathBuf::from)
+        .filter(|path| path.is_file());
+    let path = found.next()?;
+    for other in found {
+        warn_once(format!(
+            "Ignoring {}, {} is used instead",
+            other.display(),
+            path.display()
+        ));
+    }
+    Some(path)
+}
+
+/// Reads `.dropkickrc` (or its TOML or JSON equivalent), warning about keys
+/// it doesn't know.  Only a missing one falls back to a placeholder project;
+/// one that can't be read or parsed is an error.
+pub fn get_repo_config() -> Result<Config, DropkickError> {
+    let Some(path) = find_repo_config() else {
+        debug!("no .dropkickrc here, the project is called 'Repo Name' (dropkick init writes one)");
+        return Ok(Config::new("Repo Name"));
+    };
+    let raw = fs::read_to_string(&path).map_err(|source| DropkickError::Read {
+        path: path.clone(),
+        source,
+    })?;
+    let format = ConfigFormat::detect(&path, &raw);
+    let (config, unknown) =
+        parse_checked(&raw, format).map_err(|source| DropkickError::Config {
+            path: path.clone(),
+            source,
+        })?;
+    warn_unknown_keys(&path, &unknown);
+    Ok(config)
+}
+
+/// Parses a `.dropkickrc` document written in YAML.
+pub fn parse_config(raw: &str) -> Result<Config, Box<dyn Error + Send + Sync>> {
+    parse_checked(raw, ConfigFormat::Yaml).map(|(config, _)| config)
+}
+
+/// A key in a config file that nothing reads, most likely a typo.
+#[derive(Debug)]
+pub struct UnknownKey {
+    /// Where it is in the document, e.g. `project.bni`
+    pub path: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown key '{}'", self.path)?;
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a config document, along with the keys it has that `T` doesn't.
+///
+/// In YAML, anchors and aliases are resolved by serde_yaml itself, but merge
+/// keys (`<<: *base`) are left as literal `<<` entries unless we expand them
+/// before deserializing, which loses where a value came from; so when that
+/// fails, a document without merge keys is parsed again as written, for an
+/// error with a line and column.
+pub fn parse_checked<T: DeserializeOwned>(
+    raw: &str,
+    format: ConfigFormat,
+) -> Result<(T, Vec<UnknownKey>), Box<dyn Error + Send + Sync>> {
+    let mut ignored = Vec::new();
+    let mut ignore = |path: serde_ignored::Path| ignored.push(path.to_string());
+    let parsed = match format {
+        ConfigFormat::Yaml => {
+            let mut value: serde_yaml::Value = serde_yaml::from_str(raw)?;
+            value.apply_merge()?;
+            serde_ignored::deserialize(value, &mut ignore).map_err(|e| {
+                match raw.contains("<<:") {
+                    true => e,
+                    false => serde_yaml::from_str::<T>(raw).err().unwrap_or(e),
+                }
+            })?
+        }
+        ConfigFormat::Toml => {
+            serde_ignored::deserialize(toml::Deserializer::new(raw), &mut ignore)?
+        }
+        ConfigFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(raw);
+            let parsed = serde_ignored::deserialize(&mut deserializer, &mut ignore)?;
+            deserializer.end()?;
+            parsed
+        }
+    };
+
+    let unknown = ignored
+        .into_iter()
+        .filter(|path| !is_anchor_holder(path))
+        .map(|path| UnknownKey {
+            line: key_line(raw, &path, format),
+            path,
+        })
+        .collect();
+    Ok((parsed, unknown))
+}
+
+/// Whether `path` is under a top-level key that only holds anchors for the
+/// rest of the document, like `x-defaults` or `.defaults`, so isn't a typo.
+fn is_anchor_holder(path: &str) -> bool {
+    path.starts_with("x-") || path.starts_with('.')
+}
+
+/// The line the last part of key `path` is first written on, going by the
+/// text alone.
+fn key_line(raw: &str, path: &str, format: ConfigFormat) -> Option<usize> {
+    let key = path.rsplit('.').next()?;
+    let written = |line: &str| match format {
+        ConfigFormat::Yaml => line
+            .trim_start_matches("- ")
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.starts_with(':')),
+        ConfigFormat::Toml => {
+            line.strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+                || line.starts_with('[') && line.trim_end_matches(']').ends_with(key)
+        }
+        ConfigFormat::Json => line.starts_with(&format!("\"{}\"", key)),
+    };
+    raw.lines()
+        .position(|line| written(line.trim_start()))
+        .map(|index| index + 1)
+}
+
+fn warn_unknown_keys(path: &Path, unknown: &[UnknownKey]) {
+    for key in unknown {
+        warn_once(format!(
+            "Ignoring {} in {}",
+            key,
+            clean_path(path).display()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors_and_merge_keys_are_expanded() {
+        let raw = "\
+x-defaults: &defaults
+  github_user: octocat
+  bin: true
+project:
+  <<: *defaults
+  name: blog_post
+  bin: false
+rewrite_rules:
+  - &rule
+    pattern: '%name%'
+    replacement: '{{name}}'
+  - *rule
+";
+        let (config, unknown) = parse_checked::<Config>(raw, ConfigFormat::Yaml).unwrap();
+        assert_eq!(config.project.name, "blog_post");
+        assert_eq!(config.project.github_user.as_deref(), Some("octocat"));
+        assert!(!config.project.bin, "keys written beside `<<` win");
+        assert_eq!(config.rewrite_rules.len(), 2);
+        assert_eq!(config.rewrite_rules[1].replacement, "{{name}}");
+        assert!(unknown.is_empty(), "{unknown:?}");
+    }
+
+    #[test]
+    fn anchors_are_shared_between_variables() {
+        let raw = "\
+.registry: &registry
+  registry_domain: ghcr.io
+  k8s_domain: prod.example.com
+project:
+  name: blog_post
+variables:
+  <<: *registry
+  port: &port 8080
+  health_port: *port
+  staging:
+    <<: *registry
+    k8s_domain: staging.example.com
+";
+        let (config, unknown) = parse_checked::<Config>(raw, ConfigFormat::Yaml).unwrap();
+        let variables = &config.variables;
+        assert_eq!(variables["registry_domain"], "ghcr.io");
+        assert_eq!(variables["k8s_domain"], "prod.example.com");
+        assert_eq!(variables["port"], 8080);
+        assert_eq!(variables["health_port"], 8080);
+        assert_eq!(variables["staging"]["registry_domain"], "ghcr.io");
+        assert_eq!(variables["staging"]["k8s_domain"], "staging.example.com");
+        assert!(unknown.is_empty(), "{unknown:?}");
+    }
+
+    #[test]
+    fn other_top_level_keys_are_still_unknown() {
+        let raw = "project:\n  name: blog_post\ndefaults:\n  bin: true\n";
+        let (_, unknown) = parse_checked::<Config>(raw, ConfigFormat::Yaml).unwrap();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].path, "defaults");
+    }
+
+    #[test]
+    fn errors_without_merge_keys_keep_their_position() {
+        let err = parse_config("project:\n  name: [blog_post]\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
 }
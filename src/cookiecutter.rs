@@ -0,0 +1,203 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::{Captures, Regex};
+use serde_yaml::{Mapping, Value};
+
+use crate::{
+    clean_path,
+    logging::warn_once,
+    manifest::{Manifest, Variable, VariableKind},
+    template_rendering::Engine,
+};
+
+/// The variables of a cookiecutter template, kept at the top of its folder.
+pub const COOKIECUTTER_FILE: &str = "cookiecutter.json";
+
+/// Whether template folder `root` is a cookiecutter template.
+pub fn is_cookiecutter(root: &Path) -> bool {
+    root.join(COOKIECUTTER_FILE).is_file()
+}
+
+/// Whether `dir` is the `{{cookiecutter.project_slug}}` style folder a
+/// cookiecutter template keeps the project's files in.
+pub fn is_project_dir(dir: &Path) -> bool {
+    let Some(name) = dir.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    name.contains("{{")
+        && name.contains("cookiecutter.")
+        && dir.parent().is_some_and(is_cookiecutter)
+}
+
+/// Whether `path` is one of a cookiecutter template's project files, every
+/// one of which is rendered, `.tt` suffix or not.
+pub fn is_project_file(path: &Path) -> bool {
+    path.ancestors().skip(1).any(is_project_dir)
+}
+
+/// The settings dropkick reads from cookiecutter template `root`: the
+/// `cookiecutter` engine, a variable for each key of `cookiecutter.json`
+/// (a list offers its items as choices, the first being the default), and
+/// `_copy_without_render` as files that are copied as they are.
+pub fn manifest(root: &Path) -> Result<Manifest, Box<dyn Error>> {
+    let path = root.join(COOKIECUTTER_FILE);
+    let display = clean_path(&path);
+    let raw = fs::read_to_string(&path)?;
+    // Read as YAML, which JSON is a subset of, because its maps keep their
+    // order and a default can refer to the keys before it
+    let Value::Mapping(keys) = serde_yaml::from_str(&raw)
+        .map_err(|e| format!("unable to parse {}: {}", display.display(), e))?
+    else {
+        return Err(format!("{} must contain an object", display.display()).into());
+    };
+
+    let prompts = match keys.get("__prompts__") {
+        Some(Value::Mapping(prompts)) => prompts.clone(),
+        _ => Mapping::new(),
+    };
+    let mut variables = Vec::new();
+    for (name, value) in &keys {
+        let Some(name) = name.as_str() else {
+            continue;
+        };
+        // `_copy_without_render` and the like are settings, not questions
+        if name.starts_with('_') {
+            continue;
+        }
+        let prompt = prompts.get(name).and_then(|p| p.as_str()).map(String::from);
+        match variable(name, value, prompt) {
+            Some(variable) => variables.push(variable),
+            None => warn_once(format!(
+                "Ignoring '{}' in {}, only strings, numbers, booleans and lists of choices are supported",
+                name,
+                display.display()
+            )),
+        }
+    }
+
+    Ok(Manifest {
+        engine: Some(Engine::Cookiecutter),
+        variables,
+        binary: copy_without_render(root, keys.get("_copy_without_render")),
+        ..Manifest::default()
+    })
+}
+
+fn variable(name: &str, value: &Value, prompt: Option<String>) -> Option<Variable> {
+    let kind = match value {
+        Value::String(_) => VariableKind::String,
+        Value::Bool(_) => VariableKind::Bool,
+        Value::Number(n) if n.is_i64() => VariableKind::Integer,
+        Value::Number(_) => VariableKind::String,
+        Value::Sequence(choices) => {
+            let choices: Vec<String> = choices
+                .iter()
+                .map(|c| match c {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::Bool(b) => Some(b.to_string()),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            let alternatives: Vec<String> = choices.iter().map(|c| regex::escape(c)).collect();
+            return Some(Variable {
+                name: name.to_string(),
+                kind: VariableKind::String,
+                default: Some(Value::from(choices.first()?.as_str())),
+                prompt: Some(format!(
+                    "{} ({})",
+                    prompt.as_deref().unwrap_or(name),
+                    choices.join(", ")
+                )),
+                validation: Some(format!("^(?:{})$", alternatives.join("|"))),
+            });
+        }
+        _ => return None,
+    };
+
+    Some(Variable {
+        name: name.to_string(),
+        kind,
+        default: Some(value.clone()),
+        prompt,
+        validation: None,
+    })
+}
+
+/// `_copy_without_render` entries as `binary` entries: `*.ext` patterns, and
+/// paths below the project folder.  Other globs can't be expressed there.
+fn copy_without_render(root: &Path, entries: Option<&Value>) -> Vec<String> {
+    let Some(Value::Sequence(entries)) = entries else {
+        return Vec::new();
+    };
+    let project_dir = project_dir(root);
+    let project_dir = project_dir.as_ref().and_then(|d| d.file_name());
+
+    let mut binary = Vec::new();
+    for entry in entries.iter().filter_map(|e| e.as_str()) {
+        if let Some(ext) = entry.strip_prefix("*.")
+            && !ext.contains(['*', '?', '[', '/'])
+        {
+            binary.push(entry.to_string());
+        } else if let Some(dir) = project_dir
+            && !entry.contains(['*', '?', '['])
+        {
+            binary.push(Path::new(dir).join(entry).to_string_lossy().into_owned());
+        } else {
+            warn_once(format!(
+                "Rendering files matching '{}' anyway, only `*.ext` and plain paths are supported in _copy_without_render",
+                entry
+            ));
+        }
+    }
+    binary
+}
+
+fn project_dir(root: &Path) -> Option<PathBuf> {
+    fs::read_dir(root)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| p.is_dir() && is_project_dir(p))
+}
+
+/// Rewrites the Jinja2 a cookiecutter template is written in into Tera:
+/// `cookiecutter.name` becomes `name`, Python string methods such as
+/// `.lower()` and `.replace(' ', '_')` become filters, and positional filter
+/// arguments Tera wants named are named.  Only the inside of `{{ }}` and
+/// `{% %}` tags is touched.
+pub fn to_tera(template: &str) -> String {
+    let tags = Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap();
+    let namespace = Regex::new(r"\bcookiecutter\.(\w)").unwrap();
+    let method = Regex::new(r"\.(lower|upper|strip|title|capitalize)\(\)").unwrap();
+    let string = r#"('[^']*'|"[^"]*")"#;
+    let replace = Regex::new(&format!(
+        r"(?:\.|\|\s*)replace\(\s*{}\s*,\s*{}\s*\)",
+        string, string
+    ))
+    .unwrap();
+    let one_argument = Regex::new(&format!(r"\|\s*(default|join)\(\s*{}\s*\)", string)).unwrap();
+
+    tags.replace_all(template, |caps: &Captures| {
+        let tag = namespace.replace_all(&caps[0], "$1");
+        let tag = method.replace_all(&tag, |caps: &Captures| {
+            let filter = match &caps[1] {
+                "strip" => "trim",
+                other => other,
+            };
+            format!(" | {}", filter)
+        });
+        let tag = replace.replace_all(&tag, " | replace(from=$1, to=$2)");
+        one_argument
+            .replace_all(&tag, |caps: &Captures| {
+                let name = if &caps[1] == "join" { "sep" } else { "value" };
+                format!("| {}({}={})", &caps[1], name, &caps[2])
+            })
+            .into_owned()
+    })
+    .into_owned()
+}
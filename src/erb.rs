@@ -0,0 +1,407 @@
+use regex::Regex;
+
+use crate::error::DropkickError;
+
+/// A value an ERB tag reads: `config[:name]`, `config["name"]`, a loop
+/// variable, or either followed by more `[:key]` lookups.
+const PATH_PATTERN: &str = r#"^(\w+)((?:\[\s*(?::\w+|"[^"]*"|'[^']*')\s*\])*)$"#;
+
+/// One `[:key]`, `["key"]` or `['key']` lookup.
+const SEGMENT_PATTERN: &str = r#"\[\s*(?::(\w+)|"([^"]*)"|'([^']*)')\s*\]"#;
+
+/// `config[:items].each do |item|`, optionally `each_with_index do |item, i|`
+const EACH_PATTERN: &str = r"^(.+?)\.each(?:_with_index)?\s+do\s*\|\s*(\w+)\s*(?:,\s*(\w+)\s*)?\|$";
+
+/// `for item in config[:items]`
+const FOR_PATTERN: &str = r"^for\s+(\w+)\s+in\s+(.+?)(?:\s+do)?$";
+
+/// A block opened by an ERB logic tag, waiting for its `<% end %>`.
+enum Block {
+    If,
+    Unless,
+    /// With the loop's variable names, which tags inside can read
+    Each(Vec<String>),
+}
+
+impl Block {
+    fn helper(&self) -> &'static str {
+        match self {
+            Block::If => "if",
+            Block::Unless => "unless",
+            Block::Each(_) => "each",
+        }
+    }
+}
+
+/// Translates an ERB template, as written for the Ruby version of dropkick,
+/// into handlebars.  `<%= %>` output, `<% if %>`/`unless`/`elsif`/`else`/
+/// `end`, `each` and `for` loops over config values, `<%# %>` comments,
+/// `<%%` and the `<%-`/`-%>` trim markers are understood; any other Ruby is
+/// an error naming its line.  Text outside the tags is kept literally, even
+/// where it looks like handlebars.
+pub fn translate(template: &str) -> Result<String, DropkickError> {
+    Translator::new(true).run(template)
+}
+
+/// Translates just the `<%= config[:name] %>` output tags in a handlebars
+/// template, leaving any other ERB as it is, e.g. in a Rails view the
+/// template generates.
+pub fn translate_output_tags(template: &str) -> String {
+    Translator::new(false)
+        .run(template)
+        .unwrap_or_else(|_| template.to_string())
+}
+
+struct Translator {
+    /// Everything has to translate, rather than only what can
+    strict: bool,
+    blocks: Vec<(Block, usize)>,
+    output: String,
+}
+
+impl Translator {
+    fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            blocks: Vec::new(),
+            output: String::new(),
+        }
+    }
+
+    fn run(mut self, template: &str) -> Result<String, DropkickError> {
+        let mut pos = 0;
+        while let Some(found) = template[pos..].find("<%") {
+            let start = pos + found;
+            let line = template[..start].matches('\n').count() + 1;
+            let mut literal = &template[pos..start];
+
+            if template[start..].starts_with("<%%") {
+                self.literal(literal);
+                self.literal("<%");
+                pos = start + 3;
+                continue;
+            }
+            // From after the `<%`, so the `%>` in `<%>` doesn't close it
+            let Some(length) = template[start + 2..].find("%>") else {
+                if self.strict {
+                    return Err(error(line, "`<%` is never closed with `%>`".to_string()));
+                }
+                break;
+            };
+            let end = start + 2 + length + 2;
+            let tag = &template[start + 2..end - 2];
+
+            let (tag, trim_before) = match tag.strip_prefix('-') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (tag, trim_after) = match tag.strip_suffix('-') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+
+            let translated = if tag.starts_with('#') {
+                self.strict.then(String::new)
+            } else if let Some(expression) = tag.strip_prefix('=') {
+                // `<%==` is Rails' unescaped output, which ERB output is anyway
+                let expression = expression.strip_prefix('=').unwrap_or(expression).trim();
+                match self.path(expression) {
+                    Some(path) => Some(format!("{{{{{{{}}}}}}}", path)),
+                    None if self.strict => {
+                        return Err(error(
+                            line,
+                            format!(
+                                "`<%= {} %>` can't be translated, only config[:key] values and loop variables can be output",
+                                expression
+                            ),
+                        ));
+                    }
+                    None => None,
+                }
+            } else if self.strict {
+                Some(self.logic(tag.trim(), line)?)
+            } else {
+                None
+            };
+
+            let Some(translated) = translated else {
+                // Not ours to translate, so it's left exactly as written
+                self.output.push_str(&template[pos..end]);
+                pos = end;
+                continue;
+            };
+
+            // `<%-` drops the indentation before a tag that starts its line
+            if trim_before {
+                let trimmed = literal.trim_end_matches([' ', '\t']);
+                let starts_line = if trimmed.is_empty() {
+                    pos == 0 || template[..pos].ends_with('\n')
+                } else {
+                    trimmed.ends_with('\n')
+                };
+                if starts_line {
+                    literal = trimmed;
+                }
+            }
+            self.literal(literal);
+            self.output.push_str(&translated);
+
+            pos = end;
+            // `-%>` drops the newline after the tag
+            if trim_after {
+                if template[pos..].starts_with("\r\n") {
+                    pos += 2;
+                } else if template[pos..].starts_with('\n') {
+                    pos += 1;
+                }
+            }
+        }
+        self.literal(&template[pos..]);
+
+        if let Some((block, line)) = self.blocks.first() {
+            return Err(error(
+                *line,
+                format!(
+                    "`<% {} %>` is never closed with `<% end %>`",
+                    block.helper()
+                ),
+            ));
+        }
+        Ok(self.output)
+    }
+
+    /// Adds text from outside the tags, escaped so handlebars keeps it as it
+    /// is when the whole template is ERB.
+    fn literal(&mut self, text: &str) {
+        if self.strict {
+            self.output.push_str(&text.replace("{{", "\\{{"));
+        } else {
+            self.output.push_str(text);
+        }
+    }
+
+    /// Translates a `<% %>` tag, opening or closing handlebars blocks.
+    fn logic(&mut self, code: &str, line: usize) -> Result<String, DropkickError> {
+        let code = code.strip_suffix(" then").unwrap_or(code).trim();
+        let unsupported = || {
+            error(
+                line,
+                format!(
+                    "`<% {} %>` can't be translated, only if, unless, elsif, else, end and each loops over config values are supported",
+                    code
+                ),
+            )
+        };
+
+        if let Some(condition) = code.strip_prefix("if ") {
+            let condition = self.condition(condition).ok_or_else(unsupported)?;
+            self.blocks.push((Block::If, line));
+            return Ok(format!("{{{{#if {}}}}}", condition));
+        }
+        if let Some(condition) = code.strip_prefix("unless ") {
+            let condition = self.condition(condition).ok_or_else(unsupported)?;
+            self.blocks.push((Block::Unless, line));
+            return Ok(format!("{{{{#unless {}}}}}", condition));
+        }
+        if let Some(condition) = code.strip_prefix("elsif ") {
+            if !matches!(self.blocks.last(), Some((Block::If, _))) {
+                return Err(error(
+                    line,
+                    "`<% elsif %>` outside an `<% if %>`".to_string(),
+                ));
+            }
+            let condition = self.condition(condition).ok_or_else(unsupported)?;
+            return Ok(format!("{{{{else if {}}}}}", condition));
+        }
+        if code == "else" {
+            if !matches!(self.blocks.last(), Some((Block::If | Block::Unless, _))) {
+                return Err(error(
+                    line,
+                    "`<% else %>` outside an `<% if %>` or `<% unless %>`".to_string(),
+                ));
+            }
+            return Ok("{{else}}".to_string());
+        }
+        if code == "end" {
+            let Some((block, _)) = self.blocks.pop() else {
+                return Err(error(line, "`<% end %>` has no block to close".to_string()));
+            };
+            return Ok(format!("{{{{/{}}}}}", block.helper()));
+        }
+
+        let each = Regex::new(EACH_PATTERN).unwrap();
+        let for_in = Regex::new(FOR_PATTERN).unwrap();
+        let (collection, item, index) = if let Some(caps) = each.captures(code) {
+            (
+                caps.get(1).map(|m| m.as_str()),
+                caps.get(2).map(|m| m.as_str()),
+                caps.get(3).map(|m| m.as_str()),
+            )
+        } else if let Some(caps) = for_in.captures(code) {
+            (
+                caps.get(2).map(|m| m.as_str()),
+                caps.get(1).map(|m| m.as_str()),
+                None,
+            )
+        } else {
+            return Err(unsupported());
+        };
+        let collection = collection
+            .and_then(|c| self.path(c.trim()))
+            .ok_or_else(unsupported)?;
+        let names: Vec<String> = item.into_iter().chain(index).map(String::from).collect();
+        let each = format!("{{{{#each {} as |{}|}}}}", collection, names.join(" "));
+        self.blocks.push((Block::Each(names), line));
+        Ok(each)
+    }
+
+    /// A Ruby condition as a handlebars one: operands compared with `==` or
+    /// `!=`, negated with `!` and joined by `&&` or `||` (not both).
+    fn condition(&self, condition: &str) -> Option<String> {
+        let condition = condition.trim();
+        let (helper, parts): (_, Vec<&str>) = if condition.contains("&&") {
+            ("and", condition.split("&&").collect())
+        } else if condition.contains("||") {
+            ("or", condition.split("||").collect())
+        } else {
+            ("", vec![condition])
+        };
+        if helper == "and" && condition.contains("||") {
+            return None;
+        }
+
+        let mut translated = parts
+            .iter()
+            .map(|part| self.comparison(part.trim()))
+            .collect::<Option<Vec<_>>>()?;
+        // The helpers take two values, so longer chains nest
+        let mut joined = translated.pop()?;
+        while let Some(previous) = translated.pop() {
+            joined = format!("({} {} {})", helper, previous, joined);
+        }
+        Some(joined)
+    }
+
+    fn comparison(&self, part: &str) -> Option<String> {
+        for (operator, helper) in [("==", "eq"), ("!=", "ne")] {
+            if let Some((left, right)) = part.split_once(operator) {
+                let left = self.operand(left.trim())?;
+                let right = self.operand(right.trim())?;
+                return Some(format!("({} {} {})", helper, left, right));
+            }
+        }
+        if let Some(negated) = part.strip_prefix('!') {
+            return Some(format!("(not {})", self.operand(negated.trim())?));
+        }
+        self.operand(part)
+    }
+
+    /// A value as a handlebars parameter: a path or a literal.
+    fn operand(&self, operand: &str) -> Option<String> {
+        let literal = match operand {
+            "nil" => Some("null".to_string()),
+            "true" | "false" => Some(operand.to_string()),
+            _ if operand.parse::<f64>().is_ok() => Some(operand.to_string()),
+            _ => string_literal(operand).map(|s| format!("{:?}", s)),
+        };
+        literal.or_else(|| self.path(operand))
+    }
+
+    /// `config[:a][:b]` as `a.b`, or `item[:name]` as `item.name` inside a
+    /// loop over `item`.
+    fn path(&self, expression: &str) -> Option<String> {
+        let caps = Regex::new(PATH_PATTERN).unwrap().captures(expression)?;
+        let head = &caps[1];
+        let mut segments: Vec<String> = Regex::new(SEGMENT_PATTERN)
+            .unwrap()
+            .captures_iter(&caps[2])
+            .map(|seg| {
+                let key = seg
+                    .iter()
+                    .skip(1)
+                    .flatten()
+                    .next()
+                    .map_or("", |m| m.as_str());
+                if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    key.to_string()
+                } else {
+                    format!("[{}]", key)
+                }
+            })
+            .collect();
+
+        if head == "config" {
+            if segments.is_empty() {
+                return None;
+            }
+        } else if self.in_loop(head) {
+            segments.insert(0, head.to_string());
+        } else {
+            return None;
+        }
+        Some(segments.join("."))
+    }
+
+    fn in_loop(&self, name: &str) -> bool {
+        self.blocks.iter().any(|(block, _)| match block {
+            Block::Each(names) => names.iter().any(|n| n == name),
+            _ => false,
+        })
+    }
+}
+
+/// The text of a `"double"` or `'single'` quoted Ruby string without
+/// interpolation.
+fn string_literal(operand: &str) -> Option<&str> {
+    let inner = operand
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .or_else(|| {
+            operand
+                .strip_prefix('\'')
+                .and_then(|s| s.strip_suffix('\''))
+        })?;
+    (!inner.contains(['"', '\'', '\\']) && !inner.contains("#{")).then_some(inner)
+}
+
+/// The `config[...]` keys read anywhere in the ERB tags of `template`.
+pub fn config_keys(template: &str) -> Vec<String> {
+    let tags = Regex::new(r"(?s)<%[^%].*?%>").unwrap();
+    let keys = Regex::new(&format!(r"config{}", SEGMENT_PATTERN)).unwrap();
+    tags.find_iter(template)
+        .flat_map(|tag| {
+            keys.captures_iter(tag.as_str())
+                .filter_map(|caps| caps.iter().skip(1).flatten().next())
+                .map(|key| key.as_str().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn error(line: usize, reason: String) -> DropkickError {
+    DropkickError::Erb { line, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_tags_are_translated() {
+        assert_eq!(
+            translate_output_tags("# <%= config[:name] %>\n"),
+            "# {{{name}}}\n"
+        );
+    }
+
+    #[test]
+    fn a_tag_closed_by_its_own_percent_is_left_alone() {
+        assert_eq!(translate_output_tags("a <%> b\n"), "a <%> b\n");
+        assert_eq!(translate_output_tags("<%>"), "<%>");
+        assert_eq!(
+            translate_output_tags("<%> <%= config[:name] %>"),
+            "<%> <%= config[:name] %>"
+        );
+        assert!(translate("a <%> b").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DropkickError {
+    #[error("'{}' isn't inside a template folder under {}", path.display(), templates_path.display())]
+    OutsideTemplates {
+        path: PathBuf,
+        templates_path: PathBuf,
+    },
+    #[error("path segment '{segment}' {reason}")]
+    PathSegment { segment: String, reason: String },
+    #[error("'{}' would be written outside the output directory", path.display())]
+    OutsideOutputDir { path: PathBuf },
+    #[error("line {line}: {reason}")]
+    Erb { line: usize, reason: String },
+    #[error("unable to parse {}: {source}", path.display())]
+    Config {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("failed to run git: {0}")]
+    Git(#[source] io::Error),
+    #[error(
+        "no GitHub user: neither project.github_user in .dropkickrc nor git config user.github or user.name is set.  Set project.github_user, or configure git with:\n\ngit config --global user.github YOUR_GH_NAME"
+    )]
+    MissingGithubUser,
+    #[error("unable to find the home directory, set HOME to it")]
+    Home,
+    #[error("unable to show the template tree: {0}")]
+    Tree(#[source] io::Error),
+    #[error("unable to read '{}' ({source})", path.display())]
+    Read { path: PathBuf, source: io::Error },
+}
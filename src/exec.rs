@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+
+use log::debug;
+use serde::Deserialize;
+
+/// `allow_exec` in `$XDG_CONFIG_HOME/dropkick/config.yaml`: whether the `sh` template
+/// helper may run commands, `true` for any or a list of the programs it may
+/// run, e.g. `[git, rustc]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum AllowExec {
+    All(bool),
+    Programs(Vec<String>),
+}
+
+impl Default for AllowExec {
+    fn default() -> Self {
+        AllowExec::All(false)
+    }
+}
+
+impl AllowExec {
+    fn allows(&self, program: &str) -> bool {
+        match self {
+            AllowExec::All(all) => *all,
+            AllowExec::Programs(programs) => programs.iter().any(|allowed| allowed == program),
+        }
+    }
+}
+
+/// Set once at startup, since the template engines have no other way of
+/// being told.  Nothing may run until it is.
+static ALLOWED: OnceLock<AllowExec> = OnceLock::new();
+
+/// Each command's output, so rendering a preview again or the same command
+/// in every file runs it just once.
+static OUTPUTS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+pub fn allow(policy: AllowExec) {
+    let _ = ALLOWED.set(policy);
+}
+
+/// Runs `command` for `{{sh "..."}}` and returns what it printed, without
+/// the trailing newline.  It isn't run by a shell, so there are no pipes,
+/// redirects or variables: it's split into words, with single or double
+/// quotes keeping spaces inside one, and the first word is the program that
+/// `allow_exec` has to allow.
+pub fn run(command: &str) -> Result<String, String> {
+    if let Some(output) = OUTPUTS.lock().unwrap().get(command) {
+        return Ok(output.clone());
+    }
+
+    let words = split_command(command)?;
+    let Some((program, args)) = words.split_first() else {
+        return Err("sh needs a command to run".to_string());
+    };
+    let allowed = ALLOWED.get().cloned().unwrap_or_default();
+    if !allowed.allows(program) {
+        return Err(match allowed {
+            AllowExec::All(_) => format!(
+                "not running '{}': the sh helper is off, pass --allow-exec or set allow_exec in config.yaml",
+                command
+            ),
+            AllowExec::Programs(_) => format!(
+                "not running '{}': {} isn't in allow_exec in config.yaml",
+                command, program
+            ),
+        });
+    }
+
+    debug!("running {:?} for the sh helper", words);
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("unable to run '{}': {}", command, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("'{}' failed ({})", command, output.status),
+            stderr => format!("'{}' failed ({}): {}", command, output.status, stderr),
+        });
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout = stdout.strip_suffix('\n').unwrap_or(&stdout);
+    let stdout = stdout.strip_suffix('\r').unwrap_or(stdout).to_string();
+    OUTPUTS
+        .lock()
+        .unwrap()
+        .insert(command.to_string(), stdout.clone());
+    Ok(stdout)
+}
+
+/// Splits `command` on whitespace, except inside single or double quotes.
+/// A quote left open is an error rather than running to the end.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_default().push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_default().push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!(
+            "not running '{}': its {} quote isn't closed",
+            command, q
+        ));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_runs_by_default() {
+        assert!(!AllowExec::default().allows("git"));
+        assert!(!AllowExec::All(false).allows("git"));
+        assert!(AllowExec::All(true).allows("git"));
+        // Never set outside main
+        let err = run("git --version").unwrap_err();
+        assert!(err.contains("the sh helper is off"), "{err}");
+    }
+
+    #[test]
+    fn only_listed_programs_run() {
+        let allowed = AllowExec::Programs(vec!["git".to_string(), "rustc".to_string()]);
+        assert!(allowed.allows("git"));
+        assert!(allowed.allows("rustc"));
+        assert!(!allowed.allows("rm"));
+        assert!(!allowed.allows("/usr/bin/git"));
+        assert!(!AllowExec::Programs(Vec::new()).allows("git"));
+    }
+
+    #[test]
+    fn quotes_keep_spaces_inside_one_word() {
+        assert_eq!(
+            split_command(r#"git log -1 --format="%an <%ae>" 'a b'c"#).unwrap(),
+            ["git", "log", "-1", "--format=%an <%ae>", "a bc"]
+        );
+        assert_eq!(
+            split_command(r#"echo "it's" '' x"#).unwrap(),
+            ["echo", "it's", "", "x"]
+        );
+        assert!(split_command("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unclosed_quotes_are_an_error() {
+        let err = split_command("echo 'a b").unwrap_err();
+        assert!(err.contains("' quote isn't closed"), "{err}");
+        assert!(split_command(r#"echo "a"#).is_err());
+    }
+}
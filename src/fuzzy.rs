@@ -0,0 +1,91 @@
+/// Where a pattern matched within some text, and how well.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// Char index in the text of each pattern char
+    pub positions: Vec<usize>,
+}
+
+const MATCH: i64 = 16;
+/// Extra for a char right after the previous match, so `main` beats `m_a_i_n`
+const CONSECUTIVE: i64 = 8;
+/// Extra for a char starting a word, so `dr` prefers `docker/run.sh`
+const BOUNDARY: i64 = 8;
+/// Taken off for each char skipped between two matches
+const GAP: i64 = 1;
+
+/// Matches `pattern`'s chars in order anywhere in `text`, fzf style.  The
+/// match is case-insensitive unless the pattern has an upper case letter, and
+/// whitespace in the pattern is ignored.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<FuzzyMatch> {
+    let pattern: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if pattern.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+
+    let case_sensitive = pattern.iter().any(|c| c.is_uppercase());
+    let same = |a: char, b: char| {
+        if case_sensitive {
+            a == b
+        } else {
+            a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    let text: Vec<char> = text.chars().collect();
+
+    // The earliest point the whole pattern has matched...
+    let mut matched = 0;
+    let mut end = None;
+    for (i, &c) in text.iter().enumerate() {
+        if same(c, pattern[matched]) {
+            matched += 1;
+            if matched == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    // ...then back from there, for the tightest match ending at that point
+    let mut positions = vec![0; pattern.len()];
+    let mut remaining = pattern.len();
+    for i in (0..=end).rev() {
+        if same(text[i], pattern[remaining - 1]) {
+            remaining -= 1;
+            positions[remaining] = i;
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    Some(FuzzyMatch {
+        score: score(&text, &positions),
+        positions,
+    })
+}
+
+fn score(text: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    for (n, &i) in positions.iter().enumerate() {
+        score += MATCH;
+        if i == 0 || is_boundary(text[i - 1], text[i]) {
+            score += BOUNDARY;
+        }
+        if n > 0 {
+            match i - positions[n - 1] - 1 {
+                0 => score += CONSECUTIVE,
+                gap => score -= GAP * gap as i64,
+            }
+        }
+    }
+    score
+}
+
+fn is_boundary(prev: char, c: char) -> bool {
+    matches!(prev, '/' | '_' | '-' | '.' | ' ') || (prev.is_lowercase() && c.is_uppercase())
+}
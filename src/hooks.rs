@@ -0,0 +1,102 @@
+use std::{error::Error, fs, path::Path, process::Command};
+
+use log::{debug, info};
+use serde_json::Value;
+
+use crate::{clean_path, import::ImportOptions, template_rendering::Renderer};
+
+/// Folder within a template holding the scripts run around generation.
+pub const HOOKS_DIR: &str = "hooks";
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    /// Runs before any file is written; failing aborts the import
+    PreGen,
+    /// Runs once every file is written, e.g. for `git init` or `cargo fmt`
+    PostGen,
+}
+
+impl Hook {
+    fn file_name(self) -> &'static str {
+        match self {
+            Hook::PreGen => "pre_gen.sh",
+            Hook::PostGen => "post_gen.sh",
+        }
+    }
+}
+
+/// Runs template folder `root`'s `hook` script, if it has one, with `sh` in
+/// the output directory.  Every template variable is exported to it as
+/// `DROPKICK_<NAME>`, e.g. `DROPKICK_PASCAL_NAME`.
+pub fn run(
+    root: &Path,
+    hook: Hook,
+    renderer: &Renderer,
+    import_options: &ImportOptions,
+) -> Result<(), Box<dyn Error>> {
+    let script = root.join(HOOKS_DIR).join(hook.file_name());
+    if !script.is_file() {
+        return Ok(());
+    }
+
+    let display = clean_path(&script);
+    if import_options.no_hooks {
+        info!("Not running {} (--no-hooks)", display.display());
+        return Ok(());
+    }
+    if import_options.dry_run {
+        info!("Would run {}", display.display());
+        return Ok(());
+    }
+
+    // A new project's directory doesn't exist until the first file is written
+    let output_dir = &import_options.output_dir;
+    if !output_dir.as_os_str().is_empty() {
+        fs::create_dir_all(output_dir)?;
+    }
+
+    info!("Running {}", display.display());
+    let mut command = Command::new("sh");
+    command
+        .arg(&script)
+        .current_dir(output_dir)
+        .envs(env_vars(&renderer.context));
+    debug!("running {:?}", command);
+
+    let status = command
+        .status()
+        .map_err(|e| format!("unable to run {}: {}", display.display(), e))?;
+    if !status.success() {
+        return Err(format!("{} failed ({})", display.display(), status).into());
+    }
+
+    Ok(())
+}
+
+/// The render context as environment variables: strings as they are, other
+/// values as JSON.
+fn env_vars(context: &Value) -> Vec<(String, String)> {
+    let Value::Object(map) = context else {
+        return Vec::new();
+    };
+
+    map.iter()
+        .map(|(key, value)| {
+            let name: String = key
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (format!("DROPKICK_{}", name), value)
+        })
+        .collect()
+}
@@ -0,0 +1,1299 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    fs::{self, create_dir_all},
+    io::{self, IsTerminal, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{Level, debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clean_path,
+    cookiecutter::is_project_dir,
+    error::DropkickError,
+    get_templates_path, journal, logging,
+    manifest::{Manifest, is_truthy},
+    shadow::{self, MergeResult},
+    stats::{self, Phase},
+    template_rendering::Renderer,
+    templates::{
+        MergeDirective, is_binary, split_flag, split_frontmatter, strip_template_suffix,
+        template_root_of,
+    },
+    transaction::Transaction,
+};
+
+/// What to do when a rendered file would land on a path that already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Leave the existing file alone
+    #[default]
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Move the existing file to `<name>.orig` before writing
+    Backup,
+    /// Abort the whole import on the first collision
+    Error,
+    /// Ask what to do for each existing file (skips when there's no terminal)
+    Prompt,
+    /// Three-way merge local edits with the newly rendered file, based on
+    /// what was rendered when it was last imported (skips files imported
+    /// before that was recorded)
+    Merge,
+}
+
+/// Where and how selected template files get written.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub output_dir: PathBuf,
+    pub on_conflict: ConflictPolicy,
+    /// Keep the template's top-level folder in the destination path
+    pub keep_root: bool,
+    /// Print what would happen instead of writing anything
+    pub dry_run: bool,
+    /// Leave out the template's `hooks/` scripts
+    pub no_hooks: bool,
+    /// Answers already given for particular destinations, e.g. in the TUI,
+    /// which take precedence over `on_conflict`
+    pub resolutions: BTreeMap<PathBuf, ConflictPolicy>,
+}
+
+impl ImportOptions {
+    pub fn policy_for(&self, dest: &Path) -> ConflictPolicy {
+        self.resolutions
+            .get(dest)
+            .copied()
+            .unwrap_or(self.on_conflict)
+    }
+}
+
+/// What importing one file did.  `copied` is set for a binary file, which
+/// is copied as it is rather than rendered.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Imported {
+        copied: bool,
+    },
+    /// Imported to a path where there was nothing before
+    Created {
+        dest: PathBuf,
+        copied: bool,
+    },
+    Skipped,
+    /// The destination exists and the conflict policy says to stop
+    Conflict(PathBuf),
+}
+
+/// Tally of a batch import.  Per-file failures are collected rather than
+/// aborting, so they're all reported at once, but any of them means nothing
+/// is written.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// How many files were rendered, copied as they are, and skipped
+    pub rendered: usize,
+    pub copied: usize,
+    pub skipped: usize,
+    /// Files that didn't exist before, for `dropkick undo`
+    pub created: Vec<PathBuf>,
+    pub failures: Vec<(PathBuf, Box<dyn Error>)>,
+    /// Nothing was imported because this was a `--dry-run`
+    pub dry_run: bool,
+}
+
+impl ImportReport {
+    /// Logs the closing `Imported: 3 of 4 file(s)` line, with a table of
+    /// how many were rendered, copied and skipped when anything was written.
+    /// A dry run already summarized its plan, so there's nothing to add.
+    pub fn log_summary(&self, verb: &str, total: usize) {
+        if self.dry_run {
+            return;
+        }
+        info!("{}: {} of {} file(s)", verb, self.imported, total);
+        if self.failures.is_empty() {
+            for (status, count) in [
+                (FileStatus::Rendered, self.rendered),
+                (FileStatus::Copied, self.copied),
+                (FileStatus::Skipped, self.skipped),
+            ] {
+                info!("  {:<10}{:>6}", status, count);
+            }
+        }
+        info!("");
+    }
+
+    /// Lists every file that failed, folding them into a single error so the
+    /// process exits non-zero.
+    pub fn check_failures(&self) -> Result<(), Box<dyn Error>> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+
+        error!("Failed to import {} file(s):", self.failures.len());
+        for (path, e) in &self.failures {
+            error!("  • {}: {}", clean_path(path).to_string_lossy(), e);
+        }
+
+        Err(format!(
+            "{} file(s) failed to import, nothing was written",
+            self.failures.len()
+        )
+        .into())
+    }
+}
+
+/// Renders each file in turn into a staging directory, and only once they all
+/// have moves them into place, so a failure (or a conflict under
+/// `ConflictPolicy::Error`) leaves the output directory as it was.  The files
+/// created are recorded in its journal, replacing the last import's.
+pub fn import_template_files(
+    files: &[PathBuf],
+    renderer: &Renderer,
+    options: &ImportOptions,
+) -> Result<ImportReport, Box<dyn Error>> {
+    let mut report = ImportReport::default();
+
+    if options.dry_run {
+        print_import_plan(files, renderer, options);
+        report.dry_run = true;
+        return Ok(report);
+    }
+
+    // A `{{#each list}}` file stands for one file per item
+    let mut expanded = Vec::new();
+    for file in files {
+        match renderer.for_each_item(file) {
+            Ok(renderers) => {
+                expanded.extend(renderers.into_iter().map(|renderer| (file, renderer)))
+            }
+            Err(e) => report.failures.push((file.clone(), e)),
+        }
+    }
+
+    let mut transaction = Transaction::new(&options.output_dir)?;
+    let progress = ImportProgress::start(expanded.len());
+    for (file, renderer) in &expanded {
+        let status = match import_selected_template_file(file, renderer, options, &mut transaction)
+        {
+            Ok(ImportOutcome::Imported { copied }) => {
+                report.imported += 1;
+                FileStatus::written(copied)
+            }
+            Ok(ImportOutcome::Created { dest, copied }) => {
+                report.imported += 1;
+                report.created.push(dest);
+                FileStatus::written(copied)
+            }
+            Ok(ImportOutcome::Skipped) => FileStatus::Skipped,
+            Ok(ImportOutcome::Conflict(dest)) => {
+                progress.finish();
+                return Err(format!(
+                    "'{}' already exists locally, aborting import",
+                    dest.to_string_lossy()
+                )
+                .into());
+            }
+            Err(e) => {
+                debug!("failed to import {}: {}", file.display(), e);
+                report.failures.push(((*file).clone(), e));
+                FileStatus::Failed
+            }
+        };
+        match status {
+            FileStatus::Rendered => report.rendered += 1,
+            FileStatus::Copied => report.copied += 1,
+            FileStatus::Skipped => report.skipped += 1,
+            FileStatus::Failed => {}
+        }
+        progress.advance(file, status);
+    }
+    progress.finish();
+
+    if !report.failures.is_empty() {
+        report.imported = 0;
+        report.created.clear();
+        return Ok(report);
+    }
+    stats::timed(Phase::Write, || transaction.commit())?;
+
+    // Even when nothing was created, so undo doesn't reach back to an
+    // earlier import
+    if let Err(e) = journal::record(&options.output_dir, &report.created) {
+        warn!("Unable to record the created files for undo: {}", e);
+    }
+
+    Ok(report)
+}
+
+/// What happened to one file of an import, as the progress bar and the
+/// summary put it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Rendered,
+    /// A binary file, copied as it is
+    Copied,
+    Skipped,
+    Failed,
+}
+
+impl FileStatus {
+    fn written(copied: bool) -> Self {
+        if copied {
+            FileStatus::Copied
+        } else {
+            FileStatus::Rendered
+        }
+    }
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            FileStatus::Rendered => "rendered",
+            FileStatus::Copied => "copied",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Failed => "failed",
+        };
+        f.pad(label)
+    }
+}
+
+/// Imports of at least this many files show a progress bar.
+const PROGRESS_MIN_FILES: usize = 20;
+
+/// A progress bar on stderr for a big import, naming each file as it's
+/// done.  Log lines are written above it while it's up.  It isn't shown
+/// for a small import, under `--quiet`, or when stderr isn't a terminal.
+struct ImportProgress {
+    bar: ProgressBar,
+}
+
+impl ImportProgress {
+    fn start(total: usize) -> Self {
+        if total < PROGRESS_MIN_FILES
+            || !logging::shows_on_stderr(Level::Info)
+            || !io::stderr().is_terminal()
+        {
+            return Self {
+                bar: ProgressBar::hidden(),
+            };
+        }
+
+        let bar = ProgressBar::new(total as u64).with_style(
+            ProgressStyle::with_template("{bar:40.cyan/blue} {pos}/{len} {msg}")
+                .expect("Invalid progress bar template"),
+        );
+        logging::show_progress(Some(bar.clone()));
+        Self { bar }
+    }
+
+    fn advance(&self, file: &Path, status: FileStatus) {
+        self.bar.inc(1);
+        self.bar
+            .set_message(format!("{} {}", status, clean_path(file).to_string_lossy()));
+    }
+
+    fn finish(&self) {
+        self.bar.finish_and_clear();
+        logging::show_progress(None);
+    }
+}
+
+/// What importing a file would do, worked out without writing anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlannedAction {
+    Create,
+    Update,
+    /// Rendered into a marked section of another file
+    Merge,
+    Skip,
+    /// The file couldn't be read or rendered, or a conflict would abort
+    Error,
+}
+
+/// Tally of planned actions, for reporting what an import would do.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub errored: usize,
+}
+
+impl ImportCounts {
+    pub fn add(&mut self, action: PlannedAction) {
+        match action {
+            PlannedAction::Create => self.created += 1,
+            PlannedAction::Update | PlannedAction::Merge => self.updated += 1,
+            PlannedAction::Skip => self.skipped += 1,
+            PlannedAction::Error => self.errored += 1,
+        }
+    }
+}
+
+/// Runs the full resolution and render pipeline for one file in memory and
+/// reports what importing it would do.  As in the importer, a render failure
+/// counts as an error.
+pub fn plan_template_file(
+    src_path: &Path,
+    renderer: &Renderer,
+    options: &ImportOptions,
+) -> PlannedAction {
+    let Ok(dest) = compute_destination(
+        src_path,
+        &get_templates_path(),
+        &options.output_dir,
+        options.keep_root,
+        Some(renderer),
+    ) else {
+        return PlannedAction::Error;
+    };
+
+    match skip_reason(src_path, renderer) {
+        Ok(None) => {}
+        Ok(Some(_)) => return PlannedAction::Skip,
+        Err(_) => return PlannedAction::Error,
+    }
+
+    let Ok(bytes) = fs::read(src_path) else {
+        return PlannedAction::Error;
+    };
+    let Ok(binary) = is_binary(src_path, &bytes) else {
+        return PlannedAction::Error;
+    };
+    if !binary {
+        let content = String::from_utf8_lossy(&bytes);
+        let Ok((frontmatter, body)) = split_frontmatter(&content) else {
+            return PlannedAction::Error;
+        };
+        if stats::timed(Phase::Render, || renderer.render(body)).is_err() {
+            return PlannedAction::Error;
+        }
+
+        // Merging edits the target in place whatever the conflict policy
+        if let Some(merge) = &frontmatter.merge {
+            return match merge_target(&dest, merge) {
+                Ok(_) => PlannedAction::Merge,
+                Err(_) => PlannedAction::Error,
+            };
+        }
+    }
+
+    if !dest.exists() {
+        return PlannedAction::Create;
+    }
+
+    // Nobody can answer a prompt while planning, so it's planned as the
+    // answer given without a terminal
+    match options.policy_for(&dest) {
+        ConflictPolicy::Skip | ConflictPolicy::Prompt => PlannedAction::Skip,
+        ConflictPolicy::Overwrite | ConflictPolicy::Backup => PlannedAction::Update,
+        ConflictPolicy::Error => PlannedAction::Error,
+        ConflictPolicy::Merge if binary => PlannedAction::Skip,
+        ConflictPolicy::Merge => match shadow::read(&options.output_dir, &dest) {
+            Ok(Some(_)) => PlannedAction::Update,
+            Ok(None) => PlannedAction::Skip,
+            Err(_) => PlannedAction::Error,
+        },
+    }
+}
+
+/// Prints what importing `files` would do as a tree of destinations, for
+/// `--dry-run`.  Nothing on disk is touched.
+fn print_import_plan(files: &[PathBuf], renderer: &Renderer, options: &ImportOptions) {
+    let templates_path = get_templates_path();
+    let mut counts = ImportCounts::default();
+
+    let mut planned: Vec<(PathBuf, PlannedAction, &PathBuf, Cow<Renderer>)> = Vec::new();
+    for file in files {
+        let Ok(renderers) = renderer.for_each_item(file) else {
+            counts.add(PlannedAction::Error);
+            planned.push((
+                file.clone(),
+                PlannedAction::Error,
+                file,
+                Cow::Borrowed(renderer),
+            ));
+            continue;
+        };
+        for renderer in renderers {
+            let action = plan_template_file(file, &renderer, options);
+            counts.add(action);
+            let dest = compute_destination(
+                file,
+                &templates_path,
+                &options.output_dir,
+                options.keep_root,
+                Some(&renderer),
+            )
+            .unwrap_or_else(|_| file.clone());
+            planned.push((dest, action, file, renderer));
+        }
+    }
+    planned.sort_by(|a, b| a.0.cmp(&b.0));
+
+    info!("Dry run, nothing will be written:");
+
+    let mut previous_dirs: Vec<&OsStr> = Vec::new();
+    for (dest, action, file, renderer) in &planned {
+        let parts: Vec<&OsStr> = dest.iter().collect();
+        let Some((file_name, dirs)) = parts.split_last() else {
+            continue;
+        };
+
+        // Only print the folders that differ from the previous entry's
+        let shared = previous_dirs
+            .iter()
+            .zip(dirs)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, dir) in dirs.iter().enumerate().skip(shared) {
+            info!("{}{}/", "  ".repeat(depth + 1), dir.to_string_lossy());
+        }
+
+        let skipped_because = match action {
+            PlannedAction::Skip => skip_reason(file, renderer).ok().flatten(),
+            _ => None,
+        };
+        let label = match action {
+            PlannedAction::Create => "create",
+            PlannedAction::Update if options.policy_for(dest) == ConflictPolicy::Backup => {
+                "overwrite, backing up to .orig"
+            }
+            PlannedAction::Update if options.policy_for(dest) == ConflictPolicy::Merge => {
+                "merge with local edits"
+            }
+            PlannedAction::Update => "overwrite",
+            PlannedAction::Merge => "merge",
+            PlannedAction::Skip if skipped_because.is_some() => "skip",
+            PlannedAction::Skip if options.policy_for(dest) == ConflictPolicy::Prompt => {
+                "ask, already exists"
+            }
+            PlannedAction::Skip if options.policy_for(dest) == ConflictPolicy::Merge => {
+                "skip, nothing recorded to merge with"
+            }
+            PlannedAction::Skip => "skip, already exists",
+            PlannedAction::Error => "error",
+        };
+        let label = match skipped_because {
+            Some(reason) => format!("{}, {}", label, reason),
+            None => label.to_string(),
+        };
+        info!(
+            "{}{}  ({})",
+            "  ".repeat(dirs.len() + 1),
+            file_name.to_string_lossy(),
+            label
+        );
+
+        previous_dirs = dirs.to_vec();
+    }
+
+    info!(
+        "Would create {}, update {}, skip {}; {} with errors",
+        counts.created, counts.updated, counts.skipped, counts.errored
+    );
+}
+
+/// A destination that more than one selected file would render to, which
+/// would otherwise end in a silent last-writer-wins overwrite.
+#[derive(Debug)]
+pub struct DestinationCollision {
+    pub dest: PathBuf,
+    pub sources: Vec<PathBuf>,
+}
+
+impl fmt::Display for DestinationCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let templates_path = get_templates_path();
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|src| {
+                src.strip_prefix(&templates_path)
+                    .unwrap_or(src)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+
+        write!(
+            f,
+            "'{}' would be written by {}",
+            self.dest.to_string_lossy(),
+            sources.join(", ")
+        )
+    }
+}
+
+/// Finds selected files that map onto the same destination.  This only
+/// looks within the selection; files already on disk are the conflict
+/// policy's concern.
+pub fn find_destination_collisions(
+    files: &[PathBuf],
+    renderer: &Renderer,
+    options: &ImportOptions,
+) -> Vec<DestinationCollision> {
+    let templates_path = get_templates_path();
+    let mut by_dest: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+
+    // Only one of `main.rs.tt[bin]` and `main.rs.tt[!bin]` is ever generated
+    let generated = files
+        .iter()
+        .filter(|file| !matches!(skip_reason(file, renderer), Ok(Some(_))));
+
+    for file in generated {
+        if let Ok(dest) = compute_destination(
+            file,
+            &templates_path,
+            &options.output_dir,
+            options.keep_root,
+            Some(renderer),
+        ) {
+            by_dest.entry(dest).or_default().push(file.clone());
+        }
+    }
+
+    by_dest
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(dest, sources)| DestinationCollision { dest, sources })
+        .collect()
+}
+
+/// Files from several templates layered in one run.
+#[derive(Debug, Default)]
+pub struct LayeredFiles {
+    pub files: Vec<PathBuf>,
+    /// Each file left out, with the file from a later template that replaces it
+    pub overridden: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Layers templates on top of each other.  `layers` holds each template's
+/// files, base template first; where files from different layers land on the
+/// same destination, only the one from the latest layer is kept.  Files whose
+/// condition is off never override anything.
+pub fn layer_templates(
+    layers: &[Vec<PathBuf>],
+    renderer: &Renderer,
+    options: &ImportOptions,
+) -> LayeredFiles {
+    let templates_path = get_templates_path();
+    let destination = |file: &Path| {
+        if matches!(skip_reason(file, renderer), Ok(Some(_))) {
+            return None;
+        }
+        compute_destination(
+            file,
+            &templates_path,
+            &options.output_dir,
+            options.keep_root,
+            Some(renderer),
+        )
+        .ok()
+    };
+
+    let mut top: BTreeMap<PathBuf, (usize, &PathBuf)> = BTreeMap::new();
+    for (layer, files) in layers.iter().enumerate() {
+        for file in files {
+            if let Some(dest) = destination(file) {
+                top.insert(dest, (layer, file));
+            }
+        }
+    }
+
+    let mut layered = LayeredFiles::default();
+    for (layer, files) in layers.iter().enumerate() {
+        for file in files {
+            match destination(file).and_then(|dest| top.get(&dest)) {
+                Some((top_layer, winner)) if *top_layer != layer => {
+                    layered.overridden.push((file.clone(), (*winner).clone()));
+                }
+                _ => layered.files.push(file.clone()),
+            }
+        }
+    }
+
+    layered
+}
+
+/// Works out where a template file lands: its path below `template_root`,
+/// minus the leading template folder (unless `keep_root` is set) and the
+/// `.tt` suffix, joined onto `output_dir`.  With a `renderer`, placeholders in
+/// each segment are rendered too, so `src/{{underscored_name}}/mod.rs.tt`
+/// lands in the project's own folder.  Nothing on disk is checked.
+pub fn compute_destination(
+    src: &Path,
+    template_root: &Path,
+    output_dir: &Path,
+    keep_root: bool,
+    renderer: Option<&Renderer>,
+) -> Result<PathBuf, DropkickError> {
+    let outside_templates = || DropkickError::OutsideTemplates {
+        path: src.to_path_buf(),
+        templates_path: template_root.to_path_buf(),
+    };
+
+    let relative = src
+        .strip_prefix(template_root)
+        .map_err(|_| outside_templates())?;
+
+    // Remove the first segment (template folder), and for a cookiecutter
+    // template the `{{cookiecutter.project_slug}}` folder inside it too
+    let mut skip = if keep_root { 0 } else { 1 };
+    if !keep_root
+        && relative.iter().count() > 2
+        && is_project_dir(&template_root.join(relative.iter().take(2).collect::<PathBuf>()))
+    {
+        skip = 2;
+    }
+    let mut dest = relative.iter().skip(skip).collect::<PathBuf>();
+    if relative.iter().count() < 2 {
+        return Err(outside_templates());
+    }
+
+    // Remove `.tt` suffix
+    if let Some(name) = dest.file_name().and_then(|n| n.to_str()) {
+        let name = strip_template_suffix(name).unwrap_or(name).to_string();
+        dest.set_file_name(name);
+    }
+
+    if let Some(renderer) = renderer {
+        dest = render_path(&dest, renderer)?;
+    }
+    // e.g. `../../etc/foo.tt`, which only the renderer would otherwise catch
+    if !dest.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(DropkickError::OutsideOutputDir { path: dest });
+    }
+
+    Ok(output_dir.join(dest))
+}
+
+/// Makes sure writing `dest` doesn't go through a symlinked directory that
+/// points outside `output_dir`, checked against the deepest part of the path
+/// that exists so far.
+pub fn check_inside_output_dir(dest: &Path, output_dir: &Path) -> Result<(), DropkickError> {
+    let resolve = |path: &Path| {
+        let path = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        path.ancestors().find_map(|p| p.canonicalize().ok())
+    };
+
+    // A new project's directory doesn't exist until the first file is written
+    let Some(root) = resolve(output_dir) else {
+        return Ok(());
+    };
+    match dest.parent().and_then(resolve) {
+        Some(parent) if parent.starts_with(&root) => Ok(()),
+        _ => Err(DropkickError::OutsideOutputDir {
+            path: dest.to_path_buf(),
+        }),
+    }
+}
+
+/// Renders each segment of a relative path on its own.  A segment has to stay
+/// a single plain name once rendered, so a value like `../x` can't move a file
+/// out of the output directory.
+pub fn render_path(path: &Path, renderer: &Renderer) -> Result<PathBuf, DropkickError> {
+    path.iter()
+        .map(|segment| {
+            let segment = segment.to_string_lossy();
+            let invalid = |reason: &str| DropkickError::PathSegment {
+                segment: segment.to_string(),
+                reason: reason.to_string(),
+            };
+
+            let rendered = renderer
+                .render_name(&segment)
+                .map_err(|e| invalid(&format!("couldn't be rendered ({})", e)))?;
+
+            if rendered.is_empty() || rendered == "." || rendered == ".." {
+                return Err(invalid(&format!("renders to '{}'", rendered)));
+            }
+            if rendered.contains(['/', '\\']) {
+                return Err(invalid(&format!(
+                    "renders to '{}', which contains a path separator",
+                    rendered
+                )));
+            }
+
+            Ok(rendered)
+        })
+        .collect()
+}
+
+/// Renders one file and stages what importing it writes in `transaction`.
+pub fn import_selected_template_file(
+    src_path: &Path,
+    renderer: &Renderer,
+    options: &ImportOptions,
+    transaction: &mut Transaction,
+) -> Result<ImportOutcome, Box<dyn Error>> {
+    let dest = compute_destination(
+        src_path,
+        &get_templates_path(),
+        &options.output_dir,
+        options.keep_root,
+        Some(renderer),
+    )?;
+    debug!("{} -> {}", src_path.display(), dest.display());
+    check_inside_output_dir(&dest, &options.output_dir)?;
+
+    if let Some(reason) = skip_reason(src_path, renderer)? {
+        info!(
+            "  • {} (skipped, {})",
+            clean_path(src_path).to_string_lossy(),
+            reason
+        );
+        return Ok(ImportOutcome::Skipped);
+    }
+
+    // Render before touching the destination, so a file that can't be read
+    // doesn't leave a half-finished backup or empty directories behind
+    let bytes = fs::read(src_path).map_err(|e| format!("unable to read template file ({})", e))?;
+    // Images, fonts and the like are copied byte for byte
+    let binary = is_binary(src_path, &bytes)?;
+    trace!(
+        "read {} bytes from {}, binary: {}",
+        bytes.len(),
+        src_path.display(),
+        binary
+    );
+    let mut interpolated = None;
+    if !binary {
+        let content = std::str::from_utf8(&bytes)?;
+        let (frontmatter, body) = split_frontmatter(content)?;
+        // A file that doesn't render fails the import, rather than landing
+        // half-templated
+        let rendered = stats::timed(Phase::Render, || renderer.render(body))
+            .map_err(|e| format!("unable to render template file ({})", e))?;
+
+        if let Some(merge) = &frontmatter.merge {
+            merge_section_into(&dest, &rendered, merge, src_path, options, transaction)?;
+            return Ok(ImportOutcome::Imported { copied: false });
+        }
+        interpolated = Some(rendered);
+    }
+
+    let mut note = "";
+    let mut merged = None;
+    let existed = dest.exists();
+    if existed {
+        let mut policy = options.policy_for(&dest);
+        if policy == ConflictPolicy::Prompt {
+            policy = ask_conflict_policy(&dest)?;
+        }
+        debug!("{} exists, conflict policy {:?}", dest.display(), policy);
+
+        match policy {
+            ConflictPolicy::Skip | ConflictPolicy::Prompt => {
+                warn!(
+                    "Skipping copy of '{}' because file existed locally.",
+                    dest.to_string_lossy()
+                );
+                return Ok(ImportOutcome::Skipped);
+            }
+            ConflictPolicy::Overwrite => note = " (overwrote existing file)",
+            ConflictPolicy::Backup => {
+                transaction.backup(&dest);
+                note = " (existing file backed up to .orig)";
+            }
+            ConflictPolicy::Error => return Ok(ImportOutcome::Conflict(dest)),
+            ConflictPolicy::Merge => {
+                let Some(interpolated) = &interpolated else {
+                    warn!(
+                        "Skipping copy of '{}' because file existed locally and binary files can't be merged.",
+                        dest.to_string_lossy()
+                    );
+                    return Ok(ImportOutcome::Skipped);
+                };
+                let Some(base) = shadow::read(&options.output_dir, &dest)? else {
+                    warn!(
+                        "Skipping copy of '{}' because file existed locally and there's no record of what was imported to merge with.",
+                        dest.to_string_lossy()
+                    );
+                    return Ok(ImportOutcome::Skipped);
+                };
+                let local = fs::read_to_string(&dest).map_err(|source| DropkickError::Read {
+                    path: dest.clone(),
+                    source,
+                })?;
+
+                match shadow::three_way_merge(&base, &local, interpolated) {
+                    MergeResult::Clean(content) => {
+                        note = " (merged with local edits)";
+                        merged = Some(content);
+                    }
+                    MergeResult::Conflicted(content) => {
+                        warn!(
+                            "'{}' has conflicting local edits, look for the <<<<<<< markers",
+                            dest.to_string_lossy()
+                        );
+                        note = " (merged, with conflicts)";
+                        merged = Some(content);
+                    }
+                }
+            }
+        }
+    }
+
+    let written = match (&merged, &interpolated) {
+        (Some(merged), _) => merged.as_bytes(),
+        (None, Some(interpolated)) => interpolated.as_bytes(),
+        (None, None) => &bytes,
+    };
+    trace!("staging {} bytes for {}", written.len(), dest.display());
+    transaction.write(&dest, written)?;
+    if let Some(mode) = file_mode(src_path)? {
+        transaction.set_mode(&dest, mode)?;
+    }
+    // The next merge needs what the template rendered, not what was written
+    if let Some(interpolated) = &interpolated
+        && let Some(shadow) = shadow::shadow_path(&options.output_dir, &dest)
+        && let Err(e) = transaction.write(&shadow, interpolated)
+    {
+        warn!(
+            "Unable to record what was imported to '{}': {}",
+            dest.to_string_lossy(),
+            e
+        );
+    }
+
+    let display_path = clean_path(src_path);
+    let binary_note = if binary {
+        " (binary, copied as is)"
+    } else {
+        ""
+    };
+    info!(
+        "  • {}{}{}",
+        display_path.to_string_lossy(),
+        binary_note,
+        note
+    );
+
+    if existed {
+        Ok(ImportOutcome::Imported { copied: binary })
+    } else {
+        Ok(ImportOutcome::Created {
+            dest,
+            copied: binary,
+        })
+    }
+}
+
+/// Stages `rendered` into the section of the file a `merge` directive names.
+fn merge_section_into(
+    dest: &Path,
+    rendered: &str,
+    merge: &MergeDirective,
+    src_path: &Path,
+    options: &ImportOptions,
+    transaction: &mut Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let target = merge_target(dest, merge)?;
+    check_inside_output_dir(&target, &options.output_dir)?;
+    let existing = match transaction.read(&target) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(format!("unable to read '{}' ({})", target.to_string_lossy(), e).into());
+        }
+    };
+    transaction.write(&target, merge_section(&existing, rendered, merge))?;
+    info!(
+        "  • {} (merged into {})",
+        clean_path(src_path).to_string_lossy(),
+        target.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+/// Why `src_path` isn't generated this time, when the `[flag]` at the end of
+/// its name or a `when` rule in its template's `dropkick.yaml` is off.
+pub fn skip_reason(src_path: &Path, renderer: &Renderer) -> Result<Option<String>, Box<dyn Error>> {
+    let name = src_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    if let (_, Some(flag)) = split_flag(name) {
+        let (key, wanted) = match flag.strip_prefix('!') {
+            Some(key) => (key, false),
+            None => (flag, true),
+        };
+        if renderer.flag(key) != wanted {
+            return Ok(Some(format!("only generated when {} is {}", key, wanted)));
+        }
+    }
+
+    let Some(root) = template_root_of(src_path) else {
+        return Ok(None);
+    };
+    let relative = src_path.strip_prefix(&root).unwrap_or(src_path);
+    if let Some(rule) = Manifest::load(&root)?.rule_for(relative)
+        && let Some(when) = &rule.when
+        && !is_truthy(&renderer.render(when)?)
+    {
+        return Ok(Some(format!("only generated when {}", when)));
+    }
+
+    Ok(None)
+}
+
+/// Unix permissions for the file generated from `src_path`: the `mode` its
+/// template's `dropkick.yaml` gives it, or else the template file's own, so
+/// scripts stay executable.  `None` where there are no such permissions.
+pub fn file_mode(src_path: &Path) -> Result<Option<u32>, Box<dyn Error>> {
+    if let Some(root) = template_root_of(src_path) {
+        let relative = src_path.strip_prefix(&root).unwrap_or(src_path);
+        if let Some(mode) = Manifest::load(&root)?
+            .rule_for(relative)
+            .and_then(|rule| rule.mode)
+        {
+            return Ok(Some(mode));
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(Some(fs::metadata(src_path)?.permissions().mode() & 0o7777))
+    }
+    #[cfg(not(unix))]
+    Ok(None)
+}
+
+/// Asks on the terminal what to do with an existing file.  Without a
+/// terminal to ask on, the file is kept.
+fn ask_conflict_policy(dest: &Path) -> Result<ConflictPolicy, Box<dyn Error>> {
+    if !io::stdin().is_terminal() {
+        return Ok(ConflictPolicy::Skip);
+    }
+
+    loop {
+        print!(
+            "'{}' already exists.  [o]verwrite, [s]kip or [b]ackup to .orig? ",
+            dest.to_string_lossy()
+        );
+        io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(ConflictPolicy::Skip);
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "o" | "overwrite" => return Ok(ConflictPolicy::Overwrite),
+            "s" | "skip" | "" => return Ok(ConflictPolicy::Skip),
+            "b" | "backup" => return Ok(ConflictPolicy::Backup),
+            _ => {}
+        }
+    }
+}
+
+/// Resolves a merge directive's `into` against the directory `dest` would
+/// have landed in.  The target has to stay below that directory.
+pub fn merge_target(dest: &Path, merge: &MergeDirective) -> Result<PathBuf, String> {
+    if !merge
+        .into
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!(
+            "merge target '{}' must be a relative path without '..'",
+            merge.into.to_string_lossy()
+        ));
+    }
+
+    Ok(dest.parent().unwrap_or(Path::new("")).join(&merge.into))
+}
+
+/// Replaces whatever sits between the directive's markers in `target` with
+/// `rendered`, leaving the rest of the file intact.  When the markers aren't
+/// there yet they're appended, creating the file if needed.
+pub fn merge_into_file(
+    target: &Path,
+    rendered: &str,
+    merge: &MergeDirective,
+) -> Result<(), Box<dyn Error>> {
+    let existing = match fs::read_to_string(target) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(format!("unable to read '{}' ({})", target.to_string_lossy(), e).into());
+        }
+    };
+
+    write_file(target, &merge_section(&existing, rendered, merge))?;
+
+    Ok(())
+}
+
+/// `existing` with the directive's section replaced by `rendered`, or added
+/// at the end when its markers aren't there yet.
+fn merge_section(existing: &str, rendered: &str, merge: &MergeDirective) -> String {
+    let (begin, end) = &merge.between;
+    let mut section = rendered.to_string();
+    if !section.is_empty() && !section.ends_with('\n') {
+        section.push('\n');
+    }
+
+    let markers = existing.find(begin.as_str()).and_then(|start| {
+        let inner = start + begin.len();
+        existing[inner..]
+            .find(end.as_str())
+            .map(|offset| (inner, inner + offset))
+    });
+
+    match markers {
+        Some((inner, end_start)) => {
+            format!(
+                "{}\n{}{}",
+                &existing[..inner],
+                section,
+                &existing[end_start..]
+            )
+        }
+        None => {
+            let mut merged = existing.to_string();
+            if !merged.is_empty() && !merged.ends_with('\n') {
+                merged.push('\n');
+            }
+            merged.push_str(&format!("{}\n{}{}\n", begin, section, end));
+            merged
+        }
+    }
+}
+
+/// Writes `content` to `dest`, creating parent directories as needed.
+fn write_file(dest: &Path, content: &str) -> Result<(), String> {
+    stats::timed(Phase::Write, || {
+        if let Some(parent) = dest.parent() {
+            create_dir_all(parent)
+                .map_err(|e| format!("unable to create parent directories ({})", e))?;
+        }
+
+        fs::write(dest, content)
+            .map_err(|e| format!("unable to write '{}' ({})", dest.to_string_lossy(), e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        TEMPLATES_ENV, config::Config, template_rendering::ContextOptions,
+        templates::MergeDirective,
+    };
+
+    fn renderer() -> Renderer {
+        let mut config = Config::new("demo");
+        config.project.github_user = Some("me".to_string());
+        let options = ContextOptions {
+            new_project: true,
+            ..Default::default()
+        };
+        Renderer::new(&config, &options).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_unreadable_file_is_collected_as_a_failure() {
+        let _env = TEMPLATES_ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let templates = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let readme = templates.path().join("web/README.md.tt");
+        let broken = templates.path().join("web/broken.txt");
+        fs::create_dir_all(readme.parent().unwrap()).unwrap();
+        fs::write(&readme, "# {{name}}\n").unwrap();
+        // Unreadable even for root, unlike a file without read permission
+        std::os::unix::fs::symlink(templates.path().join("missing"), &broken).unwrap();
+
+        // SAFETY: other tests touching the environment wait on TEMPLATES_ENV
+        unsafe { std::env::set_var("DROPKICK_TEMPLATES", templates.path()) };
+        let options = ImportOptions {
+            output_dir: output.path().to_path_buf(),
+            ..Default::default()
+        };
+        let report = import_template_files(&[readme, broken.clone()], &renderer(), &options);
+        unsafe { std::env::remove_var("DROPKICK_TEMPLATES") };
+
+        let report = report.unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, broken);
+        assert!(
+            report.failures[0].1.to_string().contains("unable to read"),
+            "{}",
+            report.failures[0].1
+        );
+        assert_eq!(report.imported, 0);
+        assert!(report.created.is_empty());
+        assert!(report.check_failures().is_err());
+        assert!(!output.path().join("README.md").exists());
+    }
+
+    #[test]
+    fn files_rendering_to_the_same_path_collide() {
+        let _env = TEMPLATES_ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let templates = tempfile::tempdir().unwrap();
+        let web = templates.path().join("web");
+        let sources = [
+            web.join("README.md.tt"),
+            web.join("README.md"),
+            web.join("{{name}}.txt"),
+            web.join("demo.txt.TT"),
+            web.join("LICENSE"),
+        ];
+        fs::create_dir_all(&web).unwrap();
+        for source in &sources {
+            fs::write(source, "").unwrap();
+        }
+
+        // SAFETY: other tests touching the environment wait on TEMPLATES_ENV
+        unsafe { std::env::set_var("DROPKICK_TEMPLATES", templates.path()) };
+        let options = ImportOptions {
+            output_dir: PathBuf::from("out"),
+            ..Default::default()
+        };
+        let collisions = find_destination_collisions(&sources, &renderer(), &options);
+        unsafe { std::env::remove_var("DROPKICK_TEMPLATES") };
+
+        assert_eq!(collisions.len(), 2);
+        assert_eq!(collisions[0].dest, Path::new("out/README.md"));
+        assert_eq!(collisions[0].sources, sources[..2]);
+        assert_eq!(collisions[1].dest, Path::new("out/demo.txt"));
+        assert_eq!(collisions[1].sources, sources[2..4]);
+    }
+
+    #[test]
+    fn keep_root_keeps_the_template_folder() {
+        let templates = Path::new("/templates");
+        let src = templates.join("web/config/app.yaml.tt");
+        let out = Path::new("out");
+
+        let flattened = compute_destination(&src, templates, out, false, None).unwrap();
+        assert_eq!(flattened, Path::new("out/config/app.yaml"));
+        let kept = compute_destination(&src, templates, out, true, None).unwrap();
+        assert_eq!(kept, Path::new("out/web/config/app.yaml"));
+    }
+
+    #[test]
+    fn keep_root_with_a_top_level_file() {
+        let templates = Path::new("/templates");
+        let src = templates.join("web/README.md");
+        let out = Path::new("out");
+
+        let flattened = compute_destination(&src, templates, out, false, None).unwrap();
+        assert_eq!(flattened, Path::new("out/README.md"));
+        let kept = compute_destination(&src, templates, out, true, None).unwrap();
+        assert_eq!(kept, Path::new("out/web/README.md"));
+    }
+
+    #[test]
+    fn files_outside_a_template_folder_are_rejected_either_way() {
+        let templates = Path::new("/templates");
+        for keep_root in [false, true] {
+            for src in ["/templates/README.md", "/elsewhere/web/README.md"] {
+                let dest = compute_destination(
+                    Path::new(src),
+                    templates,
+                    Path::new("out"),
+                    keep_root,
+                    None,
+                );
+                assert!(
+                    matches!(dest, Err(DropkickError::OutsideTemplates { .. })),
+                    "{src} with keep_root {keep_root}: {dest:?}"
+                );
+            }
+        }
+    }
+
+    fn markers() -> MergeDirective {
+        MergeDirective {
+            into: PathBuf::from("registry.rs"),
+            between: (
+                "// BEGIN dropkick".to_string(),
+                "// END dropkick".to_string(),
+            ),
+        }
+    }
+
+    #[test]
+    fn merging_replaces_the_section_between_markers() {
+        let existing = "mod a;\n// BEGIN dropkick\nmod old;\n// END dropkick\nmod z;\n";
+        assert_eq!(
+            merge_section(existing, "mod new;", &markers()),
+            "mod a;\n// BEGIN dropkick\nmod new;\n// END dropkick\nmod z;\n"
+        );
+    }
+
+    #[test]
+    fn merging_appends_markers_that_are_absent() {
+        assert_eq!(
+            merge_section("mod a;", "mod new;\n", &markers()),
+            "mod a;\n// BEGIN dropkick\nmod new;\n// END dropkick\n"
+        );
+        assert_eq!(
+            merge_section("", "mod new;\n", &markers()),
+            "// BEGIN dropkick\nmod new;\n// END dropkick\n"
+        );
+        // Without its end marker, a begin marker doesn't count
+        assert_eq!(
+            merge_section("// BEGIN dropkick\n", "mod new;\n", &markers()),
+            "// BEGIN dropkick\n// BEGIN dropkick\nmod new;\n// END dropkick\n"
+        );
+    }
+
+    #[test]
+    fn a_file_that_doesnt_render_leaves_the_output_untouched() {
+        let _env = TEMPLATES_ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let templates = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let readme = templates.path().join("web/README.md.tt");
+        let broken = templates.path().join("web/broken.txt.tt");
+        fs::create_dir_all(readme.parent().unwrap()).unwrap();
+        fs::write(&readme, "# {{name}}\n").unwrap();
+        fs::write(&broken, "{{#if name}}broken\n").unwrap();
+        fs::write(output.path().join("keep.txt"), "mine\n").unwrap();
+
+        // SAFETY: other tests touching the environment wait on TEMPLATES_ENV
+        unsafe { std::env::set_var("DROPKICK_TEMPLATES", templates.path()) };
+        let options = ImportOptions {
+            output_dir: output.path().to_path_buf(),
+            ..Default::default()
+        };
+        let files = [readme, broken.clone()];
+        let planned = plan_template_file(&broken, &renderer(), &options);
+        let report = import_template_files(&files, &renderer(), &options);
+        unsafe { std::env::remove_var("DROPKICK_TEMPLATES") };
+
+        assert_eq!(planned, PlannedAction::Error);
+        let report = report.unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].0, broken);
+        assert_eq!(report.imported, 0);
+        let left: Vec<_> = fs::read_dir(output.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["keep.txt"]);
+        assert_eq!(
+            fs::read_to_string(output.path().join("keep.txt")).unwrap(),
+            "mine\n"
+        );
+    }
+}
@@ -1,8 +1,20 @@
-use std::process::Command;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    process::{Command, Output},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
 
+use log::debug;
 use serde::Serialize;
+use serde_json::{Map, Value};
 
-#[derive(Debug, Clone, Serialize)]
+use crate::{error::DropkickError, logging::warn_once, stats};
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct InterpolationConfig {
     pub name: String,
     pub title: String,
@@ -17,18 +29,61 @@ pub struct InterpolationConfig {
     pub constant_name: String,
     pub constant_array: Vec<String>,
     pub author: String,
+    pub github_user: String,
     pub email: String,
     pub git_repo_domain: String,
     pub git_repo_url: String,
+    pub git_repo_ssh_url: String,
     pub git_repo_path: String,
     pub image_path: String,
+    pub image_tag: String,
+    pub full_image: String,
+    pub git_sha: String,
     pub registry_domain: String,
     pub registry_repo_path: String,
     pub k8s_domain: String,
+    pub project_type: String,
     pub template: String,
     pub test: bool,
     pub ext: String,
+    pub ext_upper: String,
     pub bin: bool,
+    /// Where each field's value came from, e.g. `git config user.email`,
+    /// for `dropkick vars`.  Not part of the context.
+    #[serde(skip)]
+    pub sources: BTreeMap<String, String>,
+}
+
+impl InterpolationConfig {
+    /// Names of every built-in field exposed to templates.
+    pub fn field_names() -> Vec<String> {
+        match serde_json::to_value(Self::default()) {
+            Ok(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// `name` without `prefix`, where `-` and `_` match each other so `dk-`
+/// strips `dk_tool` too, along with any separator the prefix leaves at the
+/// start (`dk` strips `dk-tool` to `tool`).  A name that doesn't start with
+/// the prefix, or is nothing but, is kept whole.
+fn unprefix(name: &str, prefix: &str) -> String {
+    let separator = |c: char| if c == '_' { '-' } else { c };
+    let matches = name.len() >= prefix.len()
+        && name
+            .chars()
+            .map(separator)
+            .zip(prefix.chars().map(separator))
+            .all(|(a, b)| a == b)
+        && name.is_char_boundary(prefix.len());
+    if !matches {
+        return name.to_string();
+    }
+    match name[prefix.len()..].trim_start_matches(['-', '_']) {
+        "" => name.to_string(),
+        rest => rest.to_string(),
+    }
 }
 
 pub struct ConfigBuilder {
@@ -38,6 +93,12 @@ pub struct ConfigBuilder {
     test: bool,
     ext: String,
     bin: bool,
+    github_user: Option<String>,
+    project_type: String,
+    image_tag: Option<String>,
+    git_sha: Option<String>,
+    inspect_repo: bool,
+    overrides: Map<String, Value>,
 }
 
 impl ConfigBuilder {
@@ -49,6 +110,70 @@ impl ConfigBuilder {
             test: false,
             ext: String::new(),
             bin: false,
+            github_user: None,
+            project_type: String::new(),
+            image_tag: None,
+            git_sha: None,
+            inspect_repo: true,
+            overrides: Map::new(),
+        }
+    }
+
+    /// Overrides the GitHub account used for repo and image paths, which
+    /// otherwise comes from `git config user.github` or `user.name`.
+    pub fn github_user(mut self, github_user: Option<String>) -> Self {
+        self.github_user = github_user;
+        self
+    }
+
+    pub fn project_type(mut self, project_type: String) -> Self {
+        self.project_type = project_type;
+        self
+    }
+
+    /// Overrides the tag in `full_image`, which otherwise is `git_sha`, or
+    /// `latest` without one.
+    pub fn image_tag(mut self, image_tag: Option<String>) -> Self {
+        self.image_tag = image_tag;
+        self
+    }
+
+    /// The short sha of HEAD, from `get_git_sha`, when the project is the
+    /// working directory's repo.
+    pub fn git_sha(mut self, git_sha: Option<String>) -> Self {
+        self.git_sha = git_sha;
+        self
+    }
+
+    /// Whether repo fields may come from the working directory's origin
+    /// remote.  Off when generating a new project elsewhere.
+    pub fn inspect_repo(mut self, inspect_repo: bool) -> Self {
+        self.inspect_repo = inspect_repo;
+        self
+    }
+
+    /// Values from `.dropkickrc` that stand in for git config, so fields
+    /// derived from e.g. `registry_domain` follow the override too.
+    pub fn overrides(mut self, overrides: Map<String, Value>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// An override for `field`, or else git config `key`, along with where
+    /// it came from.
+    fn setting(&self, git: &GitFacts, field: &str, key: &str) -> (String, String) {
+        match self.overrides.get(field) {
+            Some(Value::String(value)) => (value.clone(), "override".to_string()),
+            Some(value) => (value.to_string(), "override".to_string()),
+            None => {
+                let value = git.settings.get(key).cloned().unwrap_or_default();
+                let source = if value.is_empty() {
+                    "default".to_string()
+                } else {
+                    format!("git config {}", key)
+                };
+                (value, source)
+            }
         }
     }
 
@@ -57,22 +182,35 @@ impl ConfigBuilder {
     //     self
     // }
 
-    // pub fn test(mut self, test: bool) -> Self {
-    //     self.test = test;
-    //     self
-    // }
+    pub fn test(mut self, test: bool) -> Self {
+        self.test = test;
+        self
+    }
 
-    // pub fn ext(mut self, ext: String) -> Self {
-    //     self.ext = ext;
-    //     self
-    // }
+    pub fn ext(mut self, ext: String) -> Self {
+        self.ext = ext;
+        self
+    }
 
-    // pub fn bin(mut self, bin: bool) -> Self {
-    //     self.bin = bin;
-    //     self
-    // }
+    pub fn bin(mut self, bin: bool) -> Self {
+        self.bin = bin;
+        self
+    }
 
-    pub fn build(self) -> Result<InterpolationConfig, String> {
+    pub fn build(self) -> Result<InterpolationConfig, DropkickError> {
+        let git = GitFacts::read(&self)?;
+        self.build_with(git)
+    }
+
+    /// `build` without asking git, with fixed stand-ins for what it would
+    /// say, so the fields come out the same on every machine.
+    pub fn build_pinned(self) -> Result<InterpolationConfig, DropkickError> {
+        self.build_with(GitFacts::pinned())
+    }
+
+    /// Works out the fields from what git said, without running it again
+    /// except for the settings `.dropkickrc` doesn't override.
+    fn build_with(self, git: GitFacts) -> Result<InterpolationConfig, DropkickError> {
         let name = &self.name;
 
         // Title: "foo-bar-baz" -> "Foo Bar Baz"
@@ -92,11 +230,7 @@ impl ConfigBuilder {
             .join("");
 
         // Unprefixed name
-        let unprefixed_name = if name.starts_with(&self.prefix) {
-            name[self.prefix.len()..].to_string()
-        } else {
-            name.clone()
-        };
+        let unprefixed_name = unprefix(name, &self.prefix);
 
         // Unprefixed pascal
         let unprefixed_pascal = unprefixed_name
@@ -128,29 +262,112 @@ impl ConfigBuilder {
         let constant_array: Vec<String> =
             constant_name.split("::").map(|s| s.to_string()).collect();
 
-        // Git config values
-        let git_user_name = get_git_config("user.name")?;
-        let git_user_email = get_git_config("user.email").unwrap_or_default();
-        let registry_domain = get_git_config("user.registry-domain").unwrap_or_default();
-        let k8s_domain = get_git_config("user.k8s-domain").unwrap_or_default();
+        let mut sources = BTreeMap::new();
+        let (registry_domain, source) =
+            self.setting(&git, "registry_domain", "user.registry-domain");
+        sources.insert("registry_domain", source);
+        let (k8s_domain, source) = self.setting(&git, "k8s_domain", "user.k8s-domain");
+        sources.insert("k8s_domain", source);
 
-        let mut git_repo_domain = get_git_config("user.repo-domain").unwrap_or_default();
+        let (mut git_repo_domain, source) =
+            self.setting(&git, "git_repo_domain", "user.repo-domain");
+        sources.insert("git_repo_domain", source);
+        let GitFacts {
+            user_name: git_user_name,
+            user_email: git_user_email,
+            user_github,
+            remote,
+            ..
+        } = git;
         if git_repo_domain.is_empty() {
             git_repo_domain = "github.com".to_string();
         }
 
-        if git_user_name.is_empty() {
-            return Err(
-                "Error: git config user.name didn't return a value. You'll probably want to make sure that's configured with your github username:\n\ngit config --global user.name YOUR_GH_NAME".to_string()
+        // user.name is often a display name like "Jane Doe", which can't be
+        // used in a URL, so a separate account name is preferred when known
+        let github_user = match self.github_user.filter(|u| !u.is_empty()) {
+            Some(user) => {
+                sources.insert("github_user", "project.github_user".to_string());
+                user
+            }
+            None => match Some(user_github).filter(|u| !u.is_empty()) {
+                Some(user) => {
+                    sources.insert("github_user", "git config user.github".to_string());
+                    user
+                }
+                None => {
+                    sources.insert("github_user", "git config user.name".to_string());
+                    git_user_name.clone()
+                }
+            },
+        };
+
+        if github_user.is_empty() {
+            if !GIT_MISSING.load(Ordering::Relaxed) {
+                return Err(DropkickError::MissingGithubUser);
+            }
+            // git can't be asked, so asking the user to configure it is no help
+            warn_once(
+                "No GitHub user, leaving it out of the repo URLs and image paths.  Set project.github_user in .dropkickrc to fill them in"
+                    .to_string(),
             );
         }
 
+        // Prefer the origin remote, which knows about forks and renamed repos,
+        // over a location synthesized from user.name and the project name
+        let (git_repo_domain, repo_owner, repo_name) = match remote {
+            Some(remote) => {
+                debug!("using origin remote {:?} for repo fields", remote);
+                for field in [
+                    "git_repo_domain",
+                    "git_repo_url",
+                    "git_repo_ssh_url",
+                    "git_repo_path",
+                ] {
+                    sources.insert(field, "origin remote".to_string());
+                }
+                (remote.domain, remote.owner, remote.repo)
+            }
+            None => (git_repo_domain, github_user.clone(), name.clone()),
+        };
+
         let git_repo_path =
-            format!("{}/{}/{}", git_repo_domain, git_user_name, name).to_lowercase();
-        let git_repo_url = format!("https://{}/{}/{}", git_repo_domain, git_user_name, name);
-        let image_path = format!("{}/{}", git_user_name, name).to_lowercase();
+            format!("{}/{}/{}", git_repo_domain, repo_owner, repo_name).to_lowercase();
+        let git_repo_url = format!("https://{}/{}/{}", git_repo_domain, repo_owner, repo_name);
+        let git_repo_ssh_url = format!("git@{}:{}/{}.git", git_repo_domain, repo_owner, repo_name);
+        let image_path = format!("{}/{}", github_user, name).to_lowercase();
         let registry_repo_path = format!("{}/{}", registry_domain, image_path).to_lowercase();
 
+        let git_sha = self.git_sha.unwrap_or_default();
+        sources.insert(
+            "git_sha",
+            if git_sha.is_empty() {
+                "default"
+            } else {
+                "HEAD"
+            }
+            .to_string(),
+        );
+        let image_tag = match self.image_tag.filter(|t| !t.is_empty()) {
+            Some(tag) => {
+                sources.insert("image_tag", "--image-tag or project.image_tag".to_string());
+                tag
+            }
+            None if !git_sha.is_empty() => {
+                sources.insert("image_tag", "HEAD".to_string());
+                git_sha.clone()
+            }
+            None => {
+                sources.insert("image_tag", "default".to_string());
+                "latest".to_string()
+            }
+        };
+        let full_image = if registry_domain.is_empty() {
+            format!("{}:{}", image_path, image_tag)
+        } else {
+            format!("{}:{}", registry_repo_path, image_tag)
+        };
+
         let camel_name = if !pascal_name.is_empty() {
             let mut chars = pascal_name.chars();
             chars.next().unwrap().to_lowercase().collect::<String>() + chars.as_str()
@@ -163,23 +380,48 @@ impl ConfigBuilder {
         let makefile_path = format!("{}/{}", underscored_name, underscored_name);
 
         let author = if git_user_name.is_empty() {
+            sources.insert("author", "default".to_string());
             "TODO: Write your name".to_string()
         } else {
+            sources.insert("author", "git config user.name".to_string());
             git_user_name
         };
 
         let email = if git_user_email.is_empty() {
+            sources.insert("email", "default".to_string());
             "TODO: Write your email address".to_string()
         } else {
+            sources.insert("email", "git config user.email".to_string());
             git_user_email
         };
 
+        // `.rs`, `rs` and `RS` all mean the same extension
+        let ext = self.ext.trim_start_matches('.').to_lowercase();
+        let ext_upper = ext.to_uppercase();
+
         let k8s_domain = if k8s_domain.is_empty() {
             "k8s.domain.missing.from.gitconfig.local".to_string()
         } else {
             k8s_domain
         };
 
+        sources.insert("name", "project name".to_string());
+        sources.insert(
+            "project_type",
+            "detected in the output directory".to_string(),
+        );
+        for (field, flag, on) in [
+            ("bin", "--bin or project.bin", self.bin),
+            ("test", "--test or project.test", self.test),
+        ] {
+            sources.insert(field, if on { flag } else { "default" }.to_string());
+        }
+        // Everything else is worked out from the values above
+        let sources = sources
+            .into_iter()
+            .map(|(field, source)| (field.to_string(), source))
+            .collect();
+
         Ok(InterpolationConfig {
             name: name.clone(),
             title,
@@ -194,35 +436,157 @@ impl ConfigBuilder {
             constant_name,
             constant_array,
             author,
+            github_user,
             email,
             git_repo_domain,
             git_repo_url,
+            git_repo_ssh_url,
             git_repo_path,
             image_path,
+            image_tag,
+            full_image,
+            git_sha,
             registry_domain,
             registry_repo_path,
             k8s_domain,
+            project_type: self.project_type,
             template: self.template,
             test: self.test,
-            ext: self.ext,
+            ext,
+            ext_upper,
             bin: self.bin,
+            sources,
+        })
+    }
+}
+
+/// What `build` asks git for, gathered up front so tests can hand the
+/// builder a made-up user and remote instead.
+#[derive(Debug, Default)]
+struct GitFacts {
+    user_name: String,
+    user_email: String,
+    /// Only looked up when `project.github_user` isn't set
+    user_github: String,
+    /// The origin remote, if the builder may inspect the repo
+    remote: Option<GitRemote>,
+    /// Values of `GIT_SETTINGS` keys that no override stands in for
+    settings: BTreeMap<&'static str, String>,
+}
+
+/// Fields that `.dropkickrc` can set, or else git config key.
+const GIT_SETTINGS: &[(&str, &str)] = &[
+    ("registry_domain", "user.registry-domain"),
+    ("k8s_domain", "user.k8s-domain"),
+    ("git_repo_domain", "user.repo-domain"),
+];
+
+impl GitFacts {
+    /// A user who's the same everywhere, without a remote or settings.
+    fn pinned() -> Self {
+        Self {
+            user_name: "Dropkick Test".to_string(),
+            user_email: "test@example.com".to_string(),
+            user_github: "dropkick-test".to_string(),
+            ..Self::default()
+        }
+    }
+
+    fn read(builder: &ConfigBuilder) -> Result<Self, DropkickError> {
+        let user_name = get_git_config("user.name")?;
+        let user_email = get_git_config("user.email").unwrap_or_default();
+        let user_github = match builder.github_user.as_deref() {
+            Some(user) if !user.is_empty() => String::new(),
+            _ => get_git_config("user.github").unwrap_or_default(),
+        };
+        let remote = builder.inspect_repo.then(get_git_remote).flatten();
+        let settings = GIT_SETTINGS
+            .iter()
+            .filter(|(field, _)| !builder.overrides.contains_key(*field))
+            .map(|(_, key)| (*key, get_git_config(key).unwrap_or_default()))
+            .collect();
+        Ok(Self {
+            user_name,
+            user_email,
+            user_github,
+            remote,
+            settings,
         })
     }
 }
 
+/// Uppercases the first character of each word and lowercases the rest, so
+/// `FOO`, `foo` and `fOO` all normalize to `Foo`.  Whitespace separates words
+/// too and is kept, so `Repo Name` stays as it is.
 fn capitalize(s: &str) -> String {
-    let mut chars = s.chars();
-    match chars.next() {
-        None => String::new(),
-        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+    let mut capitalized = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            capitalized.push(c);
+            word_start = true;
+        } else if word_start {
+            capitalized.extend(c.to_uppercase());
+            word_start = false;
+        } else {
+            capitalized.extend(c.to_lowercase());
+        }
     }
+    capitalized
 }
 
-fn get_git_config(key: &str) -> Result<String, String> {
-    let output = Command::new("git")
-        .args(&["config", key])
-        .output()
-        .map_err(|e| format!("Failed to execute git command: {}", e))?;
+/// How many times a git command is spawned before giving up, and the delay
+/// before the first retry (doubled after each attempt).
+const GIT_ATTEMPTS: u32 = 3;
+const GIT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Runs git, retrying spawns that fail for transient reasons such as a busy
+/// CI agent running out of processes.  A missing git binary or a command that
+/// ran and exited non-zero is returned straight away.
+fn run_git(args: &[&str]) -> io::Result<Output> {
+    let mut delay = GIT_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        stats::count_git_invocation();
+        match Command::new("git").args(args).output() {
+            Err(e) if is_transient(&e) && attempt < GIT_ATTEMPTS => {
+                debug!("git {:?} failed ({}), retrying in {:?}", args, e, delay);
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// Set once git turns out not to be on PATH.
+static GIT_MISSING: AtomicBool = AtomicBool::new(false);
+
+/// A git config value, empty when it isn't set.  Without git on PATH every
+/// value is empty, with a warning, so the fields can still come from
+/// `.dropkickrc` or `--vars-file`.
+fn get_git_config(key: &str) -> Result<String, DropkickError> {
+    let output = match run_git(&["config", key]) {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            GIT_MISSING.store(true, Ordering::Relaxed);
+            warn_once(
+                "git isn't installed or isn't on PATH, leaving the fields from git config empty"
+                    .to_string(),
+            );
+            return Ok(String::new());
+        }
+        Err(e) => return Err(DropkickError::Git(e)),
+    };
 
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
@@ -230,3 +594,442 @@ fn get_git_config(key: &str) -> Result<String, String> {
         Ok(String::new())
     }
 }
+
+/// Marker files that identify the kind of project a directory holds, checked
+/// in order.
+const PROJECT_MARKERS: &[(&str, &str)] = &[
+    ("Cargo.toml", "rust"),
+    ("package.json", "node"),
+    ("go.mod", "go"),
+    ("pyproject.toml", "python"),
+    ("Gemfile", "ruby"),
+];
+
+/// Guesses the kind of project in `dir` from its marker files, so a generic
+/// template can adapt with `{{#if (eq project_type "rust")}}`.  Returns an
+/// empty string when nothing matches.
+pub fn detect_project_type(dir: &Path) -> String {
+    PROJECT_MARKERS
+        .iter()
+        .find(|(marker, _)| dir.join(marker).is_file())
+        .map(|(_, kind)| kind.to_string())
+        .unwrap_or_default()
+}
+
+/// Short sha of HEAD, if the working directory is a repo with commits.
+pub fn get_git_sha() -> Option<String> {
+    let output = run_git(&["rev-parse", "--short", "HEAD"]).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let sha = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!sha.is_empty()).then_some(sha)
+}
+
+/// Where a git remote lives, e.g. `github.com`, `thenotary`, `dropkick`.
+#[derive(Debug, PartialEq)]
+struct GitRemote {
+    domain: String,
+    owner: String,
+    repo: String,
+}
+
+fn get_git_remote() -> Option<GitRemote> {
+    let output = run_git(&["remote", "get-url", "origin"]).ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_git_remote(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses both URL-style remotes (`https://github.com/org/repo.git`,
+/// `ssh://git@github.com/org/repo.git`) and scp-style SSH remotes
+/// (`git@github.com:org/repo.git`).  Nested groups stay part of the owner.
+fn parse_git_remote(url: &str) -> Option<GitRemote> {
+    let url = url.trim();
+
+    let (host, path) = match url.split_once("://") {
+        Some((_scheme, rest)) => {
+            let (authority, path) = rest.split_once('/')?;
+            // Drop any `user@` prefix and `:port` suffix
+            let host = authority.rsplit('@').next()?;
+            (host.split(':').next()?, path)
+        }
+        None => {
+            let (authority, path) = url.split_once(':')?;
+            (authority.rsplit('@').next()?, path)
+        }
+    };
+
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, repo) = path.rsplit_once('/')?;
+
+    if host.is_empty() || owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+
+    Some(GitRemote {
+        domain: host.to_string(),
+        owner: owner.to_string(),
+        repo: repo.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A builder whose values don't depend on the machine's git config or
+    /// the repo the tests run in.
+    fn builder(name: &str) -> ConfigBuilder {
+        let overrides = [
+            ("registry_domain", ""),
+            ("k8s_domain", ""),
+            ("git_repo_domain", "github.com"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+        .collect();
+        ConfigBuilder::new(name.to_string(), String::new())
+            .github_user(Some("octocat".to_string()))
+            .inspect_repo(false)
+            .overrides(overrides)
+    }
+
+    #[test]
+    fn capitalize_lowercases_the_rest_of_each_word() {
+        assert_eq!(capitalize("FOO"), "Foo");
+        assert_eq!(capitalize("fOO"), "Foo");
+        assert_eq!(capitalize("foo"), "Foo");
+        assert_eq!(capitalize("repo name"), "Repo Name");
+        assert_eq!(capitalize("ÉLAN"), "Élan");
+        assert_eq!(capitalize(""), "");
+    }
+
+    #[test]
+    fn all_caps_names_are_normalized() {
+        let config = builder("FOO_BAR").build().unwrap();
+        assert_eq!(config.title, "Foo Bar");
+        assert_eq!(config.pascal_name, "FooBar");
+        assert_eq!(config.camel_name, "fooBar");
+    }
+
+    #[test]
+    fn mixed_case_names_are_normalized() {
+        let config = builder("fOO-bAR").build().unwrap();
+        assert_eq!(config.title, "Foo Bar");
+        assert_eq!(config.pascal_name, "FooBar");
+        assert_eq!(config.constant_name, "Foo::Bar");
+    }
+
+    #[test]
+    fn spaced_names_keep_each_word_capitalized() {
+        let config = builder("Repo Name").build().unwrap();
+        assert_eq!(config.title, "Repo Name");
+        assert_eq!(config.pascal_name, "Repo Name");
+    }
+
+    fn remote(domain: &str, owner: &str, repo: &str) -> Option<GitRemote> {
+        Some(GitRemote {
+            domain: domain.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+        })
+    }
+
+    #[test]
+    fn parses_scp_style_remotes() {
+        assert_eq!(
+            parse_git_remote("git@github.com:TheNotary/dropkick.git"),
+            remote("github.com", "TheNotary", "dropkick")
+        );
+        assert_eq!(
+            parse_git_remote("github.com:TheNotary/dropkick"),
+            remote("github.com", "TheNotary", "dropkick")
+        );
+    }
+
+    #[test]
+    fn parses_https_remotes_with_and_without_dot_git() {
+        let expected = remote("gitlab.example.com", "team", "app");
+        assert_eq!(
+            parse_git_remote("https://gitlab.example.com/team/app.git"),
+            expected
+        );
+        assert_eq!(
+            parse_git_remote("https://gitlab.example.com/team/app"),
+            expected
+        );
+        assert_eq!(
+            parse_git_remote("https://gitlab.example.com/team/app/\n"),
+            expected
+        );
+    }
+
+    #[test]
+    fn parses_ssh_urls_dropping_user_and_port() {
+        assert_eq!(
+            parse_git_remote("ssh://git@git.example.com:2222/team/app.git"),
+            remote("git.example.com", "team", "app")
+        );
+    }
+
+    #[test]
+    fn nested_groups_keep_the_full_owner_path() {
+        assert_eq!(
+            parse_git_remote("git@gitlab.com:group/subgroup/app.git"),
+            remote("gitlab.com", "group/subgroup", "app")
+        );
+    }
+
+    #[test]
+    fn malformed_remotes_are_rejected() {
+        for url in [
+            "",
+            "not a remote",
+            "https://github.com",
+            "https://github.com/dropkick.git",
+            "git@github.com:dropkick.git",
+            ":owner/repo",
+            "https:///owner/repo",
+            "git@github.com:/repo",
+        ] {
+            assert_eq!(parse_git_remote(url), None, "{url:?}");
+        }
+    }
+
+    #[test]
+    fn ssh_url_follows_an_https_origin() {
+        let git = GitFacts {
+            remote: parse_git_remote("https://gitlab.example.com/team/blog-post.git"),
+            ..GitFacts::default()
+        };
+        let config = builder("blog_post").build_with(git).unwrap();
+        assert_eq!(
+            config.git_repo_ssh_url,
+            "git@gitlab.example.com:team/blog-post.git"
+        );
+        assert_eq!(
+            config.git_repo_url,
+            "https://gitlab.example.com/team/blog-post"
+        );
+    }
+
+    #[test]
+    fn ssh_url_follows_an_ssh_origin() {
+        let git = GitFacts {
+            remote: parse_git_remote("git@github.com:TheNotary/dropkick.git"),
+            ..GitFacts::default()
+        };
+        let config = builder("blog_post").build_with(git).unwrap();
+        assert_eq!(
+            config.git_repo_ssh_url,
+            "git@github.com:TheNotary/dropkick.git"
+        );
+        assert_eq!(config.sources["git_repo_ssh_url"], "origin remote");
+    }
+
+    #[test]
+    fn ssh_url_without_an_origin_uses_the_github_user() {
+        let config = builder("blog_post")
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(
+            config.git_repo_ssh_url,
+            "git@github.com:octocat/blog_post.git"
+        );
+    }
+
+    fn jane_doe() -> GitFacts {
+        GitFacts {
+            user_name: "Jane Doe".to_string(),
+            user_email: "jane@example.com".to_string(),
+            user_github: "janedoe".to_string(),
+            ..GitFacts::default()
+        }
+    }
+
+    #[test]
+    fn a_spaced_display_name_stays_out_of_urls() {
+        let config = builder("blog_post")
+            .github_user(None)
+            .build_with(jane_doe())
+            .unwrap();
+        assert_eq!(config.author, "Jane Doe");
+        assert_eq!(config.github_user, "janedoe");
+        assert_eq!(config.git_repo_url, "https://github.com/janedoe/blog_post");
+        assert_eq!(config.git_repo_path, "github.com/janedoe/blog_post");
+        assert_eq!(config.image_path, "janedoe/blog_post");
+        assert_eq!(config.sources["github_user"], "git config user.github");
+    }
+
+    #[test]
+    fn project_github_user_wins_over_git_config() {
+        let config = builder("blog_post").build_with(jane_doe()).unwrap();
+        assert_eq!(config.author, "Jane Doe");
+        assert_eq!(config.github_user, "octocat");
+        assert_eq!(config.image_path, "octocat/blog_post");
+    }
+
+    #[test]
+    fn without_a_github_user_user_name_is_used() {
+        let git = GitFacts {
+            user_name: "jane".to_string(),
+            ..GitFacts::default()
+        };
+        let config = builder("blog_post")
+            .github_user(None)
+            .build_with(git)
+            .unwrap();
+        assert_eq!(config.github_user, "jane");
+        assert_eq!(config.git_repo_url, "https://github.com/jane/blog_post");
+        assert_eq!(config.sources["github_user"], "git config user.name");
+    }
+
+    /// The project type detected in a fresh directory holding `files`.
+    fn detected(files: &[&str]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        detect_project_type(dir.path())
+    }
+
+    #[test]
+    fn each_marker_file_is_detected() {
+        assert_eq!(detected(&["Cargo.toml"]), "rust");
+        assert_eq!(detected(&["package.json"]), "node");
+        assert_eq!(detected(&["go.mod"]), "go");
+        assert_eq!(detected(&["pyproject.toml"]), "python");
+        assert_eq!(detected(&["Gemfile"]), "ruby");
+    }
+
+    #[test]
+    fn the_first_marker_wins() {
+        assert_eq!(detected(&["package.json", "Cargo.toml"]), "rust");
+    }
+
+    #[test]
+    fn nothing_is_detected_without_a_marker() {
+        assert_eq!(detected(&[]), "");
+        assert_eq!(detected(&["README.md"]), "");
+
+        // A directory named like a marker isn't one
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("Cargo.toml")).unwrap();
+        assert_eq!(detect_project_type(dir.path()), "");
+    }
+
+    #[test]
+    fn ext_is_lowercased_without_a_dot() {
+        for ext in [".rs", "rs", "RS", ".Rs"] {
+            let config = builder("blog_post")
+                .ext(ext.to_string())
+                .build_with(GitFacts::default())
+                .unwrap();
+            assert_eq!(config.ext, "rs", "{ext:?}");
+            assert_eq!(config.ext_upper, "RS", "{ext:?}");
+        }
+    }
+
+    #[test]
+    fn the_image_is_tagged_with_the_git_sha() {
+        let config = builder("blog_post")
+            .git_sha(Some("abc1234".to_string()))
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(config.git_sha, "abc1234");
+        assert_eq!(config.image_tag, "abc1234");
+        assert_eq!(config.full_image, "octocat/blog_post:abc1234");
+        assert_eq!(config.sources["image_tag"], "HEAD");
+    }
+
+    #[test]
+    fn the_image_is_tagged_latest_outside_a_repo() {
+        let config = builder("blog_post")
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(config.git_sha, "");
+        assert_eq!(config.image_tag, "latest");
+        assert_eq!(config.full_image, "octocat/blog_post:latest");
+    }
+
+    #[test]
+    fn an_explicit_image_tag_wins_over_the_git_sha() {
+        let config = builder("blog_post")
+            .git_sha(Some("abc1234".to_string()))
+            .image_tag(Some("v1.2.0".to_string()))
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(config.git_sha, "abc1234");
+        assert_eq!(config.image_tag, "v1.2.0");
+        assert_eq!(config.full_image, "octocat/blog_post:v1.2.0");
+    }
+
+    #[test]
+    fn full_image_includes_the_registry() {
+        let overrides = Map::from_iter([(
+            "registry_domain".to_string(),
+            Value::String("ghcr.io".to_string()),
+        )]);
+        let config = builder("blog_post")
+            .overrides(overrides)
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(config.full_image, "ghcr.io/octocat/blog_post:latest");
+    }
+
+    #[test]
+    fn hyphen_prefixes_are_stripped() {
+        assert_eq!(unprefix("dk-tool", "dk-"), "tool");
+        assert_eq!(unprefix("dk-tool", "dk"), "tool");
+        assert_eq!(unprefix("dk_tool", "dk-"), "tool");
+    }
+
+    #[test]
+    fn underscore_prefixes_are_stripped() {
+        assert_eq!(unprefix("dk_tool", "dk_"), "tool");
+        assert_eq!(unprefix("dk_tool", "dk"), "tool");
+        assert_eq!(unprefix("dk-tool", "dk_"), "tool");
+    }
+
+    #[test]
+    fn names_without_the_prefix_are_kept_whole() {
+        assert_eq!(unprefix("tool", "dk-"), "tool");
+        assert_eq!(unprefix("dk-", "dk-"), "dk-");
+        assert_eq!(unprefix("dk", "dk-"), "dk");
+        assert_eq!(unprefix("dk-tool", ""), "dk-tool");
+        assert_eq!(unprefix("é-tool", "e"), "é-tool");
+    }
+
+    #[test]
+    fn the_prefix_reaches_the_unprefixed_fields() {
+        let config = ConfigBuilder::new("dk-web-tool".to_string(), "dk-".to_string())
+            .github_user(Some("octocat".to_string()))
+            .build_with(GitFacts::default())
+            .unwrap();
+        assert_eq!(config.unprefixed_name, "web-tool");
+        assert_eq!(config.unprefixed_pascal, "WebTool");
+    }
+
+    #[test]
+    fn pinned_fields_dont_come_from_git() {
+        let config = ConfigBuilder::new("blog_post".to_string(), String::new())
+            .inspect_repo(false)
+            .build_pinned()
+            .unwrap();
+        assert_eq!(config.author, "Dropkick Test");
+        assert_eq!(config.email, "test@example.com");
+        assert_eq!(config.github_user, "dropkick-test");
+        assert_eq!(
+            config.git_repo_url,
+            "https://github.com/dropkick-test/blog_post"
+        );
+        assert_eq!(config.registry_domain, "");
+        assert_eq!(config.full_image, "dropkick-test/blog_post:latest");
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{clean_path, shadow};
+
+/// Where the files created by the last import are listed, relative to the
+/// output directory, so `dropkick undo` knows what to remove.
+pub const JOURNAL_FILE: &str = ".dropkick/journal.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    pub files: Vec<JournalEntry>,
+}
+
+/// A created file, with a hash of what was written so later edits are noticed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Relative to the output directory
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+/// Replaces the journal in `output_dir` with the files in `created`, which
+/// have to be below it.  An import that created nothing still empties it,
+/// though it isn't written when there was none.
+pub fn record(output_dir: &Path, created: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let path = output_dir.join(JOURNAL_FILE);
+    if created.is_empty() && !path.exists() {
+        return Ok(());
+    }
+
+    let mut journal = Journal::default();
+    for dest in created {
+        let Ok(relative) = dest.strip_prefix(output_dir) else {
+            continue;
+        };
+        journal.files.push(JournalEntry {
+            path: relative.to_path_buf(),
+            hash: hash(&fs::read(dest)?),
+        });
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&journal)?)
+        .map_err(|e| format!("unable to write '{}' ({})", path.to_string_lossy(), e))?;
+
+    Ok(())
+}
+
+/// Removes the files the last import into `output_dir` created, along with
+/// any directories that leaves empty.  A file changed since it was written is
+/// left alone and kept in the journal, so undo can be run again once the
+/// change is reverted.
+pub fn undo(output_dir: &Path, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let path = output_dir.join(JOURNAL_FILE);
+    let raw = match fs::read_to_string(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!("nothing to undo, '{}' doesn't exist", path.display()).into());
+        }
+        Err(e) => return Err(format!("unable to read '{}' ({})", path.display(), e).into()),
+    };
+    let journal: Journal = serde_json::from_str(&raw)
+        .map_err(|e| format!("unable to parse '{}': {}", path.display(), e))?;
+
+    let mut kept = Vec::new();
+    let mut removed = 0;
+    for entry in journal.files {
+        let dest = output_dir.join(&entry.path);
+        let display = clean_path(&dest);
+        let content = match fs::read(&dest) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("  • {} (already gone)", display.display());
+                continue;
+            }
+            Err(e) => return Err(format!("unable to read '{}' ({})", display.display(), e).into()),
+        };
+
+        if hash(&content) != entry.hash {
+            warn!(
+                "Not removing '{}', it has changed since it was imported",
+                display.display()
+            );
+            kept.push(entry);
+            continue;
+        }
+
+        if dry_run {
+            info!("  • {} (would remove)", display.display());
+        } else {
+            fs::remove_file(&dest)
+                .map_err(|e| format!("unable to remove '{}' ({})", display.display(), e))?;
+            // What was imported goes too, so a later merge doesn't use it
+            if let Some(shadow) = shadow::shadow_path(output_dir, &dest)
+                && fs::remove_file(&shadow).is_ok()
+            {
+                remove_empty_parents(&shadow, &output_dir.join(shadow::SHADOW_DIR));
+            }
+            remove_empty_parents(&dest, output_dir);
+            info!("  • {} (removed)", display.display());
+        }
+        removed += 1;
+    }
+
+    if dry_run {
+        info!("Would remove {} file(s)\n", removed);
+    } else {
+        info!("Removed {} file(s)\n", removed);
+        if kept.is_empty() {
+            fs::remove_file(&path)?;
+        } else {
+            let journal = Journal { files: kept };
+            fs::write(&path, serde_json::to_string_pretty(&journal)?)?;
+            return Err(format!(
+                "{} changed file(s) were left in place, revert or remove them by hand",
+                journal.files.len()
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the directories between `dest` and `top` that are empty once
+/// `dest` is gone.
+fn remove_empty_parents(dest: &Path, top: &Path) {
+    let mut dir = dest.parent();
+    while let Some(current) = dir {
+        if current == top || current.as_os_str().is_empty() {
+            break;
+        }
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// FNV-1a, which unlike the std hasher is the same from one build to the next.
+fn hash(content: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in content {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_import_creating_nothing_leaves_nothing_to_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let readme = dir.path().join("README.md");
+        fs::write(&readme, "# demo\n").unwrap();
+        record(dir.path(), std::slice::from_ref(&readme)).unwrap();
+
+        // The same import again, with README.md skipped as it exists
+        record(dir.path(), &[]).unwrap();
+        undo(dir.path(), false).unwrap();
+        assert!(readme.exists());
+    }
+
+    #[test]
+    fn undo_removes_what_the_last_import_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let readme = dir.path().join("README.md");
+        let main = dir.path().join("src/main.rs");
+        fs::write(&readme, "# demo\n").unwrap();
+        record(dir.path(), std::slice::from_ref(&readme)).unwrap();
+        fs::create_dir_all(main.parent().unwrap()).unwrap();
+        fs::write(&main, "fn main() {}\n").unwrap();
+        record(dir.path(), std::slice::from_ref(&main)).unwrap();
+
+        undo(dir.path(), false).unwrap();
+        assert!(readme.exists());
+        assert!(!main.exists());
+        assert!(!dir.path().join("src").exists());
+    }
+
+    #[test]
+    fn nothing_is_recorded_without_a_journal_to_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        record(dir.path(), &[]).unwrap();
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use log::warn;
+use serde::Deserialize;
+
+use crate::replay::parse_key_spec;
+
+/// What a key does while browsing the template tree (and, for moving and
+/// going back, in the file view).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    Up,
+    Down,
+    /// Collapse the folder, or go back from the file view
+    Collapse,
+    /// Expand the folder, or view the file
+    Expand,
+    /// Expand or collapse the folder without moving into it
+    Fold,
+    /// Select the file, or every file in the folder
+    Toggle,
+    /// Select every file shown, or clear them when they all are
+    SelectAll,
+    Filter,
+    /// In the file view, switch between rendered and raw
+    Raw,
+    Basket,
+    Import,
+    DryRun,
+    /// Choose the directory the import writes into
+    Destination,
+    Install,
+    Help,
+    Quit,
+}
+
+/// One key, or several, for an action in `$XDG_CONFIG_HOME/dropkick/config.yaml`'s `keys`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum KeySpecs {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The keys bound to each action, in the order they're listed in the help.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(KeyAction, Vec<KeyCode>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use KeyAction::*;
+        use KeyCode::Char;
+
+        let bindings = vec![
+            (Up, vec![KeyCode::Up, Char('k')]),
+            (Down, vec![KeyCode::Down, Char('j')]),
+            (Collapse, vec![KeyCode::Left, Char('h')]),
+            (Expand, vec![KeyCode::Right, Char('l'), Char('v')]),
+            (Fold, vec![KeyCode::Enter]),
+            (Toggle, vec![Char(' ')]),
+            (SelectAll, vec![Char('a')]),
+            (Filter, vec![Char('/')]),
+            (Raw, vec![Char('r')]),
+            (Basket, vec![Char('b')]),
+            (Import, vec![Char('e')]),
+            (DryRun, vec![Char('d')]),
+            (Destination, vec![Char('o')]),
+            (Install, vec![Char('i')]),
+            (Help, vec![Char('?')]),
+            (Quit, vec![Char('q')]),
+        ];
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// The default bindings with those in `keys` swapped in.  A key bound to
+    /// an action there is taken away from any other action it had by
+    /// default; a key that can't be parsed is reported and left out.
+    pub fn new(keys: &BTreeMap<KeyAction, KeySpecs>) -> Self {
+        let mut keymap = Self::default();
+        for (action, specs) in keys {
+            let specs = match specs {
+                KeySpecs::One(spec) => std::slice::from_ref(spec),
+                KeySpecs::Many(specs) => specs.as_slice(),
+            };
+            let codes: Vec<KeyCode> = specs
+                .iter()
+                .filter_map(|spec| match parse_key_spec(spec) {
+                    Ok(key) => Some(key.code),
+                    Err(e) => {
+                        warn!(
+                            "Ignoring key '{}' for {:?} in the config: {}",
+                            spec, action, e
+                        );
+                        None
+                    }
+                })
+                .collect();
+
+            for (other, bound) in &mut keymap.bindings {
+                if other == action {
+                    *bound = codes.clone();
+                } else if !keys.contains_key(other) {
+                    bound.retain(|code| !codes.contains(code));
+                }
+            }
+        }
+        keymap
+    }
+
+    /// The action `key` is bound to, if any.
+    pub fn action(&self, key: KeyEvent) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .find(|(_, codes)| codes.contains(&key.code))
+            .map(|(action, _)| *action)
+    }
+
+    /// The keys bound to `action` as they're shown in the help, e.g. `↑/k`.
+    pub fn describe(&self, action: KeyAction) -> String {
+        self.bindings
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map(|(_, codes)| codes.iter().map(key_name).collect::<Vec<_>>().join("/"))
+            .unwrap_or_default()
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = KeyAction> + '_ {
+        self.bindings.iter().map(|(action, _)| *action)
+    }
+}
+
+impl KeyAction {
+    pub fn description(self) -> &'static str {
+        match self {
+            KeyAction::Up => "Move up",
+            KeyAction::Down => "Move down",
+            KeyAction::Collapse => "Collapse folder / back",
+            KeyAction::Expand => "Expand folder / view file",
+            KeyAction::Fold => "Expand or collapse folder",
+            KeyAction::Toggle => "Toggle file, or every file in a folder",
+            KeyAction::SelectAll => "Toggle every file shown",
+            KeyAction::Filter => "Filter files by fuzzy match (Esc clears)",
+            KeyAction::Raw => "In the file view, switch between rendered and raw",
+            KeyAction::Basket => "Go over the basket of selected files, x removes one",
+            KeyAction::Import => "Import the basket, after going over it",
+            KeyAction::DryRun => "Toggle dry run (preview the import without writing)",
+            KeyAction::Destination => "Choose the directory to import into (Tab completes)",
+            KeyAction::Install => "Search the template index and install templates",
+            KeyAction::Help => "Show this help",
+            KeyAction::Quit => "Quit",
+        }
+    }
+}
+
+fn key_name(code: &KeyCode) -> String {
+    match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        other => format!("{:?}", other),
+    }
+}
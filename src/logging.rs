@@ -0,0 +1,235 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    error::Error,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use indicatif::ProgressBar;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::xdg;
+
+/// Writes log records to stderr.  While the TUI owns the terminal, records are
+/// held back instead and flushed once the alternate screen is gone, so stray
+/// output can't corrupt the display.  A `--log-file` gets every record down
+/// to debug level (trace with `-vv`), with the time and module, whatever
+/// makes it to stderr.
+struct Logger {
+    /// The most detailed records written to stderr
+    stderr_level: OnceLock<LevelFilter>,
+    file: Mutex<Option<File>>,
+    buffering: AtomicBool,
+    buffer: Mutex<Vec<String>>,
+    /// Warnings and errors held back, until the TUI takes them to show
+    notices: Mutex<Vec<(Level, String)>>,
+    /// The progress bar on screen, if any, which records are written above
+    progress: Mutex<Option<ProgressBar>>,
+}
+
+static LOGGER: Logger = Logger {
+    stderr_level: OnceLock::new(),
+    file: Mutex::new(None),
+    buffering: AtomicBool::new(false),
+    buffer: Mutex::new(Vec::new()),
+    notices: Mutex::new(Vec::new()),
+    progress: Mutex::new(None),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Dependencies such as handlebars are chatty at debug level, so only
+        // their warnings and errors are passed through
+        let ours = metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
+        metadata.level() <= log::max_level() && (ours || metadata.level() <= Level::Warn)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Ok(mut file) = self.file.lock()
+            && let Some(file) = file.as_mut()
+        {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let _ = writeln!(
+                file,
+                "{}.{:03} {:<5} {}: {}",
+                time.as_secs(),
+                time.subsec_millis(),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+        if self
+            .stderr_level
+            .get()
+            .is_some_and(|level| record.level() > *level)
+        {
+            return;
+        }
+
+        let line = match record.level() {
+            Level::Error => format!("error: {}", record.args()),
+            Level::Warn => format!("warning: {}", record.args()),
+            Level::Info => record.args().to_string(),
+            Level::Debug => format!("debug: {}", record.args()),
+            Level::Trace => format!("trace: {}", record.args()),
+        };
+
+        if self.buffering.load(Ordering::Relaxed)
+            && let Ok(mut buffer) = self.buffer.lock()
+        {
+            buffer.push(line);
+            if record.level() <= Level::Warn
+                && let Ok(mut notices) = self.notices.lock()
+            {
+                notices.push((record.level(), record.args().to_string()));
+            }
+            return;
+        }
+
+        match self
+            .progress
+            .lock()
+            .ok()
+            .and_then(|progress| progress.clone())
+        {
+            Some(bar) => bar.suspend(|| eprintln!("{}", line)),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger.  `--quiet` only lets errors through, `-v` adds
+/// debug output and `-vv` trace output too; `DROPKICK_LOG=<level>`
+/// overrides them.  With `log_file`, records are also appended to that file,
+/// or to a new one under `$XDG_DATA_HOME/dropkick/logs/` when it's empty.
+pub fn init(verbosity: u8, quiet: bool, log_file: Option<&Path>) {
+    let level = env::var("DROPKICK_LOG")
+        .ok()
+        .and_then(|value| LevelFilter::from_str(&value).ok())
+        .unwrap_or(if quiet {
+            LevelFilter::Error
+        } else {
+            match verbosity {
+                0 => LevelFilter::Info,
+                1 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            }
+        });
+    let _ = LOGGER.stderr_level.set(level);
+
+    let mut max_level = level;
+    if let Some(path) = log_file {
+        match open_log_file(path) {
+            Ok(file) => {
+                if let Ok(mut slot) = LOGGER.file.lock() {
+                    *slot = Some(file);
+                }
+                max_level = max_level.max(if verbosity > 1 {
+                    LevelFilter::Trace
+                } else {
+                    LevelFilter::Debug
+                });
+            }
+            Err(e) => eprintln!("warning: Unable to open the log file: {}", e),
+        }
+    }
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+fn open_log_file(path: &Path) -> Result<File, Box<dyn Error>> {
+    let path = if path.as_os_str().is_empty() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        xdg::data_dir()
+            .join("logs")
+            .join(format!("dropkick-{}.log", time.as_secs()))
+    } else {
+        PathBuf::from(path)
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    File::options()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Warnings already written by `warn_once`.
+static WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Logs a warning unless the same one already was, for problems in settings
+/// that are read again for every file.
+pub fn warn_once(message: String) {
+    if WARNED
+        .lock()
+        .is_ok_and(|mut warned| warned.insert(message.clone()))
+    {
+        log::warn!("{}", message);
+    }
+}
+
+/// Whether records at `level` make it to stderr.
+pub fn shows_on_stderr(level: Level) -> bool {
+    level <= log::max_level()
+        && LOGGER
+            .stderr_level
+            .get()
+            .is_none_or(|stderr_level| level <= *stderr_level)
+}
+
+/// Writes log output above `bar` while it's on screen, or as usual again
+/// once it's gone (`None`).
+pub fn show_progress(bar: Option<ProgressBar>) {
+    if let Ok(mut progress) = LOGGER.progress.lock() {
+        *progress = bar;
+    }
+}
+
+/// Holds log output back while the TUI is on screen.
+pub fn start_buffering() {
+    LOGGER.buffering.store(true, Ordering::Relaxed);
+}
+
+/// The warnings and errors held back since the last call, for the TUI to
+/// show.  They're still written out when buffering stops.
+pub fn take_notices() -> Vec<(Level, String)> {
+    LOGGER
+        .notices
+        .lock()
+        .map(|mut notices| notices.drain(..).collect())
+        .unwrap_or_default()
+}
+
+/// Stops buffering and writes out everything logged in the meantime.
+pub fn flush_buffer() {
+    LOGGER.buffering.store(false, Ordering::Relaxed);
+
+    if let Ok(mut buffer) = LOGGER.buffer.lock() {
+        for line in buffer.drain(..) {
+            eprintln!("{}", line);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use clap::Parser;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, poll},
     execute,
@@ -6,102 +7,433 @@ use crossterm::{
 use ratatui::{Terminal, backend::CrosstermBackend};
 use std::{
     error::Error,
-    fs::{self, create_dir_all},
-    io,
+    io, panic,
     path::{Path, PathBuf},
+    process,
+    sync::{Arc, OnceLock},
     time::Duration,
 };
 
-use two_face::theme::EmbeddedThemeName;
+use log::{error, info};
+use syntect::{highlighting::Theme, parsing::SyntaxSet};
 
-use crate::app::{Action, interpolate_file};
+use crate::{
+    app::Action,
+    cli::{Cli, Command, ConfigCommand, TemplateCommand},
+    config::{UserConfig, get_repo_config, get_user_config},
+    error::DropkickError,
+    import::{ImportOptions, import_template_files},
+    keymap::Keymap,
+    template_rendering::{ContextOptions, Renderer},
+    theme::ThemeConfig,
+};
 
 mod app;
+mod cargo_generate;
+mod case;
+mod cli;
+mod commands;
 mod config;
+mod cookiecutter;
+mod erb;
+mod error;
+mod exec;
+mod fuzzy;
+mod hooks;
+mod import;
 mod interpolation_config;
+mod journal;
+mod keymap;
+mod logging;
+mod manifest;
+mod registry;
+mod remote;
+mod replay;
+mod shadow;
+mod skeleton;
+mod snapshot;
+mod stats;
 mod template_rendering;
+mod templates;
+mod theme;
+mod transaction;
+mod xdg;
+
+fn main() {
+    // Paths on the command line can start with `~`, so this comes first
+    match find_home() {
+        Ok(home) => {
+            let _ = HOME.set(home);
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+    let cli = Cli::parse();
+    logging::init(cli.verbose, cli.quiet, cli.log_file.as_deref());
+    xdg::migrate();
+    let user_config = get_user_config();
+    exec::allow(if cli.allow_exec {
+        exec::AllowExec::All(true)
+    } else {
+        user_config.allow_exec.clone().unwrap_or_default()
+    });
+    let options = ContextOptions {
+        user_variables: user_config.variables(),
+        ..cli.context_options()
+    };
+    let import_options = |output_dir: PathBuf| ImportOptions {
+        output_dir,
+        on_conflict: cli
+            .on_conflict
+            .or(user_config.on_conflict)
+            .unwrap_or_default(),
+        keep_root: cli.keep_root,
+        dry_run: cli.dry_run,
+        no_hooks: cli.no_hooks,
+        ..Default::default()
+    };
+
+    let template_index = user_config
+        .template_index
+        .as_deref()
+        .unwrap_or(registry::DEFAULT_INDEX);
+
+    // Given before a command, `--output` would be silently ignored
+    if cli.command.is_some() && cli.output_dir.is_some() {
+        error!(
+            "--output before a command only applies to the TUI, give it after the command's name instead, e.g. `dropkick import web --output DIR`"
+        );
+        process::exit(1);
+    }
+    let tui_output_dir = cli.output_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+
+    let result = match cli.command {
+        Some(Command::New { name, template }) => {
+            let options = ContextOptions {
+                output_dir: name.clone(),
+                new_project: true,
+                ..options
+            };
+            default_template(template, &user_config).and_then(|templates| {
+                commands::new(
+                    &name,
+                    &templates,
+                    cli.follow_symlinks,
+                    &options,
+                    &import_options(name.clone()),
+                )
+            })
+        }
+        Some(Command::Install {
+            source,
+            name,
+            branch,
+            tag,
+        }) => commands::install(
+            &source,
+            name.as_deref(),
+            branch.or(tag).as_deref(),
+            template_index,
+        ),
+        Some(Command::Search { term }) => commands::search(term.as_deref(), template_index),
+        Some(Command::Update { template }) => commands::update(template.as_deref()),
+        Some(Command::List { template }) => {
+            commands::list(template.as_deref(), cli.follow_symlinks)
+        }
+        Some(Command::Import {
+            template,
+            files,
+            output_dir,
+        }) => commands::import(
+            &template,
+            &files,
+            cli.follow_symlinks,
+            &ContextOptions {
+                output_dir: output_dir.clone(),
+                ..options
+            },
+            &import_options(output_dir),
+        ),
+        Some(Command::Render { file, output }) => {
+            commands::render(file.as_deref(), output.as_deref(), &options)
+        }
+        Some(Command::Vars { template, format }) => match template {
+            Some(template) => commands::vars(&template, cli.follow_symlinks),
+            None => commands::context(format, &options),
+        },
+        Some(Command::Init { force }) => commands::init(force),
+        Some(Command::Lint {
+            template,
+            template_flag,
+        }) => commands::lint(
+            &template.or(template_flag).unwrap_or_default(),
+            cli.follow_symlinks,
+        ),
+        Some(Command::Test { template, update }) => {
+            commands::test(&template, update, cli.follow_symlinks)
+        }
+        Some(Command::Scaffold(mut args)) => {
+            let options = ContextOptions {
+                output_dir: args.output_dir.clone(),
+                ..options
+            };
+            let import_options = import_options(args.output_dir.clone());
+            default_template(std::mem::take(&mut args.template), &user_config).and_then(
+                |templates| {
+                    args.template = templates;
+                    if args.count_only {
+                        commands::scaffold_count_only(
+                            &args,
+                            cli.follow_symlinks,
+                            &options,
+                            &import_options,
+                        )
+                    } else {
+                        commands::scaffold(&args, cli.follow_symlinks, &options, &import_options)
+                    }
+                },
+            )
+        }
+        Some(Command::RenderFile {
+            template,
+            file,
+            stdout,
+            output_dir,
+        }) => commands::render_file(
+            &template,
+            &file,
+            stdout,
+            cli.follow_symlinks,
+            &ContextOptions {
+                output_dir: output_dir.clone(),
+                ..options
+            },
+            &import_options(output_dir),
+        ),
+        Some(Command::RenderDir { dir }) => commands::render_dir(&dir, &options),
+        Some(Command::Config {
+            command: ConfigCommand::Validate,
+        }) => commands::validate_config(),
+        Some(Command::Template {
+            command:
+                TemplateCommand::New {
+                    name,
+                    from,
+                    project_name,
+                },
+        }) => match from {
+            Some(dir) => commands::templatize(&dir, Some(&name), project_name.as_deref(), &options),
+            None => commands::new_template(&name),
+        },
+        Some(Command::Templatize {
+            dir,
+            name,
+            project_name,
+        }) => commands::templatize(&dir, name.as_deref(), project_name.as_deref(), &options),
+        Some(Command::Undo { output_dir }) => journal::undo(&output_dir, cli.dry_run),
+        None => run_tui(
+            &ContextOptions {
+                output_dir: tui_output_dir.clone(),
+                ..options
+            },
+            &import_options(tui_output_dir),
+            cli.replay.as_deref(),
+            cli.follow_symlinks,
+            user_config.theme.as_ref(),
+            template_index,
+            Keymap::new(&user_config.keys),
+        ),
+    };
+
+    if let Err(e) = result {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// The templates given with `--template`, or else the configured default.
+fn default_template(
+    templates: Vec<String>,
+    user_config: &UserConfig,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if !templates.is_empty() {
+        return Ok(templates);
+    }
+
+    match &user_config.default_template {
+        Some(template) => Ok(vec![template.clone()]),
+        None => Err(format!(
+            "no --template given and no default_template set in {}",
+            clean_path(&UserConfig::path()).display()
+        )
+        .into()),
+    }
+}
+
+fn run_tui(
+    options: &ContextOptions,
+    import_options: &ImportOptions,
+    replay: Option<&Path>,
+    follow_symlinks: bool,
+    theme: Option<&ThemeConfig>,
+    template_index: &str,
+    keymap: Keymap,
+) -> Result<(), Box<dyn Error>> {
+    // Resolve the render context up front so config problems are reported
+    // before the terminal switches to the alternate screen
+    let renderer = Renderer::new(&get_repo_config()?, options)?;
 
-fn main() -> Result<(), Box<dyn Error>> {
     // Load syntax highlighting resources with extended syntax support
-    let ss = two_face::syntax::extra_newlines();
+    // (shared, so files can be highlighted in the background)
+    let ss = Arc::new(two_face::syntax::extra_newlines());
     let theme_set = two_face::theme::extra();
-    let theme = &theme_set.get(EmbeddedThemeName::InspiredGithub);
+    let theme_name = theme::syntax_theme(theme.and_then(ThemeConfig::syntax));
+    let palette = theme.map(ThemeConfig::palette).unwrap_or_default();
+    let theme = &Arc::new(theme_set.get(theme_name).clone());
+
+    // Create app state
+    let templates_path = get_templates_path();
+    let mut app = app::App::new(
+        &templates_path,
+        renderer,
+        import_options.clone(),
+        follow_symlinks,
+        template_index,
+        keymap,
+        palette,
+    )?;
+
+    let action = match replay {
+        Some(path) => replay::run(&mut app, &replay::load_keys(path)?, &ss, theme)?,
+        None => run_event_loop(&mut app, &ss, theme)?,
+    };
+
+    if let Action::Quit = action {
+        return Ok(());
+    }
+
+    // Print selected files if user pressed 'e'
+    if !app.selected_files.is_empty() {
+        info!("\nSelected template files:");
+        info!("{}", "=".repeat(50));
+        let layered = app.layered_files();
+        for (file, winner) in &layered.overridden {
+            info!(
+                "Using {} instead of {}",
+                clean_path(winner).display(),
+                clean_path(file).display()
+            );
+        }
+        let report = import_template_files(&layered.files, &app.renderer, &app.import_options)?;
+
+        // Print Summary
+        info!("{}", "=".repeat(50));
+        report.log_summary("Imported", layered.files.len());
+        report.check_failures()?;
+    } else {
+        info!("\nNo files selected.\n");
+    }
+
+    Ok(())
+}
+
+/// How long the event loop waits for input before checking again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The same while work is going on in the background, to show its results
+/// soon after they're ready.
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Runs the app on the real terminal until the user quits or extracts.
+fn run_event_loop(
+    app: &mut app::App,
+    ss: &Arc<SyntaxSet>,
+    theme: &Arc<Theme>,
+) -> Result<Action, Box<dyn Error>> {
+    // A panic would otherwise leave the terminal in raw mode on the
+    // alternate screen, with its message lost
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture);
+        logging::flush_buffer();
+        default_hook(info);
+    }));
 
     // Setup terminal
+    logging::start_buffering();
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Create app state
-    let templates_path = get_templates_path();
-    let mut app = app::App::new(&templates_path)?;
-    let mut should_exit = false;
-
     // Main loop with error handling
-    let result = (|| -> Result<(), Box<dyn Error>> {
-        while !should_exit {
-            terminal.draw(|f| app.render(f))?;
+    let result = (|| -> Result<Action, Box<dyn Error>> {
+        // Nothing changes on screen between events, so only redraw after one
+        let mut dirty = true;
+        loop {
+            if dirty {
+                terminal.draw(|f| app.render(f))?;
+                dirty = false;
+            }
+
+            if app.tick() {
+                dirty = true;
+            }
 
             ////////////////////////
             // Handle User Inputs //
             ////////////////////////
-            if poll(Duration::from_millis(0))? {
-                if let Event::Key(key) = event::read()? {
-                    let action = app.handle_key(key, &terminal, &ss, theme)?;
-                    match action {
-                        Action::Quit => should_exit = true,
-                        Action::Extract => {
-                            break;
+            let interval = if app.is_busy() {
+                BUSY_POLL_INTERVAL
+            } else {
+                POLL_INTERVAL
+            };
+            if !poll(interval)? {
+                continue;
+            }
+            let height = terminal.size()?.height;
+            match event::read()? {
+                // A key that fails is reported in a dialog, leaving the app
+                // up for another try
+                Event::Key(key) => match app.handle_key(key, height, ss, theme) {
+                    Ok(Action::Continue) => {}
+                    Ok(action) => return Ok(action),
+                    Err(e) => {
+                        app.mode = app::AppMode::Error {
+                            message: e.to_string(),
                         }
-                        Action::Continue => {}
                     }
-                }
+                },
+                Event::Mouse(mouse) => app.handle_mouse(mouse, height),
+                _ => {}
             }
+            dirty = true;
         }
-
-        Ok(())
     })();
 
     // Always restore terminal, even on error
     cleanup_terminal(&mut terminal)?;
+    logging::flush_buffer();
+    // Back to the standard hook
+    let _ = panic::take_hook();
 
-    // Handle the result after terminal is cleaned up
-    if let Err(e) = result {
-        eprintln!("Error: {}", e);
-        return Err(e);
-    }
-
-    // Print selected files if user pressed 'e'
-    if !should_exit && !app.selected_files.is_empty() {
-        println!("\nSelected template files imported:");
-        println!("{}", "=".repeat(50));
-        let mut n_imports = 0;
-        let mut sorted_files: Vec<_> = app.selected_files.iter().collect();
-        sorted_files.sort();
-        for file in sorted_files {
-            let src_path = Path::new(file);
-
-            n_imports += import_selected_template_file(src_path).is_some() as u32;
-        }
-
-        // Print Summary
-        println!("{}", "=".repeat(50));
-        println!(
-            "Imported: {} of {} selected file(s)\n",
-            n_imports,
-            app.selected_files.len()
-        );
-    } else if !should_exit {
-        println!("\nNo files selected.\n");
-    }
-
-    Ok(())
+    result
 }
 
+/// Held by tests while they point `DROPKICK_TEMPLATES` somewhere, as the
+/// environment is shared by every test.
+#[cfg(test)]
+static TEMPLATES_ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
 fn get_templates_path() -> PathBuf {
-    get_home().join(".dropkick/templates")
+    match std::env::var("DROPKICK_TEMPLATES") {
+        Ok(path) if !path.is_empty() => expand_tilde(Path::new(&path)),
+        _ => xdg::data_dir().join("templates"),
+    }
 }
 
 fn cleanup_terminal(
@@ -117,54 +449,73 @@ fn cleanup_terminal(
     Ok(())
 }
 
-fn import_selected_template_file(src_path: &Path) -> Option<u8> {
-    let template_root = get_templates_path();
-
-    // Compute relative destination, and create a PathBuff, since we need
-    // to mutate it, we can't just have it be an &Path???
-    let mut dest = src_path.strip_prefix(template_root).ok()?.to_path_buf();
+/// The home directory, looked up once at startup.
+static HOME: OnceLock<PathBuf> = OnceLock::new();
 
-    // Remove the first segment (template folder)
-    dest = dest.iter().skip(1).collect::<PathBuf>();
+/// `$HOME`, or the user's profile folder on Windows.
+fn find_home() -> Result<PathBuf, DropkickError> {
+    dirs::home_dir().ok_or(DropkickError::Home)
+}
 
-    // Remove `.tt` suffix
-    dest = dest.with_extension("");
+/// The home directory found at startup.  `main` stops when there isn't one,
+/// so the empty path this falls back to is only seen by code that runs
+/// without it, such as tests.
+fn get_home() -> PathBuf {
+    HOME.get_or_init(|| dirs::home_dir().unwrap_or_default())
+        .clone()
+}
 
-    // Abort if a file already exists
-    if dest.exists() {
-        println!(
-            "Skipping copy of '{}' because file existed locally.",
-            dest.to_string_lossy()
-        );
-        return None;
+/// Expands a leading `~` to the home directory, for paths that didn't pass
+/// through a shell (e.g. `--output-dir=~/foo` or environment variables).
+/// `~user` and tildes later in the path are left untouched.
+fn expand_tilde(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) if rest.as_os_str().is_empty() => get_home(),
+        Ok(rest) => get_home().join(rest),
+        Err(_) => path.to_path_buf(),
     }
+}
 
-    // Create parent directories
-    if let Some(parent) = dest.parent() {
-        let display_path = clean_path(src_path);
-        create_dir_all(parent).expect("error: unable to create parent directories.");
-        println!("  • {}", display_path.to_string_lossy());
+/// `src_path` for showing: under `~` when it's in the home directory, and
+/// rebuilt from its components so a Windows path mixing `/` and `\\` (e.g.
+/// `C:\\Users\\me/templates`) comes out with one kind of separator.
+fn clean_path(src_path: &Path) -> PathBuf {
+    let home = get_home();
+    match src_path.strip_prefix(&home) {
+        Ok(p) => Path::new("~").join(p).components().collect(),
+        Err(_) => src_path.components().collect(),
     }
+}
 
-    // Copy file
-    let content = fs::read_to_string(src_path).expect("it should just read the friggin file!");
-    let interpolated = interpolate_file(&content);
-    fs::write(dest, interpolated).expect("It should just write the file");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Some(1)
-}
+    #[test]
+    fn a_leading_tilde_is_the_home_directory() {
+        assert_eq!(expand_tilde(Path::new("~")), get_home());
+        assert_eq!(expand_tilde(Path::new("~/x")), get_home().join("x"));
+        assert_eq!(expand_tilde(Path::new("~/x/y")), get_home().join("x/y"));
+    }
 
-fn get_home() -> PathBuf {
-    std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .map(PathBuf::from)
-        .expect("Could not determine home directory")
-}
+    #[test]
+    fn other_tildes_are_left_alone() {
+        for path in ["~user", "~user/x", "a/~/b", "./~", "x~", "/abs/path"] {
+            assert_eq!(expand_tilde(Path::new(path)), Path::new(path));
+        }
+    }
 
-fn clean_path(src_path: &Path) -> PathBuf {
-    let home = get_home();
-    src_path
-        .strip_prefix(&home)
-        .map(|p| PathBuf::from("~").join(p))
-        .unwrap_or_else(|_| src_path.to_path_buf())
+    #[test]
+    fn templates_path_expands_dropkick_templates() {
+        let _env = TEMPLATES_ENV.lock().unwrap_or_else(|e| e.into_inner());
+        // SAFETY: other tests touching the environment wait on TEMPLATES_ENV
+        unsafe { std::env::set_var("DROPKICK_TEMPLATES", "~/my-templates") };
+        let expanded = get_templates_path();
+        unsafe { std::env::set_var("DROPKICK_TEMPLATES", "") };
+        let empty = get_templates_path();
+        unsafe { std::env::remove_var("DROPKICK_TEMPLATES") };
+
+        assert_eq!(expanded, get_home().join("my-templates"));
+        assert_eq!(empty, xdg::data_dir().join("templates"));
+    }
 }
@@ -0,0 +1,437 @@
+use std::{
+    error::Error,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    cargo_generate::{self, is_cargo_generate},
+    clean_path,
+    cookiecutter::{self, is_cookiecutter},
+    template_rendering::{Engine, Renderer},
+    templates::{strip_template_suffix, template_root},
+};
+
+/// Per-template settings, kept at the top of the template folder.
+pub const MANIFEST_FILE: &str = "dropkick.yaml";
+
+/// A template's `dropkick.yaml`.  Templates without one get the defaults.
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    /// Template whose files this one builds on, overriding any it also has
+    pub extends: Option<String>,
+    /// Syntax the template's files are written in, handlebars by default
+    pub engine: Option<Engine>,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+    /// Files that are only generated when a condition holds
+    #[serde(default)]
+    pub files: Vec<FileRule>,
+    /// Files copied as they are rather than rendered: paths within the
+    /// template, or `*.ext` for every file with that extension
+    #[serde(default)]
+    pub binary: Vec<String>,
+    #[serde(default)]
+    pub defaults: TemplateDefaults,
+}
+
+/// Settings a template brings along, e.g. `{ ext: rb, bin: true }` for a gem,
+/// used where the command line, `.dropkickrc` and the rest leave them unset.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TemplateDefaults {
+    /// Extension of the project's sources, for `ext` and `ext_upper`
+    pub ext: Option<String>,
+    /// Only switches the flag on, `--bin` or `project.bin` can't be undone
+    pub bin: Option<bool>,
+    pub test: Option<bool>,
+    /// Stripped from the project's name for `unprefixed_name`, when
+    /// `.dropkickrc` has no `project.prefix`
+    pub prefix: Option<String>,
+    /// Any other value, e.g. `license: MIT`
+    #[serde(flatten)]
+    pub variables: Map<String, Value>,
+}
+
+impl TemplateDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.ext.is_none()
+            && self.bin.is_none()
+            && self.test.is_none()
+            && self.prefix.is_none()
+            && self.variables.is_empty()
+    }
+
+    /// These defaults with those of a template layered on top winning.
+    pub fn merge(&mut self, later: TemplateDefaults) {
+        self.ext = later.ext.or(self.ext.take());
+        self.bin = later.bin.or(self.bin);
+        self.test = later.test.or(self.test);
+        self.prefix = later.prefix.or(self.prefix.take());
+        self.variables.extend(later.variables);
+    }
+}
+
+/// e.g. `{ path: src/main.rs, when: "{{bin}}" }` or `{ path: bin/setup, mode: 755 }`
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileRule {
+    /// Path within the template, with or without the `.tt` suffix
+    pub path: PathBuf,
+    /// Rendered like the file itself; the file is skipped when this comes out
+    /// empty, `false`, `no` or `0`
+    pub when: Option<String>,
+    /// Permissions for the generated file in octal, instead of the template
+    /// file's own
+    #[serde(default, deserialize_with = "octal_mode")]
+    pub mode: Option<u32>,
+}
+
+/// Reads `mode: 755`, `mode: "0755"` or `mode: 0o755` as octal.
+fn octal_mode<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let text = match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::String(s) => s,
+        other => {
+            return Err(serde::de::Error::custom(format!(
+                "mode should be octal digits, not {:?}",
+                other
+            )));
+        }
+    };
+
+    let digits = text.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+        _ => Err(serde::de::Error::custom(format!(
+            "mode '{}' isn't an octal permission like 755",
+            text
+        ))),
+    }
+}
+
+/// A value a template needs beyond the built-in fields, e.g.
+/// `{ name: port, type: integer, default: 8080, prompt: "Port?" }`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: VariableKind,
+    pub default: Option<serde_yaml::Value>,
+    /// Question asked for the value, the name itself when not given
+    pub prompt: Option<String>,
+    /// Regex an answer has to match, checked before it's converted
+    pub validation: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableKind {
+    #[default]
+    String,
+    Bool,
+    Integer,
+    /// Text items, typed in separated by commas, e.g. `api, worker`
+    List,
+}
+
+impl Manifest {
+    /// Reads the manifest in template folder `root`, or for a cookiecutter
+    /// or cargo-generate template the equivalent of its `cookiecutter.json`
+    /// or `cargo-generate.toml`.  Validation
+    /// patterns and defaults are checked up front, so a broken manifest is
+    /// reported before anyone is asked anything.
+    pub fn load(root: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = root.join(MANIFEST_FILE);
+        if !path.is_file() {
+            if is_cookiecutter(root) {
+                return cookiecutter::manifest(root);
+            }
+            if is_cargo_generate(root) {
+                return cargo_generate::manifest(root);
+            }
+            return Ok(Self::default());
+        }
+
+        let display = clean_path(&path);
+        let raw = fs::read_to_string(&path)?;
+        let manifest: Self = serde_yaml::from_str(&raw)
+            .map_err(|e| format!("unable to parse {}: {}", display.display(), e))?;
+
+        for variable in &manifest.variables {
+            if let Some(pattern) = &variable.validation {
+                Regex::new(pattern).map_err(|e| {
+                    format!(
+                        "invalid validation pattern for '{}' in {}: {}",
+                        variable.name,
+                        display.display(),
+                        e
+                    )
+                })?;
+            }
+            if let Some(default) = variable.default_text() {
+                variable.parse(&default).map_err(|e| {
+                    format!(
+                        "invalid default for '{}' in {}: {}",
+                        variable.name,
+                        display.display(),
+                        e
+                    )
+                })?;
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// The rule for the file at `relative` within the template, if any.
+    pub fn rule_for(&self, relative: &Path) -> Option<&FileRule> {
+        let relative = normalize(relative);
+        self.files
+            .iter()
+            .find(|rule| normalize(&rule.path) == relative)
+    }
+
+    /// Whether the file at `relative` within the template is listed under
+    /// `binary`.
+    pub fn lists_binary(&self, relative: &Path) -> bool {
+        let relative = normalize(relative);
+        let extension = relative.extension().and_then(|e| e.to_str());
+        self.binary
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(ext) => extension.is_some_and(|e| e.eq_ignore_ascii_case(ext)),
+                None => normalize(Path::new(entry)) == relative,
+            })
+    }
+}
+
+/// A path within a template as it's generated, without the `.tt` suffix.
+fn normalize(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+        let name = strip_template_suffix(name).unwrap_or(name).to_string();
+        path.set_file_name(name);
+    }
+    path
+}
+
+/// Template folder `root` preceded by the templates it `extends`, directly or
+/// through its parent, base template first.
+pub fn template_lineage(root: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut lineage = vec![root.to_path_buf()];
+    let mut current = root.to_path_buf();
+
+    while let Some(parent) = Manifest::load(&current)?.extends {
+        let display = clean_path(&current.join(MANIFEST_FILE));
+        let parent_root = template_root(&parent)
+            .map_err(|e| format!("{} extends '{}': {}", display.display(), parent, e))?;
+        if lineage.contains(&parent_root) {
+            return Err(format!(
+                "{} extends '{}', which leads back to itself",
+                display.display(),
+                parent
+            )
+            .into());
+        }
+        lineage.push(parent_root.clone());
+        current = parent_root;
+    }
+
+    lineage.reverse();
+    Ok(lineage)
+}
+
+/// How a rendered condition or flag value reads as a yes or no.
+pub fn is_truthy(text: &str) -> bool {
+    !matches!(
+        text.trim().to_lowercase().as_str(),
+        "" | "false" | "no" | "0"
+    )
+}
+
+impl Variable {
+    pub fn question(&self) -> &str {
+        self.prompt.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The default as it would be typed in.
+    pub fn default_text(&self) -> Option<String> {
+        let scalar = |value: &serde_yaml::Value| match value {
+            serde_yaml::Value::String(s) => Some(s.clone()),
+            serde_yaml::Value::Bool(b) => Some(b.to_string()),
+            serde_yaml::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        match self.default.as_ref()? {
+            serde_yaml::Value::Sequence(items) => Some(
+                items
+                    .iter()
+                    .map(scalar)
+                    .collect::<Option<Vec<_>>>()?
+                    .join(", "),
+            ),
+            value => scalar(value),
+        }
+    }
+
+    /// Turns a typed answer into the value handed to templates.  An empty
+    /// answer takes the default.
+    pub fn answer(&self, input: &str) -> Result<Value, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return match self.default_text() {
+                Some(default) => self.parse(&default),
+                None => Err(format!("'{}' needs a value", self.name)),
+            };
+        }
+
+        self.parse(input)
+    }
+
+    fn parse(&self, input: &str) -> Result<Value, String> {
+        match self.kind {
+            VariableKind::List => input
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| self.parse_item(item))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            _ => self.parse_item(input),
+        }
+    }
+
+    /// `parse` for a single value, or one item of a list, which is checked
+    /// against `validation` on its own.
+    fn parse_item(&self, input: &str) -> Result<Value, String> {
+        if let Some(pattern) = &self.validation {
+            let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+            if !re.is_match(input) {
+                return Err(format!("'{}' doesn't match {}", input, pattern));
+            }
+        }
+
+        match self.kind {
+            VariableKind::String | VariableKind::List => Ok(Value::String(input.to_string())),
+            VariableKind::Bool => match input.to_lowercase().as_str() {
+                "y" | "yes" | "true" => Ok(Value::Bool(true)),
+                "n" | "no" | "false" => Ok(Value::Bool(false)),
+                _ => Err(format!("'{}' isn't yes or no", input)),
+            },
+            VariableKind::Integer => input
+                .parse::<i64>()
+                .map(Value::from)
+                .map_err(|_| format!("'{}' isn't a whole number", input)),
+        }
+    }
+}
+
+/// Applies template folder `root`'s manifest to `renderer`: its engine, and a
+/// value for each variable the context doesn't have yet.  Its defaults are
+/// left to `apply_manifests`, which has to combine those of every layer.  When `interactive`
+/// and there's a terminal, each value is asked for; otherwise defaults are
+/// used and a variable without one is an error.
+pub fn apply_manifest(
+    root: &Path,
+    renderer: &mut Renderer,
+    interactive: bool,
+) -> Result<(), Box<dyn Error>> {
+    let manifest = Manifest::load(root)?;
+    if let Some(engine) = manifest.engine {
+        renderer.set_engine(engine);
+    }
+
+    let interactive = interactive && io::stdin().is_terminal();
+    for variable in manifest.variables {
+        if renderer.has_variable(&variable.name) {
+            continue;
+        }
+        let variable = render_default(variable, renderer)?;
+
+        let value = if interactive {
+            ask_variable(&variable)?
+        } else {
+            variable.answer("").map_err(|_| {
+                format!(
+                    "'{}' has no default and there's no terminal to ask on, supply it with --vars-file",
+                    variable.name
+                )
+            })?
+        };
+        renderer.set_variable(&variable.name, value);
+    }
+
+    Ok(())
+}
+
+/// `apply_manifest` for several templates layered in one run, base template
+/// first.  They have to agree on the engine they're written for, and a
+/// variable declared by more than one is asked for as the latest declares it.
+pub fn apply_manifests(
+    roots: &[PathBuf],
+    renderer: &mut Renderer,
+    interactive: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut engines = Vec::new();
+    for root in roots {
+        engines.push(Manifest::load(root)?.engine.unwrap_or_default());
+    }
+    if engines.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err("the templates use different engines, generate them separately".into());
+    }
+
+    let mut defaults = TemplateDefaults::default();
+    for root in roots {
+        defaults.merge(Manifest::load(root)?.defaults);
+    }
+    renderer.set_template_defaults(&defaults)?;
+    renderer.load_template_files(roots)?;
+
+    for root in roots.iter().rev() {
+        apply_manifest(root, renderer, interactive)?;
+    }
+    Ok(())
+}
+
+/// Renders a default that's itself a template, e.g. `"{{name}}-service"`,
+/// with the values known so far.
+fn render_default(mut variable: Variable, renderer: &Renderer) -> Result<Variable, Box<dyn Error>> {
+    if let Some(serde_yaml::Value::String(default)) = &variable.default
+        && (default.contains("{{") || default.contains("{%"))
+    {
+        let rendered = renderer.render(default).map_err(|e| {
+            format!(
+                "unable to render the default for '{}': {}",
+                variable.name, e
+            )
+        })?;
+        variable.default = Some(serde_yaml::Value::String(rendered));
+    }
+    Ok(variable)
+}
+
+/// Asks for `variable` on the terminal until the answer is valid.
+pub fn ask_variable(variable: &Variable) -> Result<Value, Box<dyn Error>> {
+    loop {
+        match variable.default_text() {
+            Some(default) => print!("{} [{}]: ", variable.question(), default),
+            None => print!("{}: ", variable.question()),
+        }
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Err(format!("no value given for '{}'", variable.name).into());
+        }
+
+        match variable.answer(&input) {
+            Ok(value) => return Ok(value),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
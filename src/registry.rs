@@ -0,0 +1,139 @@
+use std::{error::Error, fs, path::PathBuf, process::Command};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{clean_path, xdg};
+
+/// Index of community templates used when `template_index` isn't set in
+/// `$XDG_CONFIG_HOME/dropkick/config.yaml`.
+pub const DEFAULT_INDEX: &str =
+    "https://raw.githubusercontent.com/TheNotary/dropkick-templates/main/index.json";
+
+/// A list of templates that can be installed by name, served as JSON:
+/// `{"templates": [{"name": "rust-lib", "source": "gh:user/rust-lib", ...}]}`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    #[serde(default)]
+    pub templates: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// What `dropkick install` takes, and the folder it's installed as
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// `gh:user/repo`, or an https or ssh git URL
+    pub source: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Index {
+    /// Where the last index fetched is kept, for when it can't be fetched.
+    pub fn cache_path() -> PathBuf {
+        xdg::data_dir().join("index.json")
+    }
+
+    /// Fetches the index at `location`, an https URL (through `curl`), a
+    /// `file://` URL or a path.  When that fails, or what's fetched isn't an
+    /// index, the copy cached by the last successful fetch is used instead,
+    /// with a warning.
+    pub fn load(location: &str) -> Result<Self, Box<dyn Error>> {
+        let cache = Self::cache_path();
+        let fetched = fetch(location).and_then(|raw| {
+            let index = Self::parse(&raw, location)?;
+            Ok((raw, index))
+        });
+        match fetched {
+            Ok((raw, index)) => {
+                if let Some(parent) = cache.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&cache, &raw)?;
+                Ok(index)
+            }
+            Err(e) if cache.is_file() => {
+                warn!(
+                    "{}, using the copy from {}",
+                    e,
+                    clean_path(&cache).display()
+                );
+                Self::parse(
+                    &fs::read_to_string(&cache)?,
+                    &clean_path(&cache).display().to_string(),
+                )
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn parse(raw: &str, location: &str) -> Result<Self, Box<dyn Error>> {
+        serde_json::from_str(raw)
+            .map_err(|e| format!("unable to parse the template index {}: {}", location, e).into())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&IndexEntry> {
+        self.templates.iter().find(|entry| entry.name == name)
+    }
+
+    /// The entries whose name, description or tags contain every word of
+    /// `term`, ignoring case.  Those with a word in their name come first,
+    /// then they're sorted by name.  An empty term matches everything.
+    pub fn search(&self, term: &str) -> Vec<&IndexEntry> {
+        let words: Vec<String> = term.split_whitespace().map(str::to_lowercase).collect();
+        let mut found: Vec<&IndexEntry> = self
+            .templates
+            .iter()
+            .filter(|entry| {
+                let haystack = format!(
+                    "{} {} {}",
+                    entry.name,
+                    entry.description,
+                    entry.tags.join(" ")
+                )
+                .to_lowercase();
+                words.iter().all(|word| haystack.contains(word))
+            })
+            .collect();
+
+        let in_name = |entry: &IndexEntry| {
+            let name = entry.name.to_lowercase();
+            words.iter().any(|word| name.contains(word))
+        };
+        found.sort_by(|a, b| {
+            in_name(b)
+                .cmp(&in_name(a))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        found
+    }
+}
+
+fn fetch(location: &str) -> Result<String, Box<dyn Error>> {
+    if !location.starts_with("https://") && !location.starts_with("http://") {
+        let path = location.strip_prefix("file://").unwrap_or(location);
+        return fs::read_to_string(path)
+            .map_err(|e| format!("unable to read the template index {}: {}", path, e).into());
+    }
+
+    let mut command = Command::new("curl");
+    command.args(["--fail", "--silent", "--show-error", "--location"]);
+    command.args(["--max-time", "30", location]);
+    debug!("running {:?}", command);
+
+    let output = command
+        .output()
+        .map_err(|e| format!("Failed to execute curl command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "unable to fetch the template index {}: {}",
+            location,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
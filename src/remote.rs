@@ -0,0 +1,255 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{clean_path, get_templates_path};
+
+/// Where a template repository is cloned from.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TemplateSource {
+    pub url: String,
+    /// Folder name the template is installed as unless another is given
+    pub name: String,
+}
+
+impl TemplateSource {
+    /// Parses `gh:user/repo` shorthand or a plain https, ssh or
+    /// `git@host:user/repo` URL.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        // git would take it for an option, like `--upload-pack=...`
+        if source.starts_with('-') {
+            return Err(format!("'{}' isn't a git URL", source));
+        }
+        let url = match source.strip_prefix("gh:") {
+            Some(repo) => {
+                let repo = repo.trim_end_matches('/');
+                if repo.split('/').count() != 2 || repo.split('/').any(str::is_empty) {
+                    return Err(format!("'{}' should look like gh:user/repo", source));
+                }
+                format!("https://github.com/{}.git", repo.trim_end_matches(".git"))
+            }
+            None => source.to_string(),
+        };
+
+        let is_url = ["https://", "http://", "ssh://", "git://", "file://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme));
+        let is_scp_like = url.contains('@') && url.contains(':') && !url.contains("://");
+        if !is_url && !is_scp_like {
+            return Err(format!(
+                "'{}' isn't a git URL, use gh:user/repo or an https or ssh URL",
+                source
+            ));
+        }
+
+        let name = url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .map(|last| last.trim_end_matches(".git"))
+            .unwrap_or_default()
+            .to_string();
+        if name.is_empty() {
+            return Err(format!(
+                "unable to tell the repository name from '{}'",
+                source
+            ));
+        }
+
+        Ok(Self { url, name })
+    }
+}
+
+/// Makes a shallow clone of `url` into `dest`, checking out `reference` (a
+/// branch or tag) instead of the default branch when given.  git's own
+/// progress and errors go straight to the terminal, unless `quiet` (as in the
+/// TUI), when errors are part of the one returned.
+pub fn clone(
+    url: &str,
+    dest: &Path,
+    reference: Option<&str>,
+    quiet: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut command = Command::new("git");
+    // A tag checks out as a detached HEAD, which is expected here
+    command.args(["-c", "advice.detachedHead=false", "clone", "--depth", "1"]);
+    if let Some(reference) = reference {
+        command.args(["--branch", reference]);
+    }
+    if quiet {
+        command.arg("--quiet");
+    }
+    command.arg("--").arg(url).arg(dest);
+    debug!("running {:?}", command);
+
+    if quiet {
+        let output = command.output().map_err(git_spawn_error)?;
+        if !output.status.success() {
+            return Err(format!(
+                "git clone of {} failed: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        return Ok(());
+    }
+
+    let status = command.status().map_err(git_spawn_error)?;
+    if !status.success() {
+        return Err(format!("git clone of {} failed ({})", url, status).into());
+    }
+
+    Ok(())
+}
+
+/// Why git couldn't be run, pointing out the fix when it isn't installed.
+fn git_spawn_error(e: io::Error) -> String {
+    if e.kind() == io::ErrorKind::NotFound {
+        "git isn't installed or isn't on PATH".to_string()
+    } else {
+        format!("Failed to execute git command: {}", e)
+    }
+}
+
+/// Runs a git command inside `dir`, returning its trimmed output.
+fn git_in(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(git_spawn_error)?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed in {}: {}",
+            args.join(" "),
+            clean_path(dir).display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether template folder `root` is a git checkout, i.e. was installed
+/// rather than copied in by hand.
+pub fn is_installed(root: &Path) -> bool {
+    root.join(".git").exists()
+}
+
+pub fn head_commit(root: &Path) -> Result<String, String> {
+    git_in(root, &["rev-parse", "HEAD"])
+}
+
+pub fn origin_url(root: &Path) -> Result<String, String> {
+    git_in(root, &["remote", "get-url", "origin"])
+}
+
+/// The branch checked out in `root`, or `None` when a tag or commit is.
+pub fn current_branch(root: &Path) -> Option<String> {
+    git_in(root, &["symbolic-ref", "--short", "-q", "HEAD"])
+        .ok()
+        .filter(|branch| !branch.is_empty())
+}
+
+/// Fast-forwards the checked out branch to what's upstream.
+pub fn pull(root: &Path) -> Result<(), String> {
+    git_in(root, &["pull", "--ff-only", "--quiet"]).map(|_| ())
+}
+
+/// `git diff --stat` between two commits, one line per changed file plus a
+/// summary line.
+pub fn diff_stat(root: &Path, from: &str, to: &str) -> Result<String, String> {
+    git_in(root, &["diff", "--stat", from, to])
+}
+
+/// Which commit of each installed template is checked out, kept next to the
+/// templates folder (`$XDG_DATA_HOME/dropkick/templates.lock`) so a project can be traced
+/// back to the template version it came from.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub templates: BTreeMap<String, LockedTemplate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedTemplate {
+    pub source: String,
+    /// Branch or tag asked for at install time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    pub commit: String,
+}
+
+impl Lockfile {
+    pub fn path() -> PathBuf {
+        get_templates_path().with_extension("lock")
+    }
+
+    /// Reads the lockfile, which doesn't exist until something is installed.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = Self::path();
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(&path)?;
+        let lockfile = serde_yaml::from_str(&raw)
+            .map_err(|e| format!("unable to parse {}: {}", clean_path(&path).display(), e))?;
+        Ok(lockfile)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Self::path();
+        fs::write(&path, serde_yaml::to_string(self)?)
+            .map_err(|e| format!("unable to write {}: {}", clean_path(&path).display(), e))?;
+        Ok(())
+    }
+
+    /// Records the commit now checked out in template folder `root`.
+    pub fn record(
+        &mut self,
+        name: &str,
+        root: &Path,
+        reference: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let locked = LockedTemplate {
+            source: origin_url(root)?,
+            reference,
+            commit: head_commit(root)?,
+        };
+        self.templates.insert(name.to_string(), locked);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_that_look_like_options_are_rejected() {
+        let err = TemplateSource::parse("--upload-pack=touch pwned@host:repo").unwrap_err();
+        assert!(err.contains("isn't a git URL"), "{err}");
+        assert!(TemplateSource::parse("-c@host:repo").is_err());
+    }
+
+    #[test]
+    fn shorthand_and_urls_are_parsed() {
+        let source = TemplateSource::parse("gh:octocat/web.git").unwrap();
+        assert_eq!(source.url, "https://github.com/octocat/web.git");
+        assert_eq!(source.name, "web");
+        let source = TemplateSource::parse("git@example.com:team/api").unwrap();
+        assert_eq!(source.url, "git@example.com:team/api");
+        assert_eq!(source.name, "api");
+    }
+}
@@ -0,0 +1,90 @@
+use std::{error::Error, fs, path::Path, sync::Arc, thread, time::Duration};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use log::warn;
+use ratatui::{Terminal, backend::TestBackend};
+use syntect::{highlighting::Theme, parsing::SyntaxSet};
+
+use crate::app::{Action, App};
+
+/// Size of the off-screen terminal a replay is drawn on.  The tree widget
+/// only navigates what it last rendered, so every key is preceded by a draw
+/// just like in the live loop.
+const REPLAY_WIDTH: u16 = 120;
+const REPLAY_HEIGHT: u16 = 40;
+
+/// Reads a replay script: key specs separated by whitespace or newlines, with
+/// `#` starting a comment that runs to the end of the line.
+pub fn load_keys(path: &Path) -> Result<Vec<KeyEvent>, Box<dyn Error>> {
+    let script = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read replay file {}: {}", path.display(), e))?;
+
+    let mut keys = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for spec in line.split_whitespace() {
+            let key =
+                parse_key_spec(spec).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+            keys.push(key);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Parses a single key such as `e`, `Down`, `Space` or `Enter`.  Named keys
+/// are matched case-insensitively; single characters are taken literally.
+pub fn parse_key_spec(spec: &str) -> Result<KeyEvent, String> {
+    let mut chars = spec.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyEvent::from(KeyCode::Char(c)));
+    }
+
+    let code = match spec.to_lowercase().as_str() {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => return Err(format!("unknown key '{}'", spec)),
+    };
+
+    Ok(KeyEvent::from(code))
+}
+
+/// Drives the app with scripted keys instead of a live terminal, stopping at
+/// the first key that quits or extracts.  Running out of keys counts as
+/// quitting, so a script has to confirm an extraction explicitly.
+pub fn run(
+    app: &mut App,
+    keys: &[KeyEvent],
+    ss: &Arc<SyntaxSet>,
+    theme: &Arc<Theme>,
+) -> Result<Action, Box<dyn Error>> {
+    let mut terminal = Terminal::new(TestBackend::new(REPLAY_WIDTH, REPLAY_HEIGHT))?;
+
+    for key in keys {
+        // Background work finishes first, so every run sees the same screens
+        while app.is_busy() {
+            if !app.tick() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        terminal.draw(|f| app.render(f))?;
+        match app.handle_key(*key, REPLAY_HEIGHT, ss, theme)? {
+            Action::Continue => {}
+            action => return Ok(action),
+        }
+    }
+
+    warn!("Replay ended without extracting, nothing was imported.");
+    Ok(Action::Quit)
+}
@@ -0,0 +1,51 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where copies of rendered files are kept, relative to the output directory.
+/// They're the common ancestor when a later import merges a newly rendered
+/// file with the local edits made since.
+pub const SHADOW_DIR: &str = ".dropkick/shadow";
+
+/// How a three-way merge went.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeResult {
+    Clean(String),
+    /// Merged, but with conflict markers left in for the user to sort out
+    Conflicted(String),
+}
+
+/// The shadow copy for `dest`, which has to be below `output_dir`.
+pub fn shadow_path(output_dir: &Path, dest: &Path) -> Option<PathBuf> {
+    let relative = dest.strip_prefix(output_dir).ok()?;
+    Some(output_dir.join(SHADOW_DIR).join(relative))
+}
+
+/// Reads what was rendered to `dest` the last time it was imported, if it
+/// was recorded.
+pub fn read(output_dir: &Path, dest: &Path) -> Result<Option<String>, String> {
+    let Some(path) = shadow_path(output_dir, dest) else {
+        return Ok(None);
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!(
+            "unable to read '{}' ({})",
+            path.to_string_lossy(),
+            e
+        )),
+    }
+}
+
+/// Merges the local edits (`base` to `local`) with the template's changes
+/// (`base` to `rendered`) line by line.  Overlapping edits are kept side by
+/// side between git-style conflict markers.
+pub fn three_way_merge(base: &str, local: &str, rendered: &str) -> MergeResult {
+    match diffy::merge(base, local, rendered) {
+        Ok(merged) => MergeResult::Clean(merged),
+        Err(conflicted) => MergeResult::Conflicted(conflicted),
+    }
+}
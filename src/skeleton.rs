@@ -0,0 +1,226 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use regex::Regex;
+
+use crate::{
+    clean_path, config::REPO_CONFIG_FILES, hooks::HOOKS_DIR, manifest::MANIFEST_FILE,
+    templates::is_binary, transaction::set_mode,
+};
+
+/// Folders of a project that never belong in a template made from it.
+const IGNORED_DIRS: &[&str] = &[".git", ".dropkick", "target", "node_modules"];
+
+/// Built-in fields whose values are turned back into placeholders when a
+/// project becomes a template.  Where two spell the same, the first wins.
+pub const TEMPLATIZED_FIELDS: &[&str] = &[
+    "git_repo_ssh_url",
+    "git_repo_url",
+    "git_repo_path",
+    "registry_repo_path",
+    "image_path",
+    "email",
+    "author",
+    "github_user",
+    "name",
+    "underscored_name",
+    "pascal_name",
+    "camel_name",
+    "screamcase_name",
+    "title",
+];
+
+const MANIFEST_SKELETON: &str = r#"# Settings for this template, all optional.
+
+# A template whose files this one builds on
+# extends: base
+
+# handlebars (the default), tera or liquid
+# engine: handlebars
+
+# Values asked for when the template is used, beyond the built-in fields
+# variables:
+#   - name: port
+#     type: integer
+#     default: 8080
+#     prompt: Port to listen on?
+
+# Files only generated when a condition holds
+# files:
+#   - path: Dockerfile
+#     when: "{{bin}}"
+"#;
+
+const POST_GEN_SKELETON: &str = r#"#!/bin/sh
+# Runs in the generated project once every file is written.  Template values
+# are exported as DROPKICK_<NAME>, e.g. $DROPKICK_PASCAL_NAME.
+#
+# git init -q
+"#;
+
+const EXAMPLE_SKELETON: &str = r#"# {{title}}
+
+Generated by dropkick.  `{{name}}` is the project's name, `{{underscored_name}}`
+and `{{pascal_name}}` are the same in other cases.
+"#;
+
+/// Writes a new template into `dir`: a commented `dropkick.yaml`, a
+/// `post_gen.sh` hook that does nothing yet, and an example file.
+pub fn create_skeleton(dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir.join(HOOKS_DIR))?;
+    fs::write(dir.join(MANIFEST_FILE), MANIFEST_SKELETON)?;
+    fs::write(dir.join("README.md.tt"), EXAMPLE_SKELETON)?;
+
+    let hook = dir.join(HOOKS_DIR).join("post_gen.sh");
+    fs::write(&hook, POST_GEN_SKELETON)?;
+    set_mode(&hook, 0o755)?;
+
+    Ok(())
+}
+
+/// How a reverse templated project went.
+#[derive(Debug, Default)]
+pub struct ReverseReport {
+    pub files: usize,
+    /// Files whose name or content had a value replaced
+    pub replaced: usize,
+}
+
+/// Turns the files of `project` into template `dir`, with each of `values`
+/// (e.g. `my-app` for `name`, `MyApp` for `pascal_name`) in file names and
+/// text replaced by a placeholder for its field.  Binary files are copied as
+/// they are.
+pub fn reverse_template(
+    project: &Path,
+    values: Vec<(String, String)>,
+    dir: &Path,
+) -> Result<ReverseReport, Box<dyn Error>> {
+    let replacer = Replacer::new(values)?;
+    let mut report = ReverseReport::default();
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(MANIFEST_FILE), MANIFEST_SKELETON)?;
+
+    for src in project_files(project)? {
+        let relative = src.strip_prefix(project).unwrap_or(&src);
+        let templated: PathBuf = relative
+            .iter()
+            .map(|segment| replacer.replace(&segment.to_string_lossy()))
+            .collect();
+        let mut changed = templated != relative;
+        let mut dest = dir.join(templated).into_os_string();
+        dest.push(".tt");
+        let dest = PathBuf::from(dest);
+
+        let bytes = fs::read(&src)?;
+        if is_binary(&src, &bytes)? {
+            write(&dest, &bytes)?;
+        } else {
+            let text = String::from_utf8_lossy(&bytes);
+            if text.contains("{{") {
+                warn!(
+                    "'{}' already has '{{{{' in it, check it still renders as intended",
+                    clean_path(&dest).display()
+                );
+            }
+            let replaced = replacer.replace(&text);
+            changed |= replaced != text;
+            write(&dest, replaced.as_bytes())?;
+        }
+
+        let mode = fs::metadata(&src)?.permissions();
+        fs::set_permissions(&dest, mode)?;
+        report.files += 1;
+        if changed {
+            report.replaced += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+fn write(dest: &Path, content: &[u8]) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    debug!("writing {}", dest.display());
+    fs::write(dest, content)?;
+    Ok(())
+}
+
+/// Every file below `project`, except version control, build output and
+/// symlinks, sorted for stable output.
+fn project_files(project: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![project.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_symlink() {
+                warn!("Skipping '{}', symlinks aren't copied", path.display());
+            } else if path.is_dir() {
+                if !IGNORED_DIRS.contains(&name.as_ref()) {
+                    dirs.push(path);
+                }
+            } else if !REPO_CONFIG_FILES.contains(&name.as_ref()) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Replaces known values with the placeholders that render back to them.
+struct Replacer {
+    pattern: Regex,
+    /// Each value, and the field it's a placeholder for
+    values: Vec<(String, String)>,
+}
+
+impl Replacer {
+    fn new(mut values: Vec<(String, String)>) -> Result<Self, Box<dyn Error>> {
+        // Anything shorter would be replaced all over the place
+        values.retain(|(value, _)| value.chars().count() >= 3);
+        let mut seen = HashSet::new();
+        values.retain(|(value, _)| seen.insert(value.clone()));
+        if values.is_empty() {
+            return Err("there are no values to replace with placeholders".into());
+        }
+
+        // Longest first, so a value that contains another one wins
+        let mut alternatives: Vec<&String> = values.iter().map(|(value, _)| value).collect();
+        alternatives.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        let pattern = alternatives
+            .iter()
+            .map(|value| regex::escape(value))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        Ok(Self {
+            pattern: Regex::new(&pattern)?,
+            values,
+        })
+    }
+
+    fn replace(&self, text: &str) -> String {
+        self.pattern
+            .replace_all(text, |caps: &regex::Captures| {
+                let found = &caps[0];
+                let field = self
+                    .values
+                    .iter()
+                    .find(|(value, _)| value == found)
+                    .map_or("name", |(_, field)| field.as_str());
+                format!("{{{{{}}}}}", field)
+            })
+            .into_owned()
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::config::{Config, parse_config};
+
+/// Folder of a template holding its snapshot tests, one folder per case.
+pub const TESTS_DIR: &str = "tests";
+
+/// Settings a case renders the template with, in `.dropkickrc` format.
+const FIXTURE_FILE: &str = "config.yaml";
+
+/// Folder of a case holding the files the template should generate.
+const EXPECTED_DIR: &str = "expected";
+
+/// One snapshot test: `tests/<name>/config.yaml` and `tests/<name>/expected/`.
+#[derive(Debug)]
+pub struct Fixture {
+    pub name: String,
+    pub config: Config,
+    pub expected: PathBuf,
+}
+
+/// The cases in template folder `root`, sorted by name.  A case without a
+/// `config.yaml` renders with a project named after its folder.
+pub fn fixtures(root: &Path) -> Result<Vec<Fixture>, Box<dyn Error>> {
+    let tests = root.join(TESTS_DIR);
+    let Ok(entries) = fs::read_dir(&tests) else {
+        return Ok(Vec::new());
+    };
+
+    let mut fixtures = Vec::new();
+    for dir in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if !dir.join(EXPECTED_DIR).is_dir() {
+            continue;
+        }
+        let name = dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let path = dir.join(FIXTURE_FILE);
+        let config = match fs::read_to_string(&path) {
+            Ok(raw) => parse_config(&raw)
+                .map_err(|e| format!("unable to parse '{}': {}", path.display(), e))?,
+            Err(_) => Config::new(&name),
+        };
+        fixtures.push(Fixture {
+            name,
+            config,
+            expected: dir.join(EXPECTED_DIR),
+        });
+    }
+
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+/// Whether `path` in template folder `root` is part of a snapshot test
+/// rather than of the template itself.
+pub fn is_fixture_file(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let parts: Vec<Component> = relative.components().collect();
+    match parts.as_slice() {
+        [tests, _, file, rest @ ..] if tests.as_os_str() == TESTS_DIR => {
+            let file = file.as_os_str();
+            file == EXPECTED_DIR || (file == FIXTURE_FILE && rest.is_empty())
+        }
+        _ => false,
+    }
+}
+
+/// How a rendered file differs from what a case expects.
+#[derive(Debug)]
+pub enum Difference {
+    /// Expected, but the template didn't generate it
+    Missing(PathBuf),
+    /// Generated, but not expected
+    Unexpected(PathBuf),
+    /// Generated with other content, as a unified diff from the expected text
+    Changed(PathBuf, String),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Missing(path) => {
+                write!(f, "{}: expected but not generated", path.display())
+            }
+            Difference::Unexpected(path) => {
+                write!(f, "{}: generated but not expected", path.display())
+            }
+            Difference::Changed(path, diff) => {
+                write!(f, "{}: differs\n{}", path.display(), diff.trim_end())
+            }
+        }
+    }
+}
+
+/// Compares the files rendered into `actual` with those in `expected`,
+/// leaving out dropkick's own `.dropkick` folder.
+pub fn compare(expected: &Path, actual: &Path) -> Result<Vec<Difference>, Box<dyn Error>> {
+    let expected_files = relative_files(expected)?;
+    let actual_files = relative_files(actual)?;
+
+    let mut differences = Vec::new();
+    for path in expected_files.union(&actual_files) {
+        if !actual_files.contains(path) {
+            differences.push(Difference::Missing(path.clone()));
+            continue;
+        }
+        if !expected_files.contains(path) {
+            differences.push(Difference::Unexpected(path.clone()));
+            continue;
+        }
+
+        let want = fs::read(expected.join(path))?;
+        let got = fs::read(actual.join(path))?;
+        if want == got {
+            continue;
+        }
+        let diff = match (String::from_utf8(want), String::from_utf8(got)) {
+            (Ok(want), Ok(got)) => {
+                let patch = diffy::create_patch(&want, &got).to_string();
+                // The ---/+++ header only names "original" and "modified"
+                let hunks: String = patch.split_inclusive('\n').skip(2).collect();
+                format!("--- expected\n+++ generated\n{}", hunks)
+            }
+            _ => "binary content differs\n".to_string(),
+        };
+        differences.push(Difference::Changed(path.clone(), diff));
+    }
+
+    Ok(differences)
+}
+
+/// Replaces a case's expected files with what was rendered into `actual`.
+pub fn update(expected: &Path, actual: &Path) -> Result<(), Box<dyn Error>> {
+    fs::remove_dir_all(expected)?;
+    for path in relative_files(actual)? {
+        let dest = expected.join(&path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(actual.join(&path), &dest)?;
+    }
+    fs::create_dir_all(expected)?;
+
+    Ok(())
+}
+
+/// Every file below `dir`, relative to it.
+fn relative_files(dir: &Path) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for path in fs::read_dir(&current)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+        {
+            if path.is_dir() {
+                if current != dir || path.file_name().is_none_or(|n| n != ".dropkick") {
+                    dirs.push(path);
+                }
+            } else {
+                files.insert(path.strip_prefix(dir).unwrap_or(&path).to_path_buf());
+            }
+        }
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,77 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// Where time goes during a run, for `scaffold --stats`.  The counters are
+/// process-wide so the code being measured doesn't have to pass anything
+/// around; they're cheap enough to collect even when nobody asks for them.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Reading `.dropkickrc` and building the render context
+    Config,
+    Render,
+    Write,
+}
+
+static PHASE_NANOS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static GIT_INVOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs `f`, adding the time it took to `phase`.
+pub fn timed<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    PHASE_NANOS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+pub fn count_git_invocation() {
+    GIT_INVOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Snapshot of the counters, in milliseconds.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub config_ms: f64,
+    pub git_invocations: usize,
+    pub render_ms: f64,
+    pub write_ms: f64,
+    pub total_ms: f64,
+    pub files: usize,
+}
+
+impl Stats {
+    pub fn collect(total: Duration, files: usize) -> Self {
+        let ms = |phase: Phase| {
+            Duration::from_nanos(PHASE_NANOS[phase as usize].load(Ordering::Relaxed)).as_secs_f64()
+                * 1000.0
+        };
+
+        Self {
+            config_ms: ms(Phase::Config),
+            git_invocations: GIT_INVOCATIONS.load(Ordering::Relaxed),
+            render_ms: ms(Phase::Render),
+            write_ms: ms(Phase::Write),
+            total_ms: total.as_secs_f64() * 1000.0,
+            files,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stats:")?;
+        writeln!(f, "  Config:          {:.1} ms", self.config_ms)?;
+        writeln!(f, "  Git invocations: {}", self.git_invocations)?;
+        writeln!(f, "  Rendering:       {:.1} ms", self.render_ms)?;
+        writeln!(f, "  Writing:         {:.1} ms", self.write_ms)?;
+        write!(
+            f,
+            "  Total:           {:.1} ms ({} files)",
+            self.total_ms, self.files
+        )
+    }
+}
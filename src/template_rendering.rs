@@ -1,26 +1,1301 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    env,
+    error::Error,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, trace};
 use regex::Regex;
-use serde_json::json;
+use serde_json::{Map, Value, json};
+
+use chrono::{DateTime, Datelike, FixedOffset, Local};
+use clap::ValueEnum;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason,
+    ScopedJson, handlebars_helper,
+};
+use serde::Deserialize;
+
+use crate::{
+    cargo_generate, case, clean_path,
+    config::{Config, RewriteRule, UserConfig, env_variables, find_repo_config},
+    cookiecutter, erb,
+    error::DropkickError,
+    exec,
+    interpolation_config::{ConfigBuilder, InterpolationConfig, detect_project_type, get_git_sha},
+    manifest::{MANIFEST_FILE, TemplateDefaults, is_truthy},
+};
+
+/// Ruby string interpolation such as `#{config[:name]}`.
+const RUBY_CONFIG_PATTERN: &str = r"#\{config\[:(\w+)\]\}";
+
+/// Extra inputs layered onto the built-in interpolation fields.
+#[derive(Debug, Default, Clone)]
+pub struct ContextOptions {
+    /// JSON or YAML object of additional values, e.g. generated by another tool
+    pub vars_file: Option<PathBuf>,
+    /// Merge nested maps key by key instead of replacing them wholesale
+    pub deep_merge: bool,
+    /// Where to write the fully merged context for troubleshooting, `-` for stderr
+    pub dump_context: Option<PathBuf>,
+    /// Image tag given on the command line, overriding `.dropkickrc`
+    pub image_tag: Option<String>,
+    /// Directory files are rendered into, inspected to detect `project_type`.
+    /// Empty means the working directory.
+    pub output_dir: PathBuf,
+    /// The output is a brand-new project, so the working directory's git
+    /// remote and HEAD say nothing about it
+    pub new_project: bool,
+    /// Engine given on the command line, overriding each template's manifest
+    pub engine: Option<Engine>,
+    /// `--prefix`, overriding `.dropkickrc`'s
+    pub prefix: Option<String>,
+    /// `--bin` and `--test`, which switch the flags on even when
+    /// `.dropkickrc` leaves them off
+    pub bin: bool,
+    pub test: bool,
+    /// Values from `$XDG_CONFIG_HOME/dropkick/config.yaml`, beneath `.dropkickrc`'s
+    pub user_variables: Map<String, Value>,
+    /// What the templates being rendered set in their `dropkick.yaml`, beneath
+    /// everything else
+    pub template_defaults: TemplateDefaults,
+    /// What the templates being rendered hold in their `_data` folders,
+    /// beneath the repo's `data` folder
+    pub template_data: Map<String, Value>,
+    /// Leave out everything from the machine, git, `DROPKICK_VAR_*` and the
+    /// repo's data files, with fixed stand-ins for what git would say, so
+    /// snapshot tests render the same anywhere
+    pub pinned: bool,
+}
+
+/// Builds the context handed to the template engine.  Sources are layered with later
+/// ones taking precedence:
+///
+/// 1. values from `--vars-file`
+/// 2. the built-in `InterpolationConfig` fields
+/// 3. values from `$XDG_CONFIG_HOME/dropkick/config.yaml`
+/// 4. `variables` in `.dropkickrc`
+/// 5. `DROPKICK_VAR_*` environment variables
+///
+/// The templates' defaults and the `data` files go beneath all of them.
+pub fn build_context(
+    repo_config: &Config,
+    options: &ContextOptions,
+) -> Result<Value, Box<dyn Error>> {
+    let (context, _) = resolve_context(repo_config, options)?;
+
+    if let Some(target) = &options.dump_context {
+        dump_context(&context, target)?;
+    }
+
+    Ok(context)
+}
+
+/// `build_context`, along with where each top-level value came from.
+pub fn resolve_context(
+    repo_config: &Config,
+    options: &ContextOptions,
+) -> Result<(Value, BTreeMap<String, String>), Box<dyn Error>> {
+    let env = if options.pinned {
+        Map::new()
+    } else {
+        env_variables()
+    };
+    let user_config = clean_path(&UserConfig::path()).display().to_string();
+    let repo_config_file = find_repo_config().map_or_else(
+        || ".dropkickrc".to_string(),
+        |path| path.display().to_string(),
+    );
+    let layers = [
+        (user_config.as_str(), &options.user_variables),
+        (repo_config_file.as_str(), &repo_config.variables),
+        ("DROPKICK_VAR_*", &env),
+    ];
+    let mut overrides = Map::new();
+    for (_, values) in &layers {
+        overrides.extend((*values).clone());
+    }
+
+    let defaults = &options.template_defaults;
+    let prefix = options
+        .prefix
+        .clone()
+        .or_else(|| repo_config.project.prefix.clone())
+        .or_else(|| {
+            Some(repo_config.project.template.clone()).filter(|template| !template.is_empty())
+        })
+        .or_else(|| defaults.prefix.clone())
+        .unwrap_or_default();
+    let bin = options.bin || repo_config.project.bin;
+    let test = options.test || repo_config.project.test;
+    let builder = ConfigBuilder::new(repo_config.project.name.clone(), prefix)
+        .github_user(repo_config.project.github_user.clone())
+        .bin(bin || defaults.bin == Some(true))
+        .test(test || defaults.test == Some(true))
+        .ext(defaults.ext.clone().unwrap_or_default())
+        .overrides(overrides.clone())
+        .project_type(detect_project_type(&options.output_dir))
+        .inspect_repo(!options.new_project)
+        .git_sha((!options.new_project).then(get_git_sha).flatten())
+        .image_tag(
+            options
+                .image_tag
+                .clone()
+                .or_else(|| repo_config.project.image_tag.clone()),
+        );
+    let mut built_in = if options.pinned {
+        builder.build_pinned()?
+    } else {
+        builder.build()?
+    };
+    for (field, applies) in [
+        ("ext", defaults.ext.is_some()),
+        ("bin", !bin && defaults.bin == Some(true)),
+        ("test", !test && defaults.test == Some(true)),
+    ] {
+        if applies {
+            built_in
+                .sources
+                .insert(field.to_string(), MANIFEST_FILE.to_string());
+        }
+    }
+
+    let mut context = match &options.vars_file {
+        Some(path) => load_vars_file(path)?,
+        None => Value::Object(Map::new()),
+    };
+    let mut sources: BTreeMap<String, String> = context
+        .as_object()
+        .map(|map| {
+            map.keys()
+                .map(|key| (key.clone(), "--vars-file".to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    for field in InterpolationConfig::field_names() {
+        let source = built_in
+            .sources
+            .get(&field)
+            .cloned()
+            .unwrap_or_else(|| "derived".to_string());
+        sources.insert(field, source);
+    }
+    for (layer, values) in &layers {
+        for key in values.keys() {
+            let source = match *layer {
+                "DROPKICK_VAR_*" => format!("DROPKICK_VAR_{}", key.to_uppercase()),
+                layer => layer.to_string(),
+            };
+            sources.insert(key.clone(), source);
+        }
+    }
+
+    merge_values(&mut context, json!(built_in), options.deep_merge);
+    merge_values(&mut context, Value::Object(overrides), options.deep_merge);
+    if let Value::Object(map) = &mut context {
+        for (key, value) in &defaults.variables {
+            if !map.contains_key(key) {
+                map.insert(key.clone(), value.clone());
+                sources.insert(key.clone(), MANIFEST_FILE.to_string());
+            }
+        }
+
+        let mut data = options.template_data.clone();
+        let repo_data = match options.pinned {
+            true => Map::new(),
+            false => load_data(&[PathBuf::from(DATA_DIR)])?,
+        };
+        let source = match (data.is_empty(), repo_data.is_empty()) {
+            (false, false) => format!("{}/ and {}/", TEMPLATE_DATA_DIR, DATA_DIR),
+            (true, false) => format!("{}/", DATA_DIR),
+            _ => format!("{}/", TEMPLATE_DATA_DIR),
+        };
+        data.extend(repo_data);
+        if !data.is_empty() && !map.contains_key("data") {
+            map.insert("data".to_string(), Value::Object(data));
+            sources.insert("data".to_string(), source);
+        }
+    }
+
+    Ok((context, sources))
+}
+
+fn dump_context(context: &Value, target: &Path) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(context)?;
+
+    if target == Path::new("-") {
+        eprintln!("{}", json);
+    } else {
+        fs::write(target, json + "\n")
+            .map_err(|e| format!("unable to write context dump {}: {}", target.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// The template syntaxes dropkick can render.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// `{{name}}`, plus ERB-style `<%= config[:name] %>` tags
+    #[default]
+    Handlebars,
+    /// ERB as the Ruby version of dropkick wrote it, `<%= config[:name] %>`
+    /// and `<% if config[:bin] %>` blocks, translated to handlebars
+    Erb,
+    /// Jinja2-style `{{ name }}`, `{% if %}` blocks and filters
+    Tera,
+    /// Jekyll/Shopify-style `{{ name | upcase }}` and `{% if %}` tags
+    Liquid,
+    /// Cookiecutter's Jinja2, `{{ cookiecutter.name }}`, rendered with Tera
+    Cookiecutter,
+    /// cargo-generate's Liquid, with its `{{project-name}}` style built-ins
+    #[serde(rename = "cargo-generate")]
+    CargoGenerate,
+}
+
+impl Engine {
+    pub fn backend(self) -> &'static dyn TemplateEngine {
+        match self {
+            Engine::Handlebars => &HandlebarsEngine,
+            Engine::Erb => &ErbEngine,
+            Engine::Tera => &TeraEngine,
+            Engine::Liquid => &LiquidEngine,
+            Engine::Cookiecutter => &CookiecutterEngine,
+            Engine::CargoGenerate => &CargoGenerateEngine,
+        }
+    }
+}
+
+/// A template syntax problem, with the 1-based line it's on where the engine
+/// reports one.
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub line: Option<usize>,
+    pub reason: String,
+}
+
+/// Renders template source against the merged JSON context.
+pub trait TemplateEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>>;
+
+    /// `render` with the templates' `_partials`, by name, for engines that
+    /// can include them.
+    fn render_with_partials(
+        &self,
+        template: &str,
+        context: &Value,
+        _partials: &BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        self.render(template, context)
+    }
+
+    /// Parses `template` without rendering it, so syntax errors can be
+    /// reported with their position.
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError>;
+}
+
+struct HandlebarsEngine;
+
+impl TemplateEngine for HandlebarsEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        Ok(render_template_with_handlebars(template, context)?)
+    }
+
+    fn render_with_partials(
+        &self,
+        template: &str,
+        context: &Value,
+        partials: &BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        Ok(render_handlebars(
+            &erb::translate_output_tags(template),
+            context,
+            partials,
+        )?)
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        Handlebars::new()
+            .register_template_string("template", erb::translate_output_tags(template))
+            .map_err(|e| SyntaxError {
+                line: e.pos().map(|(line, _)| line),
+                reason: e.reason().to_string(),
+            })
+    }
+}
+
+struct ErbEngine;
+
+impl TemplateEngine for ErbEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        self.render_with_partials(template, context, &BTreeMap::new())
+    }
+
+    fn render_with_partials(
+        &self,
+        template: &str,
+        context: &Value,
+        partials: &BTreeMap<String, String>,
+    ) -> Result<String, Box<dyn Error>> {
+        Ok(render_handlebars(
+            &erb::translate(template)?,
+            context,
+            partials,
+        )?)
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        let translated = erb::translate(template).map_err(|e| match e {
+            DropkickError::Erb { line, reason } => SyntaxError {
+                line: Some(line),
+                reason,
+            },
+            e => SyntaxError {
+                line: None,
+                reason: e.to_string(),
+            },
+        })?;
+        Handlebars::new()
+            .register_template_string("template", translated)
+            .map_err(|e| SyntaxError {
+                line: None,
+                reason: e.reason().to_string(),
+            })
+    }
+}
+
+struct TeraEngine;
+
+impl TemplateEngine for TeraEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        let context = tera::Context::from_value(context.clone())?;
+        tera::Tera::one_off(template, &context, false).map_err(|e| tera_error_chain(&e).into())
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        tera::Tera::default()
+            .add_raw_template("template", template)
+            .map_err(|e| parse_pest_report(&tera_error_chain(&e)))
+    }
+}
+
+struct CookiecutterEngine;
+
+impl TemplateEngine for CookiecutterEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        TeraEngine.render(&cookiecutter::to_tera(template), context)
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        TeraEngine.check_syntax(&cookiecutter::to_tera(template))
+    }
+}
+
+struct CargoGenerateEngine;
+
+impl TemplateEngine for CargoGenerateEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        LiquidEngine.render(template, &cargo_generate::with_builtins(context))
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        LiquidEngine.check_syntax(template)
+    }
+}
+
+struct LiquidEngine;
+
+impl LiquidEngine {
+    fn parse(template: &str) -> Result<liquid::Template, liquid::Error> {
+        liquid::ParserBuilder::with_stdlib()
+            .filter(PascalCase)
+            .filter(UpperCamelCase)
+            .filter(LowerCamelCase)
+            .filter(SnakeCase)
+            .filter(KebabCase)
+            .filter(ShoutySnakeCase)
+            .filter(ShoutyKebabCase)
+            .filter(TitleCase)
+            .build()?
+            .parse(template)
+    }
+}
+
+impl TemplateEngine for LiquidEngine {
+    fn render(&self, template: &str, context: &Value) -> Result<String, Box<dyn Error>> {
+        let globals = liquid::model::to_object(context)?;
+        Ok(Self::parse(template)?.render(&globals)?)
+    }
+
+    fn check_syntax(&self, template: &str) -> Result<(), SyntaxError> {
+        Self::parse(template)
+            .map(|_| ())
+            .map_err(|e| parse_pest_report(&e.to_string()))
+    }
+}
+
+/// Tera and Liquid both report parse errors as a pest report: a ` --> line:col`
+/// pointer, the offending source and a `= reason` footer.
+fn parse_pest_report(report: &str) -> SyntaxError {
+    let line = Regex::new(r"-->\s*(\d+):\d+")
+        .unwrap()
+        .captures(report)
+        .and_then(|caps| caps[1].parse().ok());
+    let reason = report
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("= "))
+        .unwrap_or(report)
+        .to_string();
+
+    SyntaxError { line, reason }
+}
+
+/// Tera's top-level errors just say which template failed, the detail (with
+/// its position) is in the sources.
+fn tera_error_chain(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+/// Everything needed to render templates for one run: the merged context, the
+/// engine and the `.dropkickrc` rewrite rules applied before the engine sees
+/// a template.
+#[derive(Clone)]
+pub struct Renderer {
+    pub context: Value,
+    rewrites: Vec<(Regex, String)>,
+    engine: Engine,
+    /// `--engine` was given, which beats whatever a template's manifest says
+    engine_pinned: bool,
+    /// What the context was built from, to build it again with a template's
+    /// defaults
+    repo_config: Config,
+    options: ContextOptions,
+    /// The templates' `_partials`, by name
+    partials: BTreeMap<String, String>,
+    /// Rendering one of the files a `{{#each list}}` name stands for, so
+    /// `this` is the list item in `item`
+    looping: bool,
+}
+
+impl Renderer {
+    pub fn new(repo_config: &Config, options: &ContextOptions) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            context: build_context(repo_config, options)?,
+            rewrites: compile_rewrite_rules(&repo_config.rewrite_rules)?,
+            engine: options.engine.unwrap_or_default(),
+            engine_pinned: options.engine.is_some(),
+            repo_config: repo_config.clone(),
+            options: options.clone(),
+            partials: BTreeMap::new(),
+            looping: false,
+        })
+    }
+
+    /// Builds the context again with the defaults of the templates being
+    /// rendered beneath everything else.  Values set since are lost, so this
+    /// comes before any are asked for.
+    pub fn set_template_defaults(
+        &mut self,
+        defaults: &TemplateDefaults,
+    ) -> Result<(), Box<dyn Error>> {
+        if defaults.is_empty() && self.options.template_defaults.is_empty() {
+            return Ok(());
+        }
+        self.options.template_defaults = defaults.clone();
+        self.context = resolve_context(&self.repo_config, &self.options)?.0;
+        Ok(())
+    }
+
+    pub fn render(&self, template: &str) -> Result<String, Box<dyn Error>> {
+        let mut template = template.to_string();
+        for (re, replacement) in &self.rewrites {
+            template = re.replace_all(&template, replacement.as_str()).to_string();
+        }
+        if self.looping {
+            template = bind_this(&template);
+        }
+
+        trace!(
+            "rendering {} bytes with {:?} after {} rewrite rule(s)",
+            template.len(),
+            self.engine,
+            self.rewrites.len()
+        );
+        self.engine
+            .backend()
+            .render_with_partials(&template, &self.context, &self.partials)
+    }
+
+    /// Makes the partials in the `_partials` folders of `roots` includable
+    /// and puts their `_data` files under `data`, replacing any from before.
+    /// Like `set_template_defaults`, this builds the context again when the
+    /// data changes.
+    pub fn load_template_files(&mut self, roots: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        self.partials = load_partials(roots)?;
+        let dirs: Vec<PathBuf> = roots
+            .iter()
+            .map(|root| root.join(TEMPLATE_DATA_DIR))
+            .collect();
+        let data = load_data(&dirs)?;
+        if data != self.options.template_data {
+            self.options.template_data = data;
+            self.context = resolve_context(&self.repo_config, &self.options)?.0;
+        }
+        Ok(())
+    }
+
+    /// Switches to the engine a template's manifest asks for, unless one was
+    /// given on the command line.
+    pub fn set_engine(&mut self, engine: Engine) {
+        if !self.engine_pinned {
+            self.engine = engine;
+        }
+    }
+
+    /// Whether top-level value `name` is set and reads as true, for
+    /// `main.rs.tt[bin]` style conditions.
+    pub fn flag(&self, name: &str) -> bool {
+        match self.context.get(name) {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(b)) => *b,
+            Some(Value::String(s)) => is_truthy(s),
+            Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+            Some(Value::Array(items)) => !items.is_empty(),
+            Some(Value::Object(map)) => !map.is_empty(),
+        }
+    }
 
-use handlebars::{Handlebars, RenderError};
+    /// Whether anything has supplied a value for top-level key `name`.
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.context.get(name).is_some()
+    }
 
-use crate::{config::Config, interpolation_config::ConfigBuilder};
+    /// Adds a value declared in a template's `dropkick.yaml`.
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        if let Value::Object(map) = &mut self.context {
+            map.insert(name.to_string(), value);
+        }
+    }
+}
+
+impl Renderer {
+    /// Renders placeholders in a single file or folder name.  Names without
+    /// anything that looks like a placeholder are returned as-is, which keeps
+    /// the common case from paying for a render.
+    pub fn render_name(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        let name = match loop_segment(name) {
+            Some((_, rest)) if self.looping => rest,
+            Some((list, _)) => {
+                return Err(format!(
+                    "'{}' is only expanded into a file per item of {} when importing",
+                    name, list
+                )
+                .into());
+            }
+            None => name,
+        };
+        if self.rewrites.is_empty()
+            && !name.contains("{{")
+            && !name.contains("<%")
+            && !name.contains("{%")
+        {
+            return Ok(name.to_string());
+        }
+
+        self.render(name)
+    }
+
+    /// The renderers for the files `path` stands for: itself, or when a
+    /// folder or file name in it starts with `{{#each services}}`, one for
+    /// each item of `services`, with the item as `item` (and `this`) and its
+    /// position as `item_index`.
+    pub fn for_each_item(&self, path: &Path) -> Result<Vec<Cow<'_, Renderer>>, Box<dyn Error>> {
+        let Some(list) = loop_list(path) else {
+            return Ok(vec![Cow::Borrowed(self)]);
+        };
+
+        let items = match self.context.get(list) {
+            Some(Value::Array(items)) => items,
+            Some(Value::Null) | None => {
+                return Err(
+                    format!("'{}' has no value to generate a file per item of", list).into(),
+                );
+            }
+            Some(_) => {
+                return Err(
+                    format!("'{}' isn't a list to generate a file per item of", list).into(),
+                );
+            }
+        };
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let mut renderer = self.clone();
+                renderer.set_variable("item", item.clone());
+                renderer.set_variable("item_index", Value::from(index));
+                renderer.looping = true;
+                Cow::Owned(renderer)
+            })
+            .collect())
+    }
+}
+
+/// The list a file is generated once per item of, when a folder or file name
+/// in `path` starts with `{{#each services}}`.
+pub fn loop_list(path: &Path) -> Option<&str> {
+    path.iter()
+        .find_map(|segment| Some(loop_segment(segment.to_str()?)?.0))
+}
+
+/// For a file or folder name starting with `{{#each services}}`, the list's
+/// name and the rest of the name.
+fn loop_segment(name: &str) -> Option<(&str, &str)> {
+    let (list, rest) = name.strip_prefix("{{#each ")?.split_once("}}")?;
+    let list = list.trim();
+    if list.is_empty() || list.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((list, rest))
+}
+
+/// Rewrites `this` to `item` in every tag outside of an `#each` or `#with`
+/// block, which have a `this` of their own, so a file generated per list item
+/// can refer to the item as `this`.
+fn bind_this(template: &str) -> String {
+    let tags = Regex::new(r"(?s)\\?\{\{!--.*?--~?\}\}|\\?\{\{(.*?)\}\}").unwrap();
+    let literals = Regex::new(r#""[^"]*"|'[^']*'"#).unwrap();
+    let this = Regex::new(r"(^|[\s(={~])this\b").unwrap();
+
+    let mut bound = String::with_capacity(template.len());
+    let mut copied = 0;
+    let mut depth = 0usize;
+    for caps in tags.captures_iter(template) {
+        let (Some(whole), Some(inner)) = (caps.get(0), caps.get(1)) else {
+            continue;
+        };
+        if whole.as_str().starts_with('\\') {
+            continue;
+        }
+        let tag = inner.as_str().trim_start_matches(['{', '~']).trim_start();
+        if let Some(helper) = tag.strip_prefix('/') {
+            if matches!(helper.trim_end_matches(['}', '~']).trim(), "each" | "with") {
+                depth = depth.saturating_sub(1);
+            }
+            continue;
+        }
+        // An opening tag's arguments still have the outer `this`
+        if depth == 0 {
+            bound.push_str(&template[copied..inner.start()]);
+            let mut last = 0;
+            for literal in literals.find_iter(inner.as_str()) {
+                bound.push_str(
+                    &this.replace_all(&inner.as_str()[last..literal.start()], "${1}item"),
+                );
+                bound.push_str(literal.as_str());
+                last = literal.end();
+            }
+            bound.push_str(&this.replace_all(&inner.as_str()[last..], "${1}item"));
+            copied = inner.end();
+        }
+        if let Some(rest) = tag.strip_prefix('#')
+            && (rest.starts_with("each") || rest.starts_with("with"))
+            && rest[4..].starts_with(char::is_whitespace)
+        {
+            depth += 1;
+        }
+    }
+    bound.push_str(&template[copied..]);
+    bound
+}
+
+/// Compiles the user-supplied rewrite rules, in order, so a bad pattern is
+/// reported up front instead of on the first file that needs it.
+fn compile_rewrite_rules(rules: &[RewriteRule]) -> Result<Vec<(Regex, String)>, Box<dyn Error>> {
+    rules
+        .iter()
+        .map(|rule| {
+            let re = Regex::new(&rule.pattern).map_err(|e| {
+                format!(
+                    "invalid rewrite rule pattern '{}' in .dropkickrc: {}",
+                    rule.pattern, e
+                )
+            })?;
+            Ok((re, rule.replacement.clone()))
+        })
+        .collect()
+}
 
 pub fn render_template_with_handlebars(
     my_template: &str,
-    repo_config: &Config,
+    context: &Value,
+) -> Result<String, RenderError> {
+    render_handlebars(
+        &erb::translate_output_tags(my_template),
+        context,
+        &BTreeMap::new(),
+    )
+}
+
+fn render_handlebars(
+    template: &str,
+    context: &Value,
+    partials: &BTreeMap<String, String>,
 ) -> Result<String, RenderError> {
-    // let re = Regex::new(r"#\{config\[:(\w+)\]\}").unwrap();
-    let re = Regex::new(r"<%=\s*config\[\s*:(\w+)\s*\]\s*%>").unwrap();
-    let my_template = re.replace_all(my_template, "{{$1}}").to_string();
+    let mut reg = Handlebars::new();
+    register_helpers(&mut reg);
+    for (name, partial) in partials {
+        reg.register_partial(name, erb::translate_output_tags(partial))?;
+    }
 
-    let reg = Handlebars::new();
+    reg.render_template(template, context)
+}
 
-    let context = ConfigBuilder::new(
-        repo_config.project.name.clone(),
-        repo_config.project.template.clone(),
-    )
-    .build()
-    .expect("error: it went wrong");
+/// Folder at the top of a template whose files are handlebars partials
+/// rather than files to generate: `_partials/license_header.tt` is included
+/// with `{{> license_header}}`.
+pub const PARTIALS_DIR: &str = "_partials";
+
+/// The partials in the `_partials` folders of `roots`, named by their path
+/// there without a `.tt` or `.hbs` suffix, e.g. `make/rust.mk` for
+/// `_partials/make/rust.mk.tt`.  Roots are given base template first, so a
+/// later template's partial replaces one of the same name.
+fn load_partials(roots: &[PathBuf]) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    let mut partials = BTreeMap::new();
+    for root in roots {
+        let partials_dir = root.join(PARTIALS_DIR);
+        let mut dirs = vec![partials_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Ok(relative) = path.strip_prefix(&partials_dir) else {
+                    continue;
+                };
+                let name = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if name.ends_with(".DS_Store") {
+                    continue;
+                }
+                let name = name
+                    .strip_suffix(".tt")
+                    .or_else(|| name.strip_suffix(".hbs"))
+                    .unwrap_or(&name)
+                    .to_string();
+                let partial = fs::read_to_string(&path).map_err(|e| {
+                    format!(
+                        "unable to read partial {}: {}",
+                        clean_path(&path).display(),
+                        e
+                    )
+                })?;
+                trace!("partial '{}' from {}", name, clean_path(&path).display());
+                partials.insert(name, partial);
+            }
+        }
+    }
+    Ok(partials)
+}
+
+/// Folder at the top of a template whose YAML and JSON files are values
+/// rather than files to generate: `_data/team.yaml` is `{{data.team}}`.
+pub const TEMPLATE_DATA_DIR: &str = "_data";
+
+/// The repo's own data files, next to `.dropkickrc`, which win over the
+/// templates'.  Under `.dropkick` rather than a plain `data` folder, which
+/// plenty of projects (Hugo and Jekyll sites, test fixtures) have for
+/// themselves.
+pub const DATA_DIR: &str = ".dropkick/data";
+
+/// The `.yaml`, `.yml` and `.json` files directly in `dirs`, by their name
+/// without the extension.  A later folder's file replaces one of the same
+/// name, and a folder that doesn't exist is skipped.
+fn load_data(dirs: &[PathBuf]) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let mut data = Map::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        for path in paths {
+            let is_data = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ["yaml", "yml", "json"].contains(&ext));
+            let name = path.file_stem().and_then(|stem| stem.to_str());
+            let (true, Some(name)) = (is_data, name) else {
+                continue;
+            };
+
+            let raw = fs::read_to_string(&path).map_err(|e| {
+                format!(
+                    "unable to read data file {}: {}",
+                    clean_path(&path).display(),
+                    e
+                )
+            })?;
+            let mut yaml: serde_yaml::Value = serde_yaml::from_str(&raw).map_err(|e| {
+                format!(
+                    "unable to parse data file {}: {}",
+                    clean_path(&path).display(),
+                    e
+                )
+            })?;
+            yaml.apply_merge()?;
+            trace!("data '{}' from {}", name, clean_path(&path).display());
+            data.insert(name.to_string(), serde_json::to_value(yaml)?);
+        }
+    }
+    Ok(data)
+}
+
+handlebars_helper!(pascal_helper: |s: str| case::pascal(s));
+handlebars_helper!(camel_helper: |s: str| case::camel(s));
+handlebars_helper!(snake_helper: |s: str| case::snake(s));
+handlebars_helper!(kebab_helper: |s: str| case::kebab(s));
+handlebars_helper!(scream_helper: |s: str| case::scream(s));
+handlebars_helper!(plural_helper: |s: str| case::plural(s));
+handlebars_helper!(singular_helper: |s: str| case::singular(s));
+handlebars_helper!(humanize_helper: |s: str| case::humanize(s));
+handlebars_helper!(year_helper: | | generation_time().year());
+handlebars_helper!(uuid_helper: | | uuid::Uuid::new_v4().to_string());
+
+/// `{{env "CI_PROJECT_URL"}}` is the environment variable's value, and
+/// `{{env "CI_PROJECT_URL" "https://example.com"}}` falls back to the default
+/// when it's unset or empty; without one that's an empty string.  A bare
+/// `{{env}}` is still the template variable called `env`.
+struct EnvHelper;
+
+impl HelperDef for EnvHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let Some(name) = h.param(0) else {
+            return Ok(ctx.data().get("env").map_or(ScopedJson::Missing, |value| {
+                ScopedJson::Context(value, vec!["env".to_string()])
+            }));
+        };
+        let name = name
+            .value()
+            .as_str()
+            .ok_or(RenderErrorReason::InvalidParamType(
+                "environment variable name",
+            ))?;
+        let value = match env::var(name) {
+            Ok(value) if !value.is_empty() => Value::String(value),
+            _ => h.param(1).map_or_else(
+                || Value::String(String::new()),
+                |default| default.value().clone(),
+            ),
+        };
+        Ok(ScopedJson::Derived(value))
+    }
+}
+
+/// `{{sh "git rev-parse --short HEAD"}}` is what the command prints, when
+/// `--allow-exec` or `allow_exec` in `config.yaml` lets it run.
+struct ShHelper;
+
+impl HelperDef for ShHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let command = h
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or(RenderErrorReason::InvalidParamType("command"))?;
+        let output = exec::run(command).map_err(RenderErrorReason::Other)?;
+        Ok(ScopedJson::Derived(Value::String(output)))
+    }
+}
+
+/// When the templates are being rendered: now, unless `SOURCE_DATE_EPOCH`
+/// pins it (in UTC) for output that's the same every time, e.g. in
+/// `dropkick test` snapshots.
+fn generation_time() -> DateTime<FixedOffset> {
+    env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse().ok())
+        .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+        .map(|time| time.fixed_offset())
+        .unwrap_or_else(|| Local::now().fixed_offset())
+}
+
+/// `{{now}}` is the date, e.g. `2024-05-01`, and `{{now "%d %B %Y"}}` the
+/// time in any strftime format.
+struct NowHelper;
+
+impl HelperDef for NowHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let format = match h.param(0) {
+            Some(param) => param
+                .value()
+                .as_str()
+                .ok_or(RenderErrorReason::InvalidParamType("strftime format"))?,
+            None => "%Y-%m-%d",
+        };
+        let mut stamp = String::new();
+        write!(stamp, "{}", generation_time().format(format))
+            .map_err(|_| RenderErrorReason::Other(format!("invalid date format '{}'", format)))?;
+        Ok(ScopedJson::Derived(Value::String(stamp)))
+    }
+}
+
+/// A Liquid filter converting a value's case, e.g. `{{ service | kebab_case }}`.
+/// The names are cargo-generate's.
+macro_rules! case_filter {
+    ($filter:ident, $parsed:ident, $name:literal, $convert:path) => {
+        #[derive(Clone, liquid_core::ParseFilter, liquid_core::FilterReflection)]
+        #[filter(name = $name, description = "Converts the case of a string.", parsed($parsed))]
+        struct $filter;
+
+        #[derive(Debug, Default, liquid_core::Display_filter)]
+        #[name = $name]
+        struct $parsed;
+
+        impl liquid_core::Filter for $parsed {
+            fn evaluate(
+                &self,
+                input: &dyn liquid_core::ValueView,
+                _runtime: &dyn liquid_core::Runtime,
+            ) -> liquid_core::Result<liquid_core::Value> {
+                Ok(liquid_core::Value::scalar($convert(&input.to_kstr())))
+            }
+        }
+    };
+}
+
+case_filter!(PascalCase, PascalCaseFilter, "pascal_case", case::pascal);
+case_filter!(
+    UpperCamelCase,
+    UpperCamelCaseFilter,
+    "upper_camel_case",
+    case::pascal
+);
+case_filter!(
+    LowerCamelCase,
+    LowerCamelCaseFilter,
+    "lower_camel_case",
+    case::camel
+);
+case_filter!(SnakeCase, SnakeCaseFilter, "snake_case", case::snake);
+case_filter!(KebabCase, KebabCaseFilter, "kebab_case", case::kebab);
+case_filter!(
+    ShoutySnakeCase,
+    ShoutySnakeCaseFilter,
+    "shouty_snake_case",
+    case::scream
+);
+case_filter!(
+    ShoutyKebabCase,
+    ShoutyKebabCaseFilter,
+    "shouty_kebab_case",
+    case::scream_kebab
+);
+case_filter!(TitleCase, TitleCaseFilter, "title_case", case::title);
+
+/// Case conversions and inflections for any value, e.g. `{{kebab
+/// service_name}}` or `{{plural resource}}`, on top of the pre-computed
+/// `*_name` fields, the date and unique ids stamped at generation, and
+/// environment variables and command output.
+fn register_helpers(reg: &mut Handlebars) {
+    reg.register_helper("pascal", Box::new(pascal_helper));
+    reg.register_helper("camel", Box::new(camel_helper));
+    reg.register_helper("snake", Box::new(snake_helper));
+    reg.register_helper("kebab", Box::new(kebab_helper));
+    reg.register_helper("scream", Box::new(scream_helper));
+    reg.register_helper("plural", Box::new(plural_helper));
+    reg.register_helper("singular", Box::new(singular_helper));
+    reg.register_helper("humanize", Box::new(humanize_helper));
+    reg.register_helper("now", Box::new(NowHelper));
+    reg.register_helper("year", Box::new(year_helper));
+    reg.register_helper("uuid", Box::new(uuid_helper));
+    reg.register_helper("env", Box::new(EnvHelper));
+    reg.register_helper("sh", Box::new(ShHelper));
+}
+
+/// Reads a JSON or YAML object of extra variables.  JSON is a subset of YAML,
+/// so a single parser covers both.
+fn load_vars_file(path: &PathBuf) -> Result<Value, Box<dyn Error>> {
+    let raw = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read vars file {}: {}", path.display(), e))?;
+
+    let mut yaml: serde_yaml::Value = serde_yaml::from_str(&raw)
+        .map_err(|e| format!("unable to parse vars file {}: {}", path.display(), e))?;
+    yaml.apply_merge()?;
+
+    let value = serde_json::to_value(yaml)?;
+    debug!("loaded vars file {}", path.display());
+    if !value.is_object() {
+        return Err(format!("vars file {} must contain a map of values", path.display()).into());
+    }
+
+    Ok(value)
+}
+
+/// Layers `overlay` onto `base`.  A shallow merge replaces top-level keys
+/// outright; a deep merge recurses into maps present on both sides.
+fn merge_values(base: &mut Value, overlay: Value, deep: bool) {
+    match (base, overlay) {
+        (Value::Object(base_map), Value::Object(overlay_map)) => {
+            for (key, value) in overlay_map {
+                match base_map.get_mut(&key) {
+                    Some(existing) if deep && existing.is_object() && value.is_object() => {
+                        merge_values(existing, value, deep)
+                    }
+                    _ => {
+                        base_map.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Returns the unique `config[:...]` keys referenced by a template, in ERB
+/// tags or Ruby interpolation.
+pub fn referenced_config_keys(content: &str) -> BTreeSet<String> {
+    let ruby = Regex::new(RUBY_CONFIG_PATTERN).unwrap();
+    erb::config_keys(content)
+        .into_iter()
+        .chain(ruby.captures_iter(content).map(|caps| caps[1].to_string()))
+        .collect()
+}
+
+/// A `{{...}}` tag in a handlebars template, as far as `lint` cares.
+#[derive(Debug, PartialEq, Eq)]
+enum Tag {
+    /// `{{#helper args}}`
+    Open(String, String),
+    /// `{{/helper}}`
+    Close(String),
+    /// `{{name}}`, `{{helper args}}` or `{{else if args}}`
+    Expression(String),
+}
+
+/// Every tag in a handlebars template with the 1-based line it starts on,
+/// leaving out comments, partials and escaped `\{{`.
+fn handlebars_tags(template: &str) -> Vec<(usize, Tag)> {
+    let re = Regex::new(r"(?s)\\?\{\{!--.*?--~?\}\}|\\?\{\{(.*?)\}\}").unwrap();
+    re.captures_iter(template)
+        .filter_map(|caps| {
+            let whole = caps.get(0)?;
+            if whole.as_str().starts_with('\\') {
+                return None;
+            }
+            let inner = caps
+                .get(1)?
+                .as_str()
+                .trim_start_matches('{')
+                .trim_end_matches('}')
+                .trim_matches('~')
+                .trim();
+            let line = template[..whole.start()].matches('\n').count() + 1;
+
+            let tag = if let Some(rest) = inner.strip_prefix('#') {
+                if rest.starts_with('>') || rest.starts_with('*') {
+                    return None;
+                }
+                let (helper, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                Tag::Open(helper.to_string(), args.trim().to_string())
+            } else if let Some(helper) = inner.strip_prefix('/') {
+                Tag::Close(helper.trim().to_string())
+            } else if inner.starts_with(['!', '>', '^']) || inner.is_empty() || inner == "else" {
+                return None;
+            } else {
+                Tag::Expression(inner.to_string())
+            };
+            Some((line, tag))
+        })
+        .collect()
+}
+
+/// Blocks that are opened and never closed, or closed without being opened,
+/// as line and message.  Clearer than the parser's error for the same thing.
+pub fn unbalanced_blocks(template: &str) -> Vec<(usize, String)> {
+    let mut problems = Vec::new();
+    let mut open: Vec<(usize, String)> = Vec::new();
+    for (line, tag) in handlebars_tags(template) {
+        match tag {
+            Tag::Open(helper, _) => open.push((line, helper)),
+            Tag::Close(helper) => match open.pop() {
+                Some((_, opened)) if opened == helper => {}
+                Some((opened_line, opened)) => problems.push((
+                    line,
+                    format!(
+                        "{{{{/{}}}}} closes the {{{{#{}}}}} from line {}",
+                        helper, opened, opened_line
+                    ),
+                )),
+                None => problems.push((
+                    line,
+                    format!("{{{{/{}}}}} closes a block that was never opened", helper),
+                )),
+            },
+            Tag::Expression(_) => {}
+        }
+    }
+    for (line, helper) in open {
+        problems.push((line, format!("{{{{#{}}}}} is never closed", helper)));
+    }
+
+    problems.sort();
+    problems
+}
+
+/// Variables a handlebars template reads from the context, with the line
+/// each is on.  Inside `#each` and `#with` names are relative to the block's
+/// value, so they're left out, as are helper names and literals.
+pub fn referenced_variables(template: &str) -> Vec<(usize, String)> {
+    let mut variables = Vec::new();
+    // Whether each open block changes what names refer to
+    let mut scopes: Vec<bool> = Vec::new();
+    for (line, tag) in handlebars_tags(template) {
+        let in_scope = !scopes.contains(&true);
+        let args = match &tag {
+            Tag::Open(helper, args) => {
+                scopes.push(matches!(helper.as_str(), "each" | "with"));
+                expression_variables(args, false)
+            }
+            Tag::Close(_) => {
+                scopes.pop();
+                continue;
+            }
+            Tag::Expression(expression) => match expression.strip_prefix("else ") {
+                Some(chained) => expression_variables(chained, true),
+                None => expression_variables(expression, expression.contains(char::is_whitespace)),
+            },
+        };
+        if in_scope {
+            variables.extend(args.into_iter().map(|name| (line, name)));
+        }
+    }
+    variables
+}
+
+/// The variables among an expression's tokens.  `helper_first` says the
+/// first token names a helper, as it does whenever there are arguments.
+fn expression_variables(expression: &str, helper_first: bool) -> Vec<String> {
+    let literals = Regex::new(r#""[^"]*"|'[^']*'"#).unwrap();
+    let expression = literals
+        .replace_all(expression, " ")
+        .replace('(', " ( ")
+        .replace(')', " ) ");
+
+    let mut variables = Vec::new();
+    let mut expect_helper = helper_first;
+    for token in expression.split_whitespace() {
+        match token {
+            "(" => {
+                expect_helper = true;
+                continue;
+            }
+            ")" => continue,
+            // Block params, e.g. `as |item|`
+            "as" => break,
+            _ => {}
+        }
+        if std::mem::take(&mut expect_helper) {
+            continue;
+        }
+
+        let value = token.split_once('=').map_or(token, |(_, value)| value);
+        let name = value.split(['.', '[', '/']).next().unwrap_or_default();
+        let literal = name.is_empty()
+            || name.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '@')
+            || matches!(name, "this" | "true" | "false" | "null" | "undefined")
+            // Helpers that take no arguments, so read like variables
+            || matches!(name, "now" | "year" | "uuid");
+        if !literal {
+            variables.push(name.to_string());
+        }
+    }
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(rules: &[(&str, &str)]) -> Result<Renderer, Box<dyn Error>> {
+        let mut config = Config::new("blog_post");
+        config.project.github_user = Some("me".to_string());
+        config.rewrite_rules = rules
+            .iter()
+            .map(|(pattern, replacement)| RewriteRule {
+                pattern: pattern.to_string(),
+                replacement: replacement.to_string(),
+            })
+            .collect();
+        let options = ContextOptions {
+            new_project: true,
+            ..Default::default()
+        };
+        Renderer::new(&config, &options)
+    }
+
+    #[test]
+    fn percent_placeholders_are_rewritten_before_rendering() {
+        let renderer = renderer(&[(r"%(\w+)%", "{{$1}}")]).unwrap();
+        assert_eq!(
+            renderer
+                .render("# %title% by %github_user%, not 100%\n")
+                .unwrap(),
+            "# Blog Post by me, not 100%\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_rules_apply_in_order() {
+        let renderer = renderer(&[(r"%(\w+)%", "@$1@"), (r"@(\w+)@", "{{$1}}")]).unwrap();
+        assert_eq!(renderer.render("%name%").unwrap(), "blog_post");
+    }
 
-    reg.render_template(&my_template, &json!(context))
+    #[test]
+    fn a_rule_that_doesnt_compile_is_an_error() {
+        let err = renderer(&[(r"%(\w+%", "{{$1}}")]).err().unwrap();
+        assert!(
+            err.to_string().contains("invalid rewrite rule pattern"),
+            "{err}"
+        );
+    }
 }
@@ -0,0 +1,621 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use ignore::gitignore::Gitignore;
+use log::warn;
+use serde::Deserialize;
+
+use crate::{
+    cargo_generate::{self, CARGO_GENERATE_FILE, GENIGNORE_FILE},
+    clean_path,
+    cookiecutter::{self, COOKIECUTTER_FILE},
+    get_templates_path,
+    hooks::HOOKS_DIR,
+    manifest::{MANIFEST_FILE, Manifest},
+    snapshot::is_fixture_file,
+    template_rendering::{PARTIALS_DIR, TEMPLATE_DATA_DIR},
+};
+
+pub fn should_show_entry(path: &Path) -> bool {
+    // Get the file name
+    let file_name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    // Hide .DS_Store files
+    if file_name.eq_ignore_ascii_case(".ds_store") {
+        return false;
+    }
+
+    if file_name.eq_ignore_ascii_case(".git") {
+        return false;
+    }
+
+    if file_name.eq_ignore_ascii_case("node_modules") {
+        return false;
+    }
+
+    // Partials are only included by other files, and data files are values
+    if (file_name == PARTIALS_DIR || file_name == TEMPLATE_DATA_DIR) && path.is_dir() {
+        return false;
+    }
+
+    if let Some(root) = template_root_of(path)
+        && is_ignored(&root, path)
+    {
+        return false;
+    }
+
+    // Always show directories
+    if path.is_dir() {
+        return true;
+    }
+
+    // For files, only show .tt files.  Broken symlinks are kept so they can
+    // be reported as failures rather than silently vanishing.
+    if path.is_file() || path.is_symlink() {
+        return is_template_file_name(file_name) || is_foreign_template_file(path);
+    }
+
+    false
+}
+
+/// Whether `path` belongs to a cookiecutter or cargo-generate template, whose
+/// files are rendered without a `.tt` suffix.
+fn is_foreign_template_file(path: &Path) -> bool {
+    cookiecutter::is_project_file(path) || cargo_generate::is_template_file(path)
+}
+
+/// Strips the `.tt` template suffix from a file name, along with any `[flag]`
+/// condition after it.  The suffix is matched case-insensitively so
+/// `Config.TT` saved by a Windows editor still counts.
+pub fn strip_template_suffix(name: &str) -> Option<&str> {
+    let name = split_flag(name).0;
+    let split = name.len().checked_sub(3)?;
+    if name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(".tt") {
+        Some(&name[..split])
+    } else {
+        None
+    }
+}
+
+pub fn is_template_file_name(name: &str) -> bool {
+    strip_template_suffix(name).is_some()
+}
+
+/// Extensions of files that are never rendered, e.g. `logo.png.tt`.
+const BINARY_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "icns", "webp", "tiff", "pdf", "woff", "woff2",
+    "ttf", "otf", "eot", "zip", "gz", "tgz", "bz2", "xz", "7z", "tar", "jar", "wasm", "exe", "dll",
+    "so", "dylib", "mp3", "mp4", "wav", "ogg", "sqlite",
+];
+
+/// Whether template file `path`, holding `content`, is copied as it is
+/// rather than rendered: it has a binary extension, is listed under `binary`
+/// in its template's `dropkick.yaml`, or its content isn't text (it has a
+/// null byte near the start or isn't UTF-8).
+pub fn is_binary(path: &Path, content: &[u8]) -> Result<bool, Box<dyn Error>> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let name = strip_template_suffix(name).unwrap_or(name);
+    if let Some((_, ext)) = name.rsplit_once('.')
+        && BINARY_EXTENSIONS
+            .iter()
+            .any(|b| b.eq_ignore_ascii_case(ext))
+    {
+        return Ok(true);
+    }
+
+    if content[..content.len().min(8000)].contains(&0) || std::str::from_utf8(content).is_err() {
+        return Ok(true);
+    }
+
+    let Some(root) = template_root_of(path) else {
+        return Ok(false);
+    };
+    let relative = path.strip_prefix(&root).unwrap_or(path);
+    Ok(Manifest::load(&root)?.lists_binary(relative))
+}
+
+/// Splits the condition off a name such as `main.rs.tt[bin]`, which is only
+/// generated when `bin` is true (or `main.rs.tt[!bin]`, when it's false).
+pub fn split_flag(name: &str) -> (&str, Option<&str>) {
+    if let Some(open) = name.strip_suffix(']').and_then(|n| n.rfind('[')) {
+        let flag = &name[open + 1..name.len() - 1];
+        let key = flag.strip_prefix('!').unwrap_or(flag);
+        if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return (&name[..open], Some(flag));
+        }
+    }
+
+    (name, None)
+}
+
+/// Keeps a walk of a template tree from looping forever through a symlink to
+/// an ancestor, and from wandering out of the template through a symlink,
+/// to a directory or a file, unless `follow_symlinks` is set.
+pub struct SymlinkGuard {
+    root: PathBuf,
+    follow_symlinks: bool,
+    /// The folders the walk is in, canonicalized, from the root down.  Only
+    /// a link back to one of these is a cycle; two links to the same folder
+    /// elsewhere just show it twice.
+    ancestors: Vec<PathBuf>,
+}
+
+impl SymlinkGuard {
+    pub fn new(root: &Path, follow_symlinks: bool) -> Self {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        Self {
+            ancestors: vec![root.clone()],
+            root,
+            follow_symlinks,
+        }
+    }
+
+    /// Whether the walk should descend into `dir`, which then counts as an
+    /// ancestor of what's below it until `leave` is called.
+    pub fn should_descend(&mut self, dir: &Path) -> bool {
+        let Ok(canonical) = dir.canonicalize() else {
+            return false;
+        };
+
+        if !self.follow_symlinks && !canonical.starts_with(&self.root) {
+            warn!(
+                "Skipping '{}' because it links outside the template, pass --follow-symlinks to include it.",
+                clean_path(dir).to_string_lossy()
+            );
+            return false;
+        }
+
+        if self.ancestors.contains(&canonical) {
+            warn!(
+                "Skipping '{}' because it links to a folder it's in (symlink cycle).",
+                clean_path(dir).to_string_lossy()
+            );
+            return false;
+        }
+
+        self.ancestors.push(canonical);
+        true
+    }
+
+    /// Goes back up out of the folder last descended into.
+    pub fn leave(&mut self) {
+        self.ancestors.pop();
+    }
+
+    /// Whether the walk should include `file`, which is only a question when
+    /// it's a symlink.
+    pub fn allows_file(&self, file: &Path) -> bool {
+        if self.follow_symlinks || !file.is_symlink() {
+            return true;
+        }
+
+        if file
+            .canonicalize()
+            .is_ok_and(|target| target.starts_with(&self.root))
+        {
+            return true;
+        }
+        warn!(
+            "Skipping '{}' because it links outside the template, pass --follow-symlinks to include it.",
+            clean_path(file).to_string_lossy()
+        );
+        false
+    }
+}
+
+/// Recursively collects every template file below `root`, applying the same
+/// visibility rules as the TUI tree.  Results are sorted for stable output.
+pub fn template_files(root: &Path, follow_symlinks: bool) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    collect_template_files(
+        root,
+        &mut SymlinkGuard::new(root, follow_symlinks),
+        &mut files,
+    )?;
+    files.retain(|file| !is_fixture_file(root, file));
+    Ok(files)
+}
+
+fn collect_template_files(
+    dir: &Path,
+    guard: &mut SymlinkGuard,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| should_show_entry(p))
+        .collect();
+
+    paths.sort();
+
+    for entry in paths {
+        if entry.is_dir() {
+            if guard.should_descend(&entry) {
+                let collected = collect_template_files(&entry, guard, files);
+                guard.leave();
+                collected?;
+            }
+        } else if guard.allows_file(&entry) {
+            files.push(entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Gitignore-style patterns at the top of a template for what it holds but
+/// never shows or generates, e.g. `*.swp` or `target/`.
+pub const IGNORE_FILE: &str = ".dropkickignore";
+
+/// Each template's `.dropkickignore`, read again when it changes.
+type IgnoreCache = BTreeMap<PathBuf, (Option<SystemTime>, Arc<Gitignore>)>;
+static IGNORES: Mutex<IgnoreCache> = Mutex::new(BTreeMap::new());
+
+/// Whether `path`, below template folder `root`, is left out by the
+/// template's `.dropkickignore`, itself or through a folder it's in.
+pub fn is_ignored(root: &Path, path: &Path) -> bool {
+    if path == root || !path.starts_with(root) {
+        return false;
+    }
+    let ignore_file = root.join(IGNORE_FILE);
+    let modified = fs::metadata(&ignore_file)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+
+    let matcher = {
+        let mut ignores = IGNORES.lock().unwrap();
+        match ignores.get(root) {
+            Some((seen, matcher)) if *seen == modified => matcher.clone(),
+            _ => {
+                let (matcher, error) = Gitignore::new(&ignore_file);
+                if let Some(e) = error
+                    && modified.is_some()
+                {
+                    warn!(
+                        "Some of {} can't be used: {}",
+                        clean_path(&ignore_file).display(),
+                        e
+                    );
+                }
+                let matcher = Arc::new(matcher);
+                ignores.insert(root.to_path_buf(), (modified, matcher.clone()));
+                matcher
+            }
+        }
+    };
+    matcher
+        .matched_path_or_any_parents(path, path.is_dir())
+        .is_ignore()
+}
+
+/// Files and folders at the top of a template that configure it rather than
+/// being generated.
+pub const TEMPLATE_SETTINGS: &[&str] = &[
+    MANIFEST_FILE,
+    COOKIECUTTER_FILE,
+    CARGO_GENERATE_FILE,
+    GENIGNORE_FILE,
+    IGNORE_FILE,
+    "template.yaml",
+    HOOKS_DIR,
+    PARTIALS_DIR,
+    TEMPLATE_DATA_DIR,
+];
+
+/// Files below template folder `root` that have no `.tt` suffix, so are
+/// never generated, leaving out the template's own settings and hooks and
+/// what its `.dropkickignore` leaves out.
+pub fn untemplated_files(
+    root: &Path,
+    follow_symlinks: bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    collect_untemplated_files(
+        root,
+        root,
+        &mut SymlinkGuard::new(root, follow_symlinks),
+        &mut files,
+    )?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_untemplated_files(
+    dir: &Path,
+    root: &Path,
+    guard: &mut SymlinkGuard,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    for path in fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let settings = dir == root && TEMPLATE_SETTINGS.contains(&name);
+        if settings || name.eq_ignore_ascii_case(".ds_store") {
+            continue;
+        }
+
+        if path.is_dir() {
+            if should_show_entry(&path) && guard.should_descend(&path) {
+                let collected = collect_untemplated_files(&path, root, guard, files);
+                guard.leave();
+                collected?;
+            }
+        } else if !is_template_file_name(name)
+            && !is_foreign_template_file(&path)
+            && !is_fixture_file(root, &path)
+            && !is_ignored(root, &path)
+            && guard.allows_file(&path)
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Names of every template folder under the templates path, sorted.
+pub fn template_names() -> Result<Vec<String>, Box<dyn Error>> {
+    let templates_path = get_templates_path();
+    let entries = fs::read_dir(&templates_path).map_err(|e| {
+        format!(
+            "unable to read templates from {}: {}",
+            clean_path(&templates_path).to_string_lossy(),
+            e
+        )
+    })?;
+
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && should_show_entry(p))
+        .filter_map(|p| p.file_name().and_then(|n| n.to_str()).map(String::from))
+        .collect();
+
+    names.sort();
+    Ok(names)
+}
+
+/// Resolves a template name to its folder under the templates path.
+pub fn template_root(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let templates_path = get_templates_path();
+    let root = templates_path.join(name);
+
+    if !root.is_dir() {
+        return Err(format!(
+            "template '{}' not found in {}",
+            name,
+            clean_path(&templates_path).to_string_lossy()
+        )
+        .into());
+    }
+
+    Ok(root)
+}
+
+/// The template folder a file under the templates path belongs to.
+pub fn template_root_of(file: &Path) -> Option<PathBuf> {
+    let templates_path = get_templates_path();
+    let template = file.strip_prefix(&templates_path).ok()?.iter().next()?;
+    Some(templates_path.join(template))
+}
+
+/// Reads the variables a template declares in its `template.yaml`, given
+/// either as a map keyed by name or as a list of names, along with those in
+/// its `dropkick.yaml`.  Templates without either file declare nothing.
+pub fn declared_variables(root: &Path) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let mut names: BTreeSet<String> = Manifest::load(root)?
+        .variables
+        .into_iter()
+        .map(|v| v.name)
+        .collect();
+
+    let path = root.join("template.yaml");
+    if !path.is_file() {
+        return Ok(names);
+    }
+
+    let raw = fs::read_to_string(&path)?;
+    let manifest: serde_yaml::Value = serde_yaml::from_str(&raw)
+        .map_err(|e| format!("unable to parse {}: {}", clean_path(&path).display(), e))?;
+
+    let legacy: BTreeSet<String> = match manifest.get("variables") {
+        Some(serde_yaml::Value::Mapping(map)) => map
+            .keys()
+            .filter_map(|k| k.as_str().map(String::from))
+            .collect(),
+        Some(serde_yaml::Value::Sequence(list)) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        _ => BTreeSet::new(),
+    };
+    names.extend(legacy);
+
+    Ok(names)
+}
+
+/// Per-file directives given in a YAML block between `---` lines at the top
+/// of a template file.
+#[derive(Debug, Default, Deserialize)]
+pub struct Frontmatter {
+    pub merge: Option<MergeDirective>,
+}
+
+/// Renders a file into a marked section of another file instead of creating
+/// it, e.g. `merge: { into: "registry.rs", between: ["// BEGIN", "// END"] }`.
+/// `into` is relative to the directory the file would otherwise land in.
+#[derive(Debug, Deserialize)]
+pub struct MergeDirective {
+    pub into: PathBuf,
+    pub between: (String, String),
+}
+
+/// Splits a template into its frontmatter and body.  Plenty of YAML templates
+/// legitimately start with a `---` document marker, so a leading block only
+/// counts as frontmatter when it holds a known directive.
+pub fn split_frontmatter(content: &str) -> Result<(Frontmatter, &str), String> {
+    let no_frontmatter = || Ok((Frontmatter::default(), content));
+
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return no_frontmatter();
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let block = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+
+            let Ok(serde_yaml::Value::Mapping(map)) = serde_yaml::from_str(block) else {
+                return no_frontmatter();
+            };
+            if !map.contains_key("merge") {
+                return no_frontmatter();
+            }
+
+            let frontmatter = serde_yaml::from_value(serde_yaml::Value::Mapping(map))
+                .map_err(|e| format!("invalid frontmatter: {}", e))?;
+            return Ok((frontmatter, body));
+        }
+        offset += line.len();
+    }
+
+    no_frontmatter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_suffix_is_matched_in_any_case() {
+        assert_eq!(strip_template_suffix("Config.tt"), Some("Config"));
+        assert_eq!(strip_template_suffix("Config.TT"), Some("Config"));
+        assert_eq!(strip_template_suffix("Config.Tt"), Some("Config"));
+        assert_eq!(strip_template_suffix("main.rs.tT[bin]"), Some("main.rs"));
+        assert_eq!(strip_template_suffix("Config.ttl"), None);
+        assert_eq!(strip_template_suffix("tt"), None);
+    }
+
+    #[test]
+    fn multi_byte_names_near_the_suffix_dont_panic() {
+        assert_eq!(strip_template_suffix("é.tt"), Some("é"));
+        assert_eq!(strip_template_suffix("日本.TT"), Some("日本"));
+        assert_eq!(strip_template_suffix(".tt"), Some(""));
+        // The last three bytes fall inside a character
+        assert_eq!(strip_template_suffix("日x"), None);
+        assert_eq!(strip_template_suffix("éab"), None);
+        // Too short for the suffix at all
+        assert_eq!(strip_template_suffix("é"), None);
+    }
+
+    #[test]
+    fn a_merge_directive_is_split_off() {
+        let content = "---\nmerge:\n  into: registry.rs\n  between: [\"// BEGIN\", \"// END\"]\n---\nmod {{name}};\n";
+        let (frontmatter, body) = split_frontmatter(content).unwrap();
+        let merge = frontmatter.merge.unwrap();
+        assert_eq!(merge.into, Path::new("registry.rs"));
+        assert_eq!(
+            merge.between,
+            ("// BEGIN".to_string(), "// END".to_string())
+        );
+        assert_eq!(body, "mod {{name}};\n");
+    }
+
+    #[test]
+    fn other_leading_yaml_documents_are_left_in_the_body() {
+        for content in [
+            "fn main() {}\n",
+            "---\nkind: Deployment\n---\nkind: Service\n",
+            "---\nnot: [closed\n",
+            "---\nmerge: {}\n",
+        ] {
+            let (frontmatter, body) = split_frontmatter(content).unwrap();
+            assert!(frontmatter.merge.is_none(), "{content:?}");
+            assert_eq!(body, content);
+        }
+    }
+
+    #[test]
+    fn an_invalid_merge_directive_is_an_error() {
+        assert!(split_frontmatter("---\nmerge: {into: x}\n---\nbody\n").is_err());
+    }
+
+    /// The files found below `root`, relative to it.
+    #[cfg(unix)]
+    fn walked(root: &Path) -> Vec<String> {
+        template_files(root, false)
+            .unwrap()
+            .iter()
+            .map(|file| {
+                file.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_link_to_a_folder_it_is_in_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("web");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs.tt"), "").unwrap();
+        std::os::unix::fs::symlink("..", root.join("src/up")).unwrap();
+        std::os::unix::fs::symlink(".", root.join("src/here")).unwrap();
+
+        assert_eq!(walked(&root), ["src/main.rs.tt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn two_links_to_the_same_folder_are_both_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("web");
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("shared/common.txt.tt"), "").unwrap();
+        fs::write(root.join("shared/logo.png"), "").unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("a/shared")).unwrap();
+        std::os::unix::fs::symlink("../shared", root.join("b/shared")).unwrap();
+
+        assert_eq!(
+            walked(&root),
+            [
+                "a/shared/common.txt.tt",
+                "b/shared/common.txt.tt",
+                "shared/common.txt.tt"
+            ]
+        );
+        let untemplated = untemplated_files(&root, false).unwrap();
+        assert_eq!(
+            untemplated,
+            [
+                root.join("a/shared/logo.png"),
+                root.join("b/shared/logo.png"),
+                root.join("shared/logo.png")
+            ]
+        );
+    }
+}
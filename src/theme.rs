@@ -0,0 +1,157 @@
+use std::env;
+
+use log::warn;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders},
+};
+use serde::Deserialize;
+use two_face::theme::{EmbeddedLazyThemeSet, EmbeddedThemeName};
+
+/// `theme` in `$XDG_CONFIG_HOME/dropkick/config.yaml`: a syntax highlighting theme's name,
+/// or that along with the TUI's colors.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    Name(String),
+    Settings(ThemeSettings),
+}
+
+/// Colors are names like `cyan` or `dark-gray`, `#rrggbb`, or a 256-color
+/// index.
+#[derive(Debug, Default, Deserialize)]
+pub struct ThemeSettings {
+    /// Syntax highlighting theme for the file view, e.g. `Dracula`
+    pub syntax: Option<String>,
+    /// Background of the highlighted row
+    pub selected: Option<String>,
+    /// Text of the highlighted row
+    pub selected_text: Option<String>,
+    pub border: Option<String>,
+    /// Text of the status bar at the bottom
+    pub status: Option<String>,
+}
+
+/// The colors the TUI draws with.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub selected: Color,
+    pub selected_text: Color,
+    pub border: Color,
+    pub status: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            selected: Color::Cyan,
+            selected_text: Color::Black,
+            border: Color::Reset,
+            status: Color::Gray,
+        }
+    }
+}
+
+impl Palette {
+    pub fn selected(&self) -> Style {
+        Style::default()
+            .fg(self.selected_text)
+            .bg(self.selected)
+            .add_modifier(Modifier::BOLD)
+    }
+
+    pub fn status(&self) -> Style {
+        Style::default().fg(self.status)
+    }
+
+    /// A bordered block in the border color.
+    pub fn block(&self) -> Block<'static> {
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(self.border))
+    }
+}
+
+impl ThemeConfig {
+    pub fn syntax(&self) -> Option<&str> {
+        match self {
+            ThemeConfig::Name(name) => Some(name),
+            ThemeConfig::Settings(settings) => settings.syntax.as_deref(),
+        }
+    }
+
+    /// The default colors with those configured swapped in.  A color that
+    /// can't be parsed is reported and the default kept.
+    pub fn palette(&self) -> Palette {
+        let mut palette = Palette::default();
+        let ThemeConfig::Settings(settings) = self else {
+            return palette;
+        };
+
+        for (name, value, color) in [
+            ("selected", &settings.selected, &mut palette.selected),
+            (
+                "selected_text",
+                &settings.selected_text,
+                &mut palette.selected_text,
+            ),
+            ("border", &settings.border, &mut palette.border),
+            ("status", &settings.status, &mut palette.status),
+        ] {
+            let Some(value) = value else {
+                continue;
+            };
+            match value.parse() {
+                Ok(parsed) => *color = parsed,
+                Err(_) => warn!(
+                    "Unknown color '{}' for theme.{}, use a name like cyan, #rrggbb or 0-255",
+                    value, name
+                ),
+            }
+        }
+        palette
+    }
+}
+
+/// The syntax highlighting theme called `name`, ignoring case.
+fn find_theme(name: &str) -> Option<EmbeddedThemeName> {
+    EmbeddedLazyThemeSet::theme_names()
+        .iter()
+        .copied()
+        .find(|theme| theme.as_name().eq_ignore_ascii_case(name))
+}
+
+/// The syntax highlighting theme to use: the one named, or else one suiting
+/// the terminal's background where it can be told, InspiredGitHub otherwise.
+pub fn syntax_theme(name: Option<&str>) -> EmbeddedThemeName {
+    let fallback = match dark_background() {
+        Some(true) => EmbeddedThemeName::Base16OceanDark,
+        Some(false) | None => EmbeddedThemeName::InspiredGithub,
+    };
+    let Some(name) = name else {
+        return fallback;
+    };
+
+    find_theme(name).unwrap_or_else(|| {
+        let known: Vec<&str> = EmbeddedLazyThemeSet::theme_names()
+            .iter()
+            .map(|theme| theme.as_name())
+            .collect();
+        warn!(
+            "Unknown theme '{}', using {}.  Themes: {}",
+            name,
+            fallback.as_name(),
+            known.join(", ")
+        );
+        fallback
+    })
+}
+
+/// Whether the terminal has a dark background, going by `COLORFGBG` (e.g.
+/// `15;0`, light text on black), which many terminals set.
+fn dark_background() -> Option<bool> {
+    let colors = env::var("COLORFGBG").ok()?;
+    let background: u8 = colors.rsplit(';').next()?.parse().ok()?;
+    // 0-6 and 8 are the dark ANSI colors, 7 and 9-15 the light ones
+    Some(matches!(background, 0..=6 | 8))
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+/// Where an import's files are written before they're moved into place,
+/// relative to the output directory.
+pub const STAGING_DIR: &str = ".dropkick/staging";
+
+/// The writes of one import, held in a staging directory until every file
+/// has rendered.  Nothing in the output directory changes before `commit`,
+/// and a transaction that's dropped without one, e.g. while a panic unwinds,
+/// just removes its staging directory.
+#[derive(Debug)]
+pub struct Transaction {
+    staging: PathBuf,
+    /// Destination of each staged file, and where it's staged
+    staged: BTreeMap<PathBuf, PathBuf>,
+    /// Existing files to move to `<name>.orig` before the staged files land
+    backups: Vec<PathBuf>,
+}
+
+/// A change `commit` made, so it can be taken back if a later one fails.
+enum Step {
+    /// A file was put where there was none
+    Created(PathBuf),
+    /// A file was replaced; the original is kept at the second path
+    Replaced(PathBuf, PathBuf),
+    /// A file was moved to its backup path
+    BackedUp(PathBuf, OsString),
+}
+
+impl Transaction {
+    pub fn new(output_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let staging = output_dir.join(STAGING_DIR);
+        // Left over from a run that was killed outright
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)
+            .map_err(|e| format!("unable to create '{}' ({})", staging.display(), e))?;
+
+        Ok(Self {
+            staging,
+            staged: BTreeMap::new(),
+            backups: Vec::new(),
+        })
+    }
+
+    /// What `dest` holds as far as this transaction is concerned: what was
+    /// staged for it, or else what's on disk.
+    pub fn read(&self, dest: &Path) -> io::Result<String> {
+        fs::read_to_string(self.staged.get(dest).map_or(dest, |p| p.as_path()))
+    }
+
+    pub fn write(&mut self, dest: &Path, content: impl AsRef<[u8]>) -> Result<(), String> {
+        let next = self.staging.join(self.staged.len().to_string());
+        let staged = self.staged.entry(dest.to_path_buf()).or_insert(next);
+        fs::write(&*staged, content)
+            .map_err(|e| format!("unable to stage '{}' ({})", dest.to_string_lossy(), e))
+    }
+
+    /// Gives the file staged for `dest` permissions `mode`, which it keeps
+    /// when it's moved into place.
+    pub fn set_mode(&self, dest: &Path, mode: u32) -> Result<(), String> {
+        match self.staged.get(dest) {
+            Some(staged) => set_mode(staged, mode)
+                .map_err(|e| format!("unable to set the mode of '{}' ({})", dest.display(), e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the existing `dest` to `<name>.orig` when the transaction commits.
+    pub fn backup(&mut self, dest: &Path) {
+        self.backups.push(dest.to_path_buf());
+    }
+
+    /// Moves every staged file into place.  If one can't be, the ones already
+    /// moved are taken back out and the files they replaced restored.
+    pub fn commit(mut self) -> Result<(), Box<dyn Error>> {
+        let mut done = Vec::new();
+        if let Err(e) = self.apply(&mut done) {
+            for step in done.into_iter().rev() {
+                let undone = match &step {
+                    Step::Created(dest) => fs::remove_file(dest),
+                    Step::Replaced(dest, original) => fs::rename(original, dest),
+                    Step::BackedUp(dest, backup) => fs::rename(backup, dest),
+                };
+                if let Err(e) = undone {
+                    warn!("Unable to roll back {}: {}", step.path().display(), e);
+                }
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, done: &mut Vec<Step>) -> Result<(), Box<dyn Error>> {
+        for dest in std::mem::take(&mut self.backups) {
+            let mut backup = dest.clone().into_os_string();
+            backup.push(".orig");
+            fs::rename(&dest, &backup)
+                .map_err(|e| format!("unable to back up '{}' ({})", dest.display(), e))?;
+            done.push(Step::BackedUp(dest, backup));
+        }
+
+        for (n, (dest, staged)) in std::mem::take(&mut self.staged).into_iter().enumerate() {
+            debug!("{} -> {}", staged.display(), dest.display());
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("unable to create parent directories ({})", e))?;
+            }
+
+            let step = if dest.exists() {
+                let original = self.staging.join(format!("{}.original", n));
+                fs::rename(&dest, &original)
+                    .map_err(|e| format!("unable to replace '{}' ({})", dest.display(), e))?;
+                Step::Replaced(dest.clone(), original)
+            } else {
+                Step::Created(dest.clone())
+            };
+            let moved = fs::rename(&staged, &dest);
+            if moved.is_ok() || matches!(step, Step::Replaced(..)) {
+                done.push(step);
+            }
+            moved.map_err(|e| format!("unable to write '{}' ({})", dest.display(), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets Unix permissions on `path`.  Elsewhere there are none to set.
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+        Ok(())
+    }
+}
+
+impl Step {
+    fn path(&self) -> &Path {
+        match self {
+            Step::Created(dest) | Step::Replaced(dest, _) | Step::BackedUp(dest, _) => dest,
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.staging) {
+            debug!("unable to remove {}: {}", self.staging.display(), e);
+        }
+        // Only goes if the import left nothing else in it
+        if let Some(parent) = self.staging.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An output directory holding `b.txt`, `kept.txt` and a file `z`, which
+    /// nothing can be written under.
+    fn output_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.txt"), "old b").unwrap();
+        fs::write(dir.path().join("kept.txt"), "kept").unwrap();
+        fs::write(dir.path().join("z"), "in the way").unwrap();
+        dir
+    }
+
+    #[test]
+    fn commit_moves_every_file_into_place() {
+        let dir = output_dir();
+        let out = dir.path();
+        let mut transaction = Transaction::new(out).unwrap();
+        transaction.backup(&out.join("kept.txt"));
+        transaction.write(&out.join("a.txt"), "new a").unwrap();
+        transaction.write(&out.join("b.txt"), "new b").unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(out.join("a.txt")).unwrap(), "new a");
+        assert_eq!(fs::read_to_string(out.join("b.txt")).unwrap(), "new b");
+        assert!(!out.join("kept.txt").exists());
+        assert_eq!(
+            fs::read_to_string(out.join("kept.txt.orig")).unwrap(),
+            "kept"
+        );
+        assert!(!out.join(".dropkick").exists());
+    }
+
+    #[test]
+    fn a_failed_commit_undoes_every_step() {
+        let dir = output_dir();
+        let out = dir.path();
+        let mut transaction = Transaction::new(out).unwrap();
+        transaction.backup(&out.join("kept.txt"));
+        transaction.write(&out.join("a.txt"), "new a").unwrap();
+        transaction.write(&out.join("b.txt"), "new b").unwrap();
+        // Moved last, after the others are in place
+        transaction.write(&out.join("z/c.txt"), "new c").unwrap();
+        let err = transaction.commit().unwrap_err();
+        assert!(err.to_string().contains("parent directories"), "{err}");
+
+        // Created
+        assert!(!out.join("a.txt").exists());
+        // Replaced
+        assert_eq!(fs::read_to_string(out.join("b.txt")).unwrap(), "old b");
+        // BackedUp
+        assert_eq!(fs::read_to_string(out.join("kept.txt")).unwrap(), "kept");
+        assert!(!out.join("kept.txt.orig").exists());
+        assert_eq!(fs::read_to_string(out.join("z")).unwrap(), "in the way");
+        assert!(!out.join(".dropkick").exists());
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::{clean_path, config::CONFIG_FILE, get_home};
+
+/// Where dropkick kept everything before it followed the XDG base directory
+/// spec, and still does on Windows unless `XDG_CONFIG_HOME` and
+/// `XDG_DATA_HOME` are set.
+pub fn legacy_dir() -> PathBuf {
+    get_home().join(".dropkick")
+}
+
+/// `$var/dropkick`, or under the spec's default (`~/.config` or
+/// `~/.local/share`) when `var` isn't set to an absolute path.  Windows has
+/// no default, so there it's only used when the variable is set.
+fn xdg_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|base| base.is_absolute());
+    let base = match base {
+        Some(base) => base,
+        None if cfg!(windows) => return None,
+        None => get_home().join(default),
+    };
+    Some(base.join("dropkick"))
+}
+
+fn xdg_config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+fn xdg_data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Where `config.yaml` lives: `$XDG_CONFIG_HOME/dropkick`, unless it's still
+/// in `~/.dropkick` because it couldn't be moved.
+pub fn config_dir() -> PathBuf {
+    match xdg_config_dir() {
+        Some(dir) if dir.exists() || !legacy_dir().join(CONFIG_FILE).exists() => dir,
+        _ => legacy_dir(),
+    }
+}
+
+/// Where templates, the template index and logs live:
+/// `$XDG_DATA_HOME/dropkick`, unless they're still in `~/.dropkick` because
+/// they couldn't be moved.
+pub fn data_dir() -> PathBuf {
+    match xdg_data_dir() {
+        Some(dir) if dir.exists() || !legacy_dir().exists() => dir,
+        _ => legacy_dir(),
+    }
+}
+
+/// Moves `~/.dropkick` into the XDG folders, the first time dropkick runs
+/// without a data folder there: `config.yaml` goes to the config folder and
+/// everything else becomes the data folder.  What can't be moved (e.g.
+/// across file systems) is left where it is, with a warning, and still used
+/// from there.  A symlinked `~/.dropkick`, such as one pointing at
+/// `~/.bundlegem`, is left alone.
+pub fn migrate() {
+    let (Some(config), Some(data)) = (xdg_config_dir(), xdg_data_dir()) else {
+        return;
+    };
+    if data.exists() {
+        return;
+    }
+    let legacy = legacy_dir();
+    match fs::symlink_metadata(&legacy) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => {
+            debug!(
+                "{} is a symlink, not moving it to the XDG folders",
+                legacy.display()
+            );
+            return;
+        }
+        Err(_) => return,
+    }
+
+    let legacy_config = legacy.join(CONFIG_FILE);
+    for (from, to) in [
+        (&legacy_config, &config.join(CONFIG_FILE)),
+        (&legacy, &data),
+    ] {
+        if !from.exists() {
+            continue;
+        }
+        if let Err(e) = move_to(from, to) {
+            warn!(
+                "Unable to move {} to {}, leaving it there: {}",
+                clean_path(from).display(),
+                clean_path(to).display(),
+                e
+            );
+            return;
+        }
+    }
+    info!(
+        "Moved {} to {} and {}",
+        clean_path(&legacy).display(),
+        clean_path(&config).display(),
+        clean_path(&data).display()
+    );
+}
+
+/// Renames `from` to `to`, refusing to replace something already there.
+fn move_to(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "something is already there",
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+}